@@ -0,0 +1,376 @@
+use anyhow::{bail, Result};
+use ndarray::{Array1, ArrayView1};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+use super::{utils::group_indices, ReferenceSets};
+use crate::norm::{median, quantile};
+
+/// Number of points at which the fold change densities are compared
+const GRID_SIZE: usize = 100;
+
+/// Log2 Bayes factors of genes being essential with their empirical p-values and
+/// precision-recall FDR
+#[derive(Debug)]
+pub struct BayesFactors {
+    genes: Vec<String>,
+    bayes_factors: Array1<f64>,
+    pvalues: Array1<f64>,
+    fdr: Array1<f64>,
+}
+impl BayesFactors {
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    pub fn bayes_factors(&self) -> &Array1<f64> {
+        &self.bayes_factors
+    }
+
+    /// The fraction of non-essential training genes with an equal or larger Bayes factor
+    pub fn pvalues(&self) -> &Array1<f64> {
+        &self.pvalues
+    }
+
+    pub fn fdr(&self) -> &Array1<f64> {
+        &self.fdr
+    }
+}
+
+/// Linear model of the log2 Bayes factor of an sgRNA given its log2 fold change
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LogRatioModel {
+    slope: f64,
+    intercept: f64,
+}
+impl LogRatioModel {
+    /// Fits the log2 ratio of the essential and non-essential fold change densities
+    ///
+    /// The densities are estimated with gaussian kernels and compared between the medians
+    /// of the two training distributions, where a line is fit to the log ratio so that the
+    /// Bayes factors remain monotonic in the tails. Returns `None` if the essential sgRNAs
+    /// are not depleted relative to the non-essential sgRNAs.
+    fn fit(essential: &[f64], nonessential: &[f64]) -> Option<Self> {
+        let undefined = |values: &[f64]| values.is_empty() || values.iter().any(|x| x.is_nan());
+        if undefined(essential) || undefined(nonessential) {
+            return None;
+        }
+        let lower = median(&ArrayView1::from(essential));
+        let upper = median(&ArrayView1::from(nonessential));
+        if lower >= upper {
+            return None;
+        }
+        let (bw_essential, bw_nonessential) = (bandwidth(essential), bandwidth(nonessential));
+        let step = (upper - lower) / (GRID_SIZE - 1) as f64;
+        let (x, y): (Vec<f64>, Vec<f64>) = (0..GRID_SIZE)
+            .map(|idx| lower + step * idx as f64)
+            .map(|x| {
+                let ratio = gaussian_kde(essential, bw_essential, x)
+                    / gaussian_kde(nonessential, bw_nonessential, x);
+                (x, ratio.log2())
+            })
+            .filter(|(_, y)| y.is_finite())
+            .unzip();
+        if x.len() < 2 {
+            return None;
+        }
+
+        let n = x.len() as f64;
+        let x_mean = x.iter().sum::<f64>() / n;
+        let y_mean = y.iter().sum::<f64>() / n;
+        let (sxy, sxx) = x.iter().zip(y.iter()).fold((0., 0.), |acc, (a, b)| {
+            (
+                acc.0 + (a - x_mean) * (b - y_mean),
+                acc.1 + (a - x_mean).powi(2),
+            )
+        });
+        let slope = sxy / sxx;
+        Some(Self {
+            slope,
+            intercept: y_mean - slope * x_mean,
+        })
+    }
+
+    fn predict(&self, logfc: f64) -> f64 {
+        self.slope * logfc + self.intercept
+    }
+}
+
+/// Silverman's rule of thumb bandwidth of a gaussian kernel density estimate
+fn bandwidth(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let sd = (values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.)).sqrt();
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let iqr = (quantile(&sorted, 0.75) - quantile(&sorted, 0.25)) / 1.34;
+    let spread = if iqr > 0. { sd.min(iqr) } else { sd };
+    0.9 * spread * n.powf(-0.2)
+}
+
+/// Evaluates a gaussian kernel density estimate at a single point
+fn gaussian_kde(values: &[f64], bandwidth: f64, x: f64) -> f64 {
+    let norm = values.len() as f64 * bandwidth * (2. * std::f64::consts::PI).sqrt();
+    values
+        .iter()
+        .map(|v| (-0.5 * ((x - v) / bandwidth).powi(2)).exp())
+        .sum::<f64>()
+        / norm
+}
+
+/// Calculates the empirical p-value of every gene against the non-essential training genes
+///
+/// A pseudocount is added so that no p-value is zero.
+fn empirical_pvalues(bayes_factors: &[f64], labels: &[Option<bool>]) -> Vec<f64> {
+    let mut null = bayes_factors
+        .iter()
+        .zip(labels.iter())
+        .filter(|(_, label)| **label == Some(false))
+        .map(|(bf, _)| *bf)
+        .collect::<Vec<_>>();
+    null.sort_by(|a, b| a.total_cmp(b));
+    let n_null = null.len() as f64;
+    bayes_factors
+        .iter()
+        .map(|bf| {
+            let n_below = null.partition_point(|x| x < bf) as f64;
+            (n_null - n_below + 1.) / (n_null + 1.)
+        })
+        .collect()
+}
+
+/// Calculates the FDR of every gene from the precision of the training genes ranked at or
+/// above it
+///
+/// The FDR is the fraction of non-essential training genes among the training genes with an
+/// equal or larger Bayes factor and is made monotonic in the Bayes factor.
+fn precision_recall_fdr(bayes_factors: &[f64], labels: &[Option<bool>]) -> Vec<f64> {
+    let mut order = (0..bayes_factors.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| bayes_factors[*b].total_cmp(&bayes_factors[*a]));
+
+    let mut ranked = Vec::with_capacity(order.len());
+    let (mut n_true, mut n_false) = (0., 0.);
+    for idx in &order {
+        match labels[*idx] {
+            Some(true) => n_true += 1.,
+            Some(false) => n_false += 1.,
+            None => {}
+        }
+        let total: f64 = n_true + n_false;
+        ranked.push(if total > 0. { n_false / total } else { 0. });
+    }
+    for idx in (0..ranked.len().saturating_sub(1)).rev() {
+        ranked[idx] = ranked[idx].min(ranked[idx + 1]);
+    }
+
+    let mut fdr = vec![0.; bayes_factors.len()];
+    for (rank, idx) in order.iter().enumerate() {
+        fdr[*idx] = ranked[rank];
+    }
+    fdr
+}
+
+/// Calculates the log2 Bayes factor of every gene being essential (BAGEL)
+///
+/// The fold change densities of the sgRNAs of the essential and non-essential training
+/// genes are used to score every sgRNA and the scores are summed per gene. The training
+/// genes are bootstrapped and every gene is scored by the models it was held out of, so
+/// the training genes are not scored by a model fit on themselves.
+pub fn bayes_factors(
+    logfc: &Array1<f64>,
+    gene_names: &[String],
+    sets: &ReferenceSets,
+    n_bootstraps: usize,
+    seed: u64,
+) -> Result<BayesFactors> {
+    let (genes, gene_sgrnas) = group_indices(gene_names);
+
+    let labels = genes
+        .iter()
+        .map(|gene| {
+            if sets.essential().contains(gene) {
+                Some(true)
+            } else if sets.nonessential().contains(gene) {
+                Some(false)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let training = |label: bool| {
+        (0..genes.len())
+            .filter(|idx| labels[*idx] == Some(label))
+            .collect::<Vec<_>>()
+    };
+    let (essential, nonessential) = (training(true), training(false));
+    if essential.len() < 2 || nonessential.len() < 2 {
+        bail!("At least two essential and two non-essential training genes must be present in the sgRNA results")
+    }
+
+    let fold_changes = |training: &[usize]| {
+        training
+            .iter()
+            .flat_map(|idx| gene_sgrnas[*idx].iter().map(|s| logfc[*s]))
+            .collect::<Vec<_>>()
+    };
+    let score = |model: &LogRatioModel, idx: usize| {
+        gene_sgrnas[idx]
+            .iter()
+            .map(|s| model.predict(logfc[*s]))
+            .sum::<f64>()
+    };
+
+    let Some(full_model) =
+        LogRatioModel::fit(&fold_changes(&essential), &fold_changes(&nonessential))
+    else {
+        bail!("The essential training genes are not depleted relative to the non-essential training genes")
+    };
+
+    // out-of-bag sums and counts of the bootstrapped Bayes factors
+    let (sums, counts) = (0..n_bootstraps)
+        .into_par_iter()
+        .filter_map(|iteration| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed.wrapping_add(iteration as u64));
+            let mut in_bag = vec![false; genes.len()];
+            let mut sample = |training: &[usize]| {
+                (0..training.len())
+                    .map(|_| {
+                        let idx = training[rng.gen_range(0..training.len())];
+                        in_bag[idx] = true;
+                        idx
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let sampled_essential = sample(&essential);
+            let sampled_nonessential = sample(&nonessential);
+            let model = LogRatioModel::fit(
+                &fold_changes(&sampled_essential),
+                &fold_changes(&sampled_nonessential),
+            )?;
+            let scores = (0..genes.len())
+                .map(|idx| (!in_bag[idx]).then(|| score(&model, idx)))
+                .collect::<Vec<_>>();
+            Some(scores)
+        })
+        .fold(
+            || (vec![0.; genes.len()], vec![0usize; genes.len()]),
+            |(mut sums, mut counts), scores| {
+                for (idx, value) in scores.iter().enumerate() {
+                    if let Some(value) = value {
+                        sums[idx] += value;
+                        counts[idx] += 1;
+                    }
+                }
+                (sums, counts)
+            },
+        )
+        .reduce(
+            || (vec![0.; genes.len()], vec![0usize; genes.len()]),
+            |(mut sums, mut counts), (other_sums, other_counts)| {
+                for idx in 0..sums.len() {
+                    sums[idx] += other_sums[idx];
+                    counts[idx] += other_counts[idx];
+                }
+                (sums, counts)
+            },
+        );
+
+    // genes never held out are scored by the model fit on all training genes
+    let bayes_factors = (0..genes.len())
+        .map(|idx| {
+            if counts[idx] > 0 {
+                sums[idx] / counts[idx] as f64
+            } else {
+                score(&full_model, idx)
+            }
+        })
+        .collect::<Vec<_>>();
+    let pvalues = empirical_pvalues(&bayes_factors, &labels);
+    let fdr = precision_recall_fdr(&bayes_factors, &labels);
+
+    Ok(BayesFactors {
+        genes,
+        bayes_factors: Array1::from(bayes_factors),
+        pvalues: Array1::from(pvalues),
+        fdr: Array1::from(fdr),
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::Array1;
+
+    #[test]
+    fn test_log_ratio_model() {
+        let essential = [-3.2, -3.0, -2.8, -2.5, -3.5, -2.9];
+        let nonessential = [0.1, -0.2, 0.0, 0.3, -0.1, 0.2];
+        let model = LogRatioModel::fit(&essential, &nonessential).unwrap();
+        assert!(model.slope < 0.);
+        assert!(model.predict(-3.) > 0.);
+        assert!(model.predict(0.) < 0.);
+
+        // essential genes must be depleted
+        assert!(LogRatioModel::fit(&nonessential, &essential).is_none());
+    }
+
+    #[test]
+    fn test_precision_recall_fdr() {
+        let bayes_factors = [10., 8., 6., 4., 2.];
+        let labels = [Some(true), None, Some(false), Some(true), Some(false)];
+        let fdr = precision_recall_fdr(&bayes_factors, &labels);
+        assert_eq!(fdr[0], 0.);
+        assert_eq!(fdr[1], 0.);
+        assert!((fdr[2] - 1. / 3.).abs() < 1e-12);
+        assert!((fdr[3] - 1. / 3.).abs() < 1e-12);
+        assert_eq!(fdr[4], 0.5);
+    }
+
+    #[test]
+    fn test_empirical_pvalues() {
+        let bayes_factors = [10., 5., 3., 1.];
+        let labels = [None, Some(false), None, Some(false)];
+        let pvalues = empirical_pvalues(&bayes_factors, &labels);
+        assert_eq!(pvalues, vec![1. / 3., 2. / 3., 2. / 3., 1.]);
+    }
+
+    #[test]
+    fn test_bayes_factors() -> Result<()> {
+        let to_strings = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let mut gene_names = Vec::new();
+        let mut logfc = Vec::new();
+        for (gene, center) in [
+            ("e1", -3.),
+            ("e2", -2.8),
+            ("e3", -3.1),
+            ("n1", 0.1),
+            ("n2", -0.1),
+            ("n3", 0.),
+            ("hit", -2.9),
+            ("miss", 0.05),
+        ] {
+            for offset in [-0.2, 0., 0.2] {
+                gene_names.push(gene.to_string());
+                logfc.push(center + offset);
+            }
+        }
+        let sets = ReferenceSets::new(
+            to_strings(&["e1", "e2", "e3"]),
+            to_strings(&["n1", "n2", "n3"]),
+        )?;
+        let results = bayes_factors(&Array1::from(logfc), &gene_names, &sets, 20, 0)?;
+        assert_eq!(results.genes().len(), 8);
+        let bf = |gene: &str| {
+            let idx = results.genes().iter().position(|x| x == gene).unwrap();
+            results.bayes_factors()[idx]
+        };
+        assert!(bf("hit") > 0.);
+        assert!(bf("miss") < 0.);
+        assert!(bf("hit") > bf("miss"));
+
+        let missing = ReferenceSets::new(to_strings(&["x", "y"]), to_strings(&["n1", "n2"]))?;
+        assert!(bayes_factors(&Array1::zeros(24), &gene_names, &missing, 20, 0).is_err());
+        Ok(())
+    }
+}
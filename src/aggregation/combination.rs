@@ -0,0 +1,288 @@
+use adjustp::{adjust, Procedure};
+use anyhow::{bail, Result};
+use ndarray::Array1;
+use statrs::distribution::{ChiSquared, ContinuousCDF, Normal};
+use std::f64::consts::PI;
+
+use super::utils::group_indices;
+
+/// Method used to combine the sgRNA p-values of a gene
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combination {
+    /// Fisher's combined probability test
+    Fisher,
+    /// Weighted Stouffer's Z-method
+    Stouffer,
+    /// Cauchy combination test (ACAT)
+    Acat,
+}
+
+/// Combined gene p-values of one side of a screen
+#[derive(Debug)]
+pub struct CombinedPvalues {
+    genes: Vec<String>,
+    scores: Array1<f64>,
+    pvalues: Array1<f64>,
+    fdr: Array1<f64>,
+}
+impl CombinedPvalues {
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    /// The combination statistic of each gene (larger is more significant)
+    pub fn scores(&self) -> &Array1<f64> {
+        &self.scores
+    }
+
+    pub fn pvalues(&self) -> &Array1<f64> {
+        &self.pvalues
+    }
+
+    pub fn fdr(&self) -> &Array1<f64> {
+        &self.fdr
+    }
+}
+
+/// Bounds the p-values of a gene away from zero and one so the quantile transforms stay finite
+///
+/// p-values of one (e.g. failed fits) are replaced by `1 - 1/n` for a gene of `n` sgRNAs, as
+/// recommended for ACAT, and by one half for a single sgRNA. Bounding them next to one instead
+/// would give them extreme negative quantiles that dominate the combination.
+fn bound_pvalues(pvalues: &[f64]) -> Vec<f64> {
+    let unit = 1. - 1. / pvalues.len().max(2) as f64;
+    pvalues
+        .iter()
+        .map(|p| {
+            if *p >= 1. {
+                unit
+            } else {
+                p.clamp(f64::MIN_POSITIVE, 1. - f64::EPSILON)
+            }
+        })
+        .collect()
+}
+
+/// Fisher's method: `-2 * sum(ln p)` against a chi-squared distribution with `2k` degrees of
+/// freedom
+fn fisher(pvalues: &[f64]) -> (f64, f64) {
+    let statistic = -2.
+        * pvalues
+            .iter()
+            .map(|p| p.clamp(f64::MIN_POSITIVE, 1.).ln())
+            .sum::<f64>();
+    let dist = ChiSquared::new(2. * pvalues.len() as f64).unwrap();
+    (statistic, dist.sf(statistic))
+}
+
+/// Stouffer's method: the weighted sum of the normal quantiles of the p-values
+///
+/// Falls back to equal weights if all weights are zero.
+fn stouffer(pvalues: &[f64], weights: &[f64]) -> (f64, f64) {
+    let normal = Normal::standard();
+    let norm = weights.iter().map(|w| w * w).sum::<f64>().sqrt();
+    let (weights, norm) = if norm > 0. {
+        (weights.to_vec(), norm)
+    } else {
+        (vec![1.; pvalues.len()], (pvalues.len() as f64).sqrt())
+    };
+    let statistic = bound_pvalues(pvalues)
+        .iter()
+        .zip(weights.iter())
+        .map(|(p, w)| w * -normal.inverse_cdf(*p))
+        .sum::<f64>()
+        / norm;
+    (statistic, normal.sf(statistic))
+}
+
+/// Cauchy combination test: the mean of the Cauchy quantiles of the p-values
+///
+/// Uses the asymptotic forms for very small p-values and very large statistics.
+fn acat(pvalues: &[f64]) -> (f64, f64) {
+    let statistic = bound_pvalues(pvalues)
+        .iter()
+        .map(|p| {
+            if *p < 1e-15 {
+                1. / (p * PI)
+            } else {
+                ((0.5 - p) * PI).tan()
+            }
+        })
+        .sum::<f64>()
+        / pvalues.len() as f64;
+    let pvalue = if statistic > 1e15 {
+        1. / (statistic * PI)
+    } else {
+        0.5 - statistic.atan() / PI
+    };
+    (statistic, pvalue)
+}
+
+/// Combines the sgRNA p-values of every gene and corrects the combined p-values
+///
+/// The weights are only used by Stouffer's method. Genes whose sgRNA p-values are all one are
+/// given a combined p-value of one. Combined p-values are bounded below by the smallest
+/// positive float so they stay finite on a log scale.
+pub fn combine_pvalues(
+    pvalues: &Array1<f64>,
+    weights: &Array1<f64>,
+    gene_names: &[String],
+    method: Combination,
+    correction: Procedure,
+) -> Result<CombinedPvalues> {
+    if pvalues.len() != gene_names.len() || weights.len() != gene_names.len() {
+        bail!("The p-values, weights, and gene names must describe the same sgRNAs")
+    }
+
+    let (genes, gene_sgrnas) = group_indices(gene_names);
+    let (scores, pvalues): (Vec<f64>, Vec<f64>) = gene_sgrnas
+        .iter()
+        .map(|sgrnas| {
+            let gene_pvalues = sgrnas.iter().map(|idx| pvalues[*idx]).collect::<Vec<_>>();
+            let (score, pvalue) = match method {
+                Combination::Fisher => fisher(&gene_pvalues),
+                Combination::Stouffer => {
+                    let gene_weights = sgrnas.iter().map(|idx| weights[*idx]).collect::<Vec<_>>();
+                    stouffer(&gene_pvalues, &gene_weights)
+                }
+                Combination::Acat => acat(&gene_pvalues),
+            };
+            if gene_pvalues.iter().all(|p| *p >= 1.) {
+                (score, 1.)
+            } else {
+                (score, pvalue.max(f64::MIN_POSITIVE))
+            }
+        })
+        .unzip();
+    let fdr = Array1::from(adjust(&pvalues, correction));
+
+    Ok(CombinedPvalues {
+        genes,
+        scores: Array1::from(scores),
+        pvalues: Array1::from(pvalues),
+        fdr,
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_fisher() {
+        // with two degrees of freedom the chi-squared survival is exp(-x / 2)
+        let (statistic, pvalue) = fisher(&[0.05]);
+        assert!((statistic + 2. * 0.05f64.ln()).abs() < 1e-12);
+        assert!((pvalue - 0.05).abs() < 1e-12);
+
+        let (_, combined) = fisher(&[0.01, 0.02, 0.03]);
+        assert!(combined < 0.01);
+    }
+
+    #[test]
+    fn test_stouffer() {
+        let (statistic, pvalue) = stouffer(&[0.5, 0.5], &[1., 1.]);
+        assert!(statistic.abs() < 1e-12);
+        assert!((pvalue - 0.5).abs() < 1e-12);
+
+        // a single p-value is returned unchanged irrespective of its weight
+        let (_, pvalue) = stouffer(&[0.01], &[3.]);
+        assert!((pvalue - 0.01).abs() < 1e-9);
+
+        // up-weighting the significant sgRNA lowers the combined p-value
+        let (_, equal) = stouffer(&[0.001, 0.6], &[1., 1.]);
+        let (_, weighted) = stouffer(&[0.001, 0.6], &[3., 1.]);
+        assert!(weighted < equal);
+
+        let (_, zero) = stouffer(&[0.001, 0.6], &[0., 0.]);
+        assert_eq!(zero, equal);
+    }
+
+    #[test]
+    fn test_acat() {
+        // identical p-values combine to the same p-value
+        let (_, pvalue) = acat(&[0.2, 0.2, 0.2]);
+        assert!((pvalue - 0.2).abs() < 1e-12);
+
+        // a single strong sgRNA dominates the combination
+        let (_, pvalue) = acat(&[1e-20, 0.9, 0.9]);
+        assert!(pvalue < 1e-19);
+        assert!(pvalue.is_finite() && pvalue > 0.);
+    }
+
+    #[test]
+    fn test_unit_pvalues() {
+        assert_eq!(
+            bound_pvalues(&[0.2, 1.0, 1.0, 1.0]),
+            vec![0.2, 0.75, 0.75, 0.75]
+        );
+        assert_eq!(bound_pvalues(&[1.0]), vec![0.5]);
+
+        // an sgRNA with a p-value of one does not mask a strong sgRNA
+        let (statistic, pvalue) = stouffer(&[0.01, 1.0], &[1., 1.]);
+        assert!(statistic.is_finite());
+        assert!(pvalue < 0.06);
+        let (statistic, pvalue) = acat(&[0.01, 1.0]);
+        assert!(statistic.is_finite() && statistic > 0.);
+        assert!(pvalue < 0.05);
+
+        let gene_names = ["a", "a", "b", "b"].map(|x| x.to_string()).to_vec();
+        let pvalues = array![0.01, 1.0, 1.0, 1.0];
+        for method in [
+            Combination::Fisher,
+            Combination::Stouffer,
+            Combination::Acat,
+        ] {
+            let results = combine_pvalues(
+                &pvalues,
+                &Array1::ones(4),
+                &gene_names,
+                method,
+                Procedure::BenjaminiHochberg,
+            )
+            .unwrap();
+            assert!(results.scores().iter().all(|x| x.is_finite()));
+            assert_eq!(results.pvalues()[1], 1.);
+        }
+    }
+
+    #[test]
+    fn test_combine_pvalues() -> Result<()> {
+        let gene_names = ["a", "a", "b", "b", "c"].map(|x| x.to_string()).to_vec();
+        let pvalues = array![0.001, 0.002, 0.5, 0.7, 0.];
+        let weights = Array1::ones(5);
+        for method in [
+            Combination::Fisher,
+            Combination::Stouffer,
+            Combination::Acat,
+        ] {
+            let results = combine_pvalues(
+                &pvalues,
+                &weights,
+                &gene_names,
+                method,
+                Procedure::BenjaminiHochberg,
+            )?;
+            assert_eq!(results.genes(), &["a", "b", "c"]);
+            assert!(results.pvalues()[0] < results.pvalues()[1]);
+            assert!(results.scores()[0] > results.scores()[1]);
+            assert!(results.pvalues().iter().all(|p| *p > 0. && *p <= 1.));
+            assert!(results
+                .fdr()
+                .iter()
+                .zip(results.pvalues().iter())
+                .all(|(q, p)| *q >= p - 1e-12));
+        }
+
+        assert!(combine_pvalues(
+            &pvalues,
+            &weights,
+            &gene_names[..4],
+            Combination::Fisher,
+            Procedure::BenjaminiHochberg
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 use super::{
-    utils::{filter_zeros, num_unique, set_alpha_threshold},
-    AggregationResult, GeneAggregation,
+    bayes_factors, combine_pvalues, drugz, second_best_aggregation,
+    utils::{
+        filter_zeros, nonzero_indices, num_unique, select_from_mask, select_from_mask_array,
+        set_alpha_threshold,
+    },
+    AggregationResult, Combination, GeneAggregation, ReferenceSets, StoufferWeights,
 };
 use crate::{
     enrich::EnrichmentResult,
@@ -8,12 +12,12 @@ use crate::{
 };
 use adjustp::Procedure;
 use alpha_rra::AlphaRRA;
-use anyhow::{Context, Result};
-use bon::{bon, Builder};
+use anyhow::{bail, Context, Result};
+use bon::{bon, builder, Builder};
 use geopagg::{GeoPAGG, TransformConfig, WeightConfig};
 use intc::{fdr::Direction, Inc};
 use log::debug;
-use ndarray::Array1;
+use ndarray::{Array1, Array2, Axis};
 
 #[derive(Builder)]
 struct RunAggregation<'a> {
@@ -233,6 +237,180 @@ impl<'a> RunAggregation<'a> {
             .threshold_high(fdr)
             .build())
     }
+
+    /// Runs BAGEL which only scores gene depletion
+    ///
+    /// The depletion p-values are empirical against the non-essential training genes and
+    /// the depletion FDR is the precision-recall FDR. Enrichments are never called.
+    #[builder]
+    pub fn run_bagel(
+        &self,
+        sets: &ReferenceSets,
+        n_bootstraps: usize,
+        fdr: f64,
+    ) -> Result<InternalAggregationResult> {
+        self.logger.report_bagel_params(
+            sets.essential().len(),
+            sets.nonessential().len(),
+            n_bootstraps,
+            fdr,
+            self.seed as usize,
+        );
+        let results = bayes_factors(self.logfc, self.gene_names, sets, n_bootstraps, self.seed)?;
+
+        let gene_fc_hashmap = aggregate_fold_changes(self.gene_names, self.logfc);
+        let gene_fc = results
+            .genes()
+            .iter()
+            .map(|gene| gene_fc_hashmap.get(gene).unwrap_or(&0.0))
+            .copied()
+            .collect();
+        let ones = Array1::ones(results.genes().len());
+
+        Ok(InternalAggregationResult::builder()
+            .genes(results.genes().to_vec())
+            .logfc(gene_fc)
+            .scores_low(results.bayes_factors().to_owned())
+            .pvalues_low(results.pvalues().to_owned())
+            .correction_low(results.fdr().to_owned())
+            .scores_high(results.bayes_factors().mapv(|x| -x))
+            .pvalues_high(ones.clone())
+            .correction_high(ones)
+            .threshold_low(fdr)
+            .threshold_high(fdr)
+            .build())
+    }
+
+    /// Runs drugZ on the fold changes of each replicate
+    #[builder]
+    pub fn run_drugz(
+        &self,
+        fold_changes: &Array2<f64>,
+        control_means: &Array1<f64>,
+        half_window: usize,
+        fdr: f64,
+        correction: Procedure,
+    ) -> Result<InternalAggregationResult> {
+        self.logger
+            .report_drugz_params(half_window, fold_changes.ncols(), fdr);
+        let results = drugz(
+            fold_changes,
+            control_means,
+            self.gene_names,
+            half_window,
+            correction,
+        )?;
+
+        let gene_fc_hashmap = aggregate_fold_changes(self.gene_names, self.logfc);
+        let gene_fc = results
+            .genes()
+            .iter()
+            .map(|gene| gene_fc_hashmap.get(gene).unwrap_or(&0.0))
+            .copied()
+            .collect();
+
+        Ok(InternalAggregationResult::builder()
+            .genes(results.genes().to_vec())
+            .logfc(gene_fc)
+            .scores_low(results.norm_z().to_owned())
+            .pvalues_low(results.pvalues_low().to_owned())
+            .correction_low(results.fdr_low().to_owned())
+            .scores_high(results.norm_z().to_owned())
+            .pvalues_high(results.pvalues_high().to_owned())
+            .correction_high(results.fdr_high().to_owned())
+            .threshold_low(fdr)
+            .threshold_high(fdr)
+            .build())
+    }
+
+    /// Combines the sgRNA p-values of each gene on both sides of the screen
+    #[builder]
+    pub fn run_combination(
+        &self,
+        method: Combination,
+        weights: &Array1<f64>,
+        stouffer_weights: Option<StoufferWeights>,
+        fdr: f64,
+        correction: Procedure,
+    ) -> Result<InternalAggregationResult> {
+        self.logger
+            .report_combination_params(method, stouffer_weights, fdr);
+        let result_low = combine_pvalues(
+            self.pvalue_low,
+            weights,
+            self.gene_names,
+            method,
+            correction,
+        )
+        .context("Error combining depleted pvalues")?;
+        let result_high = combine_pvalues(
+            self.pvalue_high,
+            weights,
+            self.gene_names,
+            method,
+            correction,
+        )
+        .context("Error combining enriched pvalues")?;
+
+        let gene_fc_hashmap = aggregate_fold_changes(self.gene_names, self.logfc);
+        let gene_fc = result_low
+            .genes()
+            .iter()
+            .map(|gene| gene_fc_hashmap.get(gene).unwrap_or(&0.0))
+            .copied()
+            .collect();
+
+        Ok(InternalAggregationResult::builder()
+            .genes(result_low.genes().to_vec())
+            .logfc(gene_fc)
+            .scores_low(result_low.scores().to_owned())
+            .pvalues_low(result_low.pvalues().to_owned())
+            .correction_low(result_low.fdr().to_owned())
+            .scores_high(result_high.scores().to_owned())
+            .pvalues_high(result_high.pvalues().to_owned())
+            .correction_high(result_high.fdr().to_owned())
+            .threshold_low(fdr)
+            .threshold_high(fdr)
+            .build())
+    }
+
+    /// Scores genes by their second-best sgRNA against random non-targeting groups
+    ///
+    /// The gene log fold change is the median of its sgRNAs.
+    #[builder]
+    pub fn run_second_best(
+        &self,
+        sgrna_names: &[String],
+        token: &str,
+        n_draws: usize,
+        fdr: f64,
+        correction: Procedure,
+    ) -> Result<InternalAggregationResult> {
+        self.logger
+            .report_second_best_params(token, n_draws, fdr, self.seed as usize);
+        let results = second_best_aggregation(
+            self.logfc,
+            self.gene_names,
+            sgrna_names,
+            token,
+            n_draws,
+            correction,
+            self.seed,
+        )?;
+
+        Ok(InternalAggregationResult::builder()
+            .genes(results.genes().to_vec())
+            .logfc(results.median_logfc().to_owned())
+            .scores_low(results.second_low().to_owned())
+            .pvalues_low(results.pvalues_low().to_owned())
+            .correction_low(results.fdr_low().to_owned())
+            .scores_high(results.second_high().to_owned())
+            .pvalues_high(results.pvalues_high().to_owned())
+            .correction_high(results.fdr_high().to_owned())
+            .threshold_low(fdr)
+            .threshold_high(fdr)
+            .build())
+    }
 }
 
 /// Aggregates the results of the gene aggregation analysis for internal use
@@ -279,10 +457,18 @@ impl InternalAggregationResult {
 }
 
 /// Computes gene aggregation using the provided method and associated configurations.
+///
+/// The reference sets are only required by BAGEL, where they are the training genes, and the
+/// sgRNA efficacy scores are only required by Stouffer's method with efficacy weights. The
+/// second-best null is built from the sgRNAs whose names contain its non-targeting token.
+#[builder]
 pub fn compute_aggregation(
-    agg: &GeneAggregation,
+    agg: &GeneAggregation<'_>,
     sgrna_results: &EnrichmentResult,
     gene_names: &[String],
+    sgrna_names: &[String],
+    reference_sets: Option<&ReferenceSets>,
+    sgrna_efficacy: Option<&Array1<f64>>,
     logger: &Logger,
     correction: Procedure,
     seed: u64,
@@ -358,6 +544,94 @@ pub fn compute_aggregation(
             .use_product(*use_product)
             .maybe_zscore_threshold(*zscore_threshold)
             .call(),
+
+        GeneAggregation::Bagel { n_bootstraps, fdr } => {
+            let Some(sets) = reference_sets else {
+                bail!("BAGEL aggregation requires essential and non-essential training genes")
+            };
+            runner
+                .run_bagel()
+                .sets(sets)
+                .n_bootstraps(*n_bootstraps)
+                .fdr(*fdr)
+                .call()
+        }
+
+        GeneAggregation::DrugZ { half_window, fdr } => {
+            // fall back to the mean log fold change as a single replicate
+            let passing = nonzero_indices(sgrna_results.base_means());
+            let fold_changes = match sgrna_results.replicate_log_fold_changes() {
+                Some(fold_changes) => fold_changes.select(Axis(0), &passing),
+                None => passing_sgrna_logfc.clone().insert_axis(Axis(1)),
+            };
+            runner
+                .run_drugz()
+                .fold_changes(&fold_changes)
+                .control_means(&select_from_mask_array(
+                    sgrna_results.control_means(),
+                    &passing,
+                ))
+                .half_window(*half_window)
+                .fdr(*fdr)
+                .correction(correction)
+                .call()
+        }
+
+        GeneAggregation::Fisher { fdr } => runner
+            .run_combination()
+            .method(Combination::Fisher)
+            .weights(&Array1::ones(passing_gene_names.len()))
+            .fdr(*fdr)
+            .correction(correction)
+            .call(),
+
+        GeneAggregation::Stouffer { weights, fdr } => {
+            let passing = nonzero_indices(sgrna_results.base_means());
+            let sgrna_weights = match weights {
+                StoufferWeights::BaseMean => {
+                    select_from_mask_array(sgrna_results.base_means(), &passing).mapv(f64::sqrt)
+                }
+                StoufferWeights::Efficacy => {
+                    let Some(efficacy) = sgrna_efficacy else {
+                        bail!("Stouffer efficacy weights require sgRNA efficacy scores")
+                    };
+                    select_from_mask_array(efficacy, &passing)
+                }
+                StoufferWeights::Uniform => Array1::ones(passing.len()),
+            };
+            runner
+                .run_combination()
+                .method(Combination::Stouffer)
+                .weights(&sgrna_weights)
+                .stouffer_weights(*weights)
+                .fdr(*fdr)
+                .correction(correction)
+                .call()
+        }
+
+        GeneAggregation::Acat { fdr } => runner
+            .run_combination()
+            .method(Combination::Acat)
+            .weights(&Array1::ones(passing_gene_names.len()))
+            .fdr(*fdr)
+            .correction(correction)
+            .call(),
+
+        GeneAggregation::SecondBest {
+            token,
+            n_draws,
+            fdr,
+        } => runner
+            .run_second_best()
+            .sgrna_names(&select_from_mask(
+                sgrna_names,
+                &nonzero_indices(sgrna_results.base_means()),
+            ))
+            .token(token)
+            .n_draws(*n_draws)
+            .fdr(*fdr)
+            .correction(correction)
+            .call(),
     }?;
 
     let fold_change = agg_result
@@ -0,0 +1,484 @@
+use adjustp::{adjust, Procedure};
+use anyhow::{bail, Result};
+use bon::builder;
+use hashbrown::HashMap;
+use ndarray::Array1;
+use polars::prelude::*;
+
+use super::{compute_aggregation, AggregationResult, GeneAggregation};
+use crate::{
+    enrich::EnrichmentResult,
+    io::{write_table, OutputFormat},
+    norm::average_ranks,
+    utils::{config::Configuration, logging::Logger},
+};
+
+/// Calls the depleted and enriched hits of a gene aggregation the same way as the hit list
+pub fn hit_masks(
+    results: &AggregationResult,
+    agg: &GeneAggregation,
+) -> (Array1<bool>, Array1<bool>) {
+    match agg {
+        GeneAggregation::Inc {
+            use_product: true, ..
+        } => {
+            let low = results.threshold_low().unwrap_or(f64::NEG_INFINITY);
+            let high = results.threshold_high().unwrap_or(f64::INFINITY);
+            (
+                results.phenotype_score().mapv(|x| x < low),
+                results.phenotype_score().mapv(|x| x > high),
+            )
+        }
+        _ => (
+            results.fdr_low().mapv(|x| x < agg.fdr()),
+            results.fdr_high().mapv(|x| x < agg.fdr()),
+        ),
+    }
+}
+
+/// Side of the screen a consensus gene is called on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Depleted in the treatment
+    Low,
+    /// Enriched in the treatment
+    High,
+}
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Per-method values and consensus statistics of one side of the screen
+struct SideConsensus {
+    pvalues: Vec<Array1<f64>>,
+    fdrs: Vec<Array1<f64>>,
+    hits: Vec<Array1<bool>>,
+    min_pvalue: Array1<f64>,
+    rank_product: Array1<f64>,
+    minp_pvalue: Array1<f64>,
+    minp_fdr: Array1<f64>,
+}
+impl SideConsensus {
+    fn new(
+        pvalues: Vec<Array1<f64>>,
+        fdrs: Vec<Array1<f64>>,
+        hits: Vec<Array1<bool>>,
+        correction: Procedure,
+    ) -> Self {
+        let n_genes = pvalues[0].len();
+        let n_methods = pvalues.len() as f64;
+
+        // geometric mean of the normalized per-method ranks
+        let mut log_ranks = Array1::<f64>::zeros(n_genes);
+        for method_pvalues in pvalues.iter() {
+            let ranks = Array1::from(average_ranks(method_pvalues.as_slice().unwrap()));
+            log_ranks += &ranks.mapv(|r| (r / n_genes as f64).ln());
+        }
+        let rank_product = log_ranks.mapv(|x| (x / n_methods).exp());
+
+        let min_pvalue = (0..n_genes)
+            .map(|idx| pvalues.iter().map(|p| p[idx]).fold(1., f64::min))
+            .collect::<Array1<f64>>();
+        let minp_pvalue = min_pvalue.mapv(|x| (x * n_methods).min(1.));
+        let minp_fdr = Array1::from(adjust(minp_pvalue.as_slice().unwrap(), correction));
+
+        Self {
+            pvalues,
+            fdrs,
+            hits,
+            min_pvalue,
+            rank_product,
+            minp_pvalue,
+            minp_fdr,
+        }
+    }
+}
+
+/// Consensus of several gene aggregation methods run on the same sgRNA results
+///
+/// Only the genes reported by every method are kept (e.g. INC pseudogenes are dropped). Each
+/// side of the screen is combined separately and every gene is reported on the side with its
+/// smallest p-value across methods, so methods calling a gene in opposite directions do not
+/// agree. Genes are ranked by the rank product of their per-method p-values and tested with
+/// the minimum p-value across methods, Bonferroni-corrected for the number of methods.
+#[derive(Debug)]
+pub struct Consensus {
+    genes: Vec<String>,
+    methods: Vec<String>,
+    directions: Vec<Direction>,
+    pvalues: Vec<Array1<f64>>,
+    fdrs: Vec<Array1<f64>>,
+    hits: Vec<Array1<bool>>,
+    n_hits: Array1<usize>,
+    rank_product: Array1<f64>,
+    minp_pvalue: Array1<f64>,
+    minp_fdr: Array1<f64>,
+    consensus_rank: Array1<usize>,
+}
+impl Consensus {
+    pub fn new(
+        results: &[(&GeneAggregation, &AggregationResult)],
+        correction: Procedure,
+    ) -> Result<Self> {
+        if results.len() < 2 {
+            bail!("A consensus requires at least two aggregation methods")
+        }
+
+        // genes reported by every method in the order of the first method
+        let positions = results
+            .iter()
+            .map(|(_, result)| {
+                result
+                    .genes()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, gene)| (gene.as_str(), idx))
+                    .collect::<HashMap<_, _>>()
+            })
+            .collect::<Vec<_>>();
+        let genes = results[0]
+            .1
+            .genes()
+            .iter()
+            .filter(|gene| positions.iter().all(|map| map.contains_key(gene.as_str())))
+            .cloned()
+            .collect::<Vec<_>>();
+        if genes.is_empty() {
+            bail!("No genes are shared by all aggregation methods")
+        }
+
+        let n_methods = results.len();
+        let mut methods = Vec::with_capacity(n_methods);
+        let mut pvalues: [Vec<Array1<f64>>; 2] = Default::default();
+        let mut fdrs: [Vec<Array1<f64>>; 2] = Default::default();
+        let mut hits: [Vec<Array1<bool>>; 2] = Default::default();
+        for ((agg, result), map) in results.iter().zip(positions.iter()) {
+            let indices = genes
+                .iter()
+                .map(|gene| map[gene.as_str()])
+                .collect::<Vec<_>>();
+            let select_f64 =
+                |values: &Array1<f64>| indices.iter().map(|idx| values[*idx]).collect();
+            let select_bool =
+                |values: &Array1<bool>| indices.iter().map(|idx| values[*idx]).collect();
+            let (mask_low, mask_high) = hit_masks(result, agg);
+            methods.push(agg.name().to_string());
+            pvalues[0].push(select_f64(result.pvalues_low()));
+            pvalues[1].push(select_f64(result.pvalues_high()));
+            fdrs[0].push(select_f64(result.fdr_low()));
+            fdrs[1].push(select_f64(result.fdr_high()));
+            hits[0].push(select_bool(&mask_low));
+            hits[1].push(select_bool(&mask_high));
+        }
+        let [pvalues_low, pvalues_high] = pvalues;
+        let [fdrs_low, fdrs_high] = fdrs;
+        let [hits_low, hits_high] = hits;
+        let low = SideConsensus::new(pvalues_low, fdrs_low, hits_low, correction);
+        let high = SideConsensus::new(pvalues_high, fdrs_high, hits_high, correction);
+
+        // report every gene on the side of its smallest p-value (depletions on ties)
+        let n_genes = genes.len();
+        let directions = (0..n_genes)
+            .map(|idx| {
+                if high.min_pvalue[idx] < low.min_pvalue[idx] {
+                    Direction::High
+                } else {
+                    Direction::Low
+                }
+            })
+            .collect::<Vec<_>>();
+        let side = |idx: usize| match directions[idx] {
+            Direction::Low => &low,
+            Direction::High => &high,
+        };
+
+        let pvalues = (0..n_methods)
+            .map(|m| (0..n_genes).map(|idx| side(idx).pvalues[m][idx]).collect())
+            .collect::<Vec<Array1<f64>>>();
+        let fdrs = (0..n_methods)
+            .map(|m| (0..n_genes).map(|idx| side(idx).fdrs[m][idx]).collect())
+            .collect::<Vec<Array1<f64>>>();
+        let hits = (0..n_methods)
+            .map(|m| (0..n_genes).map(|idx| side(idx).hits[m][idx]).collect())
+            .collect::<Vec<Array1<bool>>>();
+        let n_hits = (0..n_genes)
+            .map(|idx| hits.iter().filter(|mask| mask[idx]).count())
+            .collect::<Array1<usize>>();
+        let rank_product = (0..n_genes)
+            .map(|idx| side(idx).rank_product[idx])
+            .collect::<Array1<f64>>();
+        let minp_pvalue = (0..n_genes)
+            .map(|idx| side(idx).minp_pvalue[idx])
+            .collect::<Array1<f64>>();
+        let minp_fdr = (0..n_genes)
+            .map(|idx| side(idx).minp_fdr[idx])
+            .collect::<Array1<f64>>();
+
+        let mut order = (0..n_genes).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            rank_product[*a]
+                .total_cmp(&rank_product[*b])
+                .then(minp_pvalue[*a].total_cmp(&minp_pvalue[*b]))
+        });
+        let mut consensus_rank = Array1::zeros(n_genes);
+        for (rank, idx) in order.into_iter().enumerate() {
+            consensus_rank[idx] = rank + 1;
+        }
+
+        Ok(Self {
+            genes,
+            methods,
+            directions,
+            pvalues,
+            fdrs,
+            hits,
+            n_hits,
+            rank_product,
+            minp_pvalue,
+            minp_fdr,
+            consensus_rank,
+        })
+    }
+
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    pub fn methods(&self) -> &[String] {
+        &self.methods
+    }
+
+    /// The side of the screen each gene is reported on
+    pub fn directions(&self) -> &[Direction] {
+        &self.directions
+    }
+
+    pub fn rank_product(&self) -> &Array1<f64> {
+        &self.rank_product
+    }
+
+    pub fn minp_fdr(&self) -> &Array1<f64> {
+        &self.minp_fdr
+    }
+
+    pub fn consensus_rank(&self) -> &Array1<usize> {
+        &self.consensus_rank
+    }
+
+    /// The number of shared genes called as hits by each method in the direction of the gene
+    pub fn method_hits(&self) -> Vec<usize> {
+        self.hits
+            .iter()
+            .map(|mask| mask.iter().filter(|x| **x).count())
+            .collect()
+    }
+
+    /// The number of genes called as hits in their direction by exactly `k` methods, for `k`
+    /// in `0..=n_methods`
+    pub fn agreement(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.methods.len() + 1];
+        self.n_hits.iter().for_each(|n| counts[*n] += 1);
+        counts
+    }
+
+    /// The number of genes called as hits in the same direction by every method
+    pub fn unanimous_hits(&self) -> usize {
+        self.agreement()[self.methods.len()]
+    }
+
+    /// Writes the merged gene table to `<prefix>.consensus.tsv` (sorted by consensus rank) and
+    /// the hit agreement to `<prefix>.consensus_agreement.tsv`
+    pub fn write(&self, prefix: &str) -> Result<()> {
+        let mut columns = vec![
+            Series::new("gene".into(), &self.genes),
+            Series::new(
+                "direction".into(),
+                self.directions.iter().map(|x| x.name()).collect::<Vec<_>>(),
+            ),
+        ];
+        for (idx, method) in self.methods.iter().enumerate() {
+            columns.push(Series::new(
+                format!("{method}_pvalue").into(),
+                self.pvalues[idx].to_vec(),
+            ));
+            columns.push(Series::new(
+                format!("{method}_fdr").into(),
+                self.fdrs[idx].to_vec(),
+            ));
+            columns.push(Series::new(
+                format!("{method}_hit").into(),
+                self.hits[idx].to_vec(),
+            ));
+        }
+        columns.extend([
+            Series::new(
+                "n_hits".into(),
+                self.n_hits.iter().map(|x| *x as u64).collect::<Vec<_>>(),
+            ),
+            Series::new("rank_product".into(), self.rank_product.to_vec()),
+            Series::new("minp_pvalue".into(), self.minp_pvalue.to_vec()),
+            Series::new("minp_fdr".into(), self.minp_fdr.to_vec()),
+            Series::new(
+                "consensus_rank".into(),
+                self.consensus_rank
+                    .iter()
+                    .map(|x| *x as u64)
+                    .collect::<Vec<_>>(),
+            ),
+        ]);
+        let mut frame = DataFrame::new(columns)?;
+        frame.sort_in_place(["consensus_rank"], Default::default())?;
+        write_table(
+            &mut frame,
+            Some(format!("{prefix}.consensus.tsv")),
+            OutputFormat::Tsv,
+        )?;
+
+        let agreement = self.agreement();
+        let mut frame = df!(
+            "n_methods" => (0..agreement.len() as u64).collect::<Vec<_>>(),
+            "n_genes" => agreement.iter().map(|x| *x as u64).collect::<Vec<_>>(),
+        )?;
+        write_table(
+            &mut frame,
+            Some(format!("{prefix}.consensus_agreement.tsv")),
+            OutputFormat::Tsv,
+        )
+    }
+}
+
+/// Runs the additional consensus methods of the configuration and writes the consensus with
+/// the already computed primary aggregation
+#[builder]
+pub fn run_consensus(
+    primary: &AggregationResult,
+    sgrna_results: &EnrichmentResult,
+    gene_names: &[String],
+    sgrna_names: &[String],
+    sgrna_efficacy: Option<&Array1<f64>>,
+    config: &Configuration<'_>,
+    logger: &Logger,
+) -> Result<()> {
+    let additional = config
+        .consensus_aggregations()
+        .iter()
+        .map(|agg| {
+            compute_aggregation()
+                .agg(agg)
+                .sgrna_results(sgrna_results)
+                .gene_names(gene_names)
+                .sgrna_names(sgrna_names)
+                .maybe_reference_sets(config.reference_sets().as_ref())
+                .maybe_sgrna_efficacy(sgrna_efficacy)
+                .logger(logger)
+                .correction(*config.correction())
+                .seed(*config.seed())
+                .call()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let results = config
+        .aggregations()
+        .zip(std::iter::once(primary).chain(additional.iter()))
+        .collect::<Vec<_>>();
+    let consensus = Consensus::new(&results, *config.correction())?;
+    logger.consensus(&consensus);
+    consensus.write(config.prefix())
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    fn build_result(genes: &[&str], pvalues: Vec<f64>) -> AggregationResult {
+        let n = genes.len();
+        build_sided_result(genes, pvalues, vec![1.; n])
+    }
+
+    fn build_sided_result(genes: &[&str], low: Vec<f64>, high: Vec<f64>) -> AggregationResult {
+        let n = genes.len();
+        let low = Array1::from(low);
+        let high = Array1::from(high);
+        AggregationResult::builder()
+            .genes(genes.iter().map(|x| x.to_string()).collect())
+            .gene_fc(Array1::from_elem(n, 0.5))
+            .pvalues_low(low.clone())
+            .pvalues_high(high.clone())
+            .fdr_low(low.clone())
+            .fdr_high(high.clone())
+            .aggregation_score_low(low)
+            .aggregation_score_high(high)
+            .build()
+    }
+
+    #[test]
+    fn test_consensus() -> Result<()> {
+        let rra = GeneAggregation::AlpaRRA {
+            alpha: 0.1,
+            npermutations: 100,
+            adjust_alpha: true,
+            fdr: 0.1,
+        };
+        let acat = GeneAggregation::Acat { fdr: 0.1 };
+        let first = build_result(&["a", "b", "c", "d"], vec![0.001, 0.05, 0.5, 0.9]);
+        let second = build_result(&["d", "c", "b", "a", "x"], vec![0.8, 0.4, 0.2, 0.002, 0.0]);
+
+        let consensus = Consensus::new(
+            &[(&rra, &first), (&acat, &second)],
+            Procedure::BenjaminiHochberg,
+        )?;
+        assert_eq!(consensus.genes(), &["a", "b", "c", "d"]);
+        assert_eq!(consensus.methods(), &["rra", "acat"]);
+        assert_eq!(consensus.consensus_rank().to_vec(), vec![1, 2, 3, 4]);
+        assert!((consensus.rank_product()[0] - 0.25).abs() < 1e-12);
+        assert_eq!(consensus.method_hits(), vec![2, 1]);
+        assert_eq!(consensus.agreement(), vec![2, 1, 1]);
+        assert_eq!(consensus.unanimous_hits(), 1);
+        assert!((consensus.minp_pvalue[0] - 0.002).abs() < 1e-12);
+
+        assert!(Consensus::new(&[(&rra, &first)], Procedure::BenjaminiHochberg).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consensus_directions() -> Result<()> {
+        let rra = GeneAggregation::AlpaRRA {
+            alpha: 0.1,
+            npermutations: 100,
+            adjust_alpha: true,
+            fdr: 0.1,
+        };
+        let acat = GeneAggregation::Acat { fdr: 0.1 };
+
+        // `a` is depleted in one method and enriched in the other, `c` is enriched in both
+        let first = build_sided_result(
+            &["a", "b", "c"],
+            vec![0.001, 0.5, 1.0],
+            vec![1.0, 0.5, 0.001],
+        );
+        let second = build_sided_result(
+            &["a", "b", "c"],
+            vec![1.0, 0.5, 1.0],
+            vec![0.001, 0.5, 0.002],
+        );
+
+        let consensus = Consensus::new(
+            &[(&rra, &first), (&acat, &second)],
+            Procedure::BenjaminiHochberg,
+        )?;
+        assert_eq!(
+            consensus.directions(),
+            &[Direction::Low, Direction::Low, Direction::High]
+        );
+        assert_eq!(consensus.method_hits(), vec![2, 1]);
+        assert_eq!(consensus.agreement(), vec![1, 1, 1]);
+        assert_eq!(consensus.unanimous_hits(), 1);
+        assert_eq!(consensus.consensus_rank()[2], 1);
+        assert!((consensus.minp_pvalue[2] - 0.002).abs() < 1e-12);
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use adjustp::{adjust, Procedure};
+use anyhow::{bail, Result};
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use statrs::distribution::{ContinuousCDF, Normal};
+
+use super::utils::group_indices;
+use crate::norm::quantile;
+
+/// Normalized gene z-scores of a drugZ analysis with p-values and FDR in both directions
+#[derive(Debug)]
+pub struct DrugZResult {
+    genes: Vec<String>,
+    norm_z: Array1<f64>,
+    pvalues_low: Array1<f64>,
+    pvalues_high: Array1<f64>,
+    fdr_low: Array1<f64>,
+    fdr_high: Array1<f64>,
+}
+impl DrugZResult {
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    /// The sum of the sgRNA z-scores of a gene divided by the square root of their number
+    pub fn norm_z(&self) -> &Array1<f64> {
+        &self.norm_z
+    }
+
+    pub fn pvalues_low(&self) -> &Array1<f64> {
+        &self.pvalues_low
+    }
+
+    pub fn pvalues_high(&self) -> &Array1<f64> {
+        &self.pvalues_high
+    }
+
+    pub fn fdr_low(&self) -> &Array1<f64> {
+        &self.fdr_low
+    }
+
+    pub fn fdr_high(&self) -> &Array1<f64> {
+        &self.fdr_high
+    }
+}
+
+/// Calculates the empirical-Bayes standard deviation of every sgRNA from the fold changes of
+/// the sgRNAs with the most similar control abundance
+///
+/// The sgRNAs are ordered by their control means and the standard deviation is taken over a
+/// window of `2 * half_window + 1` neighbors, shifted inwards at the edges so every window
+/// is the same size.
+fn empirical_bayes_std(
+    fold_changes: &ArrayView1<f64>,
+    order: &[usize],
+    half_window: usize,
+) -> Array1<f64> {
+    let n = order.len();
+    let width = (2 * half_window + 1).min(n);
+
+    // prefix sums of the fold changes and their squares in control abundance order
+    let mut sums = vec![0.; n + 1];
+    let mut squares = vec![0.; n + 1];
+    for (rank, idx) in order.iter().enumerate() {
+        sums[rank + 1] = sums[rank] + fold_changes[*idx];
+        squares[rank + 1] = squares[rank] + fold_changes[*idx].powi(2);
+    }
+
+    let mut std = Array1::zeros(n);
+    for (rank, idx) in order.iter().enumerate() {
+        let start = rank.saturating_sub(half_window).min(n - width);
+        let end = start + width;
+        let sum = sums[end] - sums[start];
+        let square = squares[end] - squares[start];
+        let variance = (square - sum * sum / width as f64) / (width as f64 - 1.);
+        std[*idx] = variance.max(0.).sqrt();
+    }
+    std
+}
+
+/// Calculates the z-scores of the sgRNAs of every replicate (sgRNAs x replicates)
+///
+/// The fold changes of each replicate are centered on their median and scaled by the
+/// empirical-Bayes standard deviation of sgRNAs with a similar control abundance. sgRNAs
+/// without any variation in their window are given a z-score of zero.
+fn sgrna_zscores(
+    fold_changes: &Array2<f64>,
+    control_means: &Array1<f64>,
+    half_window: usize,
+) -> Array2<f64> {
+    let mut order = (0..control_means.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| control_means[*a].total_cmp(&control_means[*b]));
+
+    let mut zscores = Array2::zeros(fold_changes.dim());
+    for (replicate, mut column) in fold_changes
+        .axis_iter(Axis(1))
+        .zip(zscores.axis_iter_mut(Axis(1)))
+    {
+        let mut sorted = replicate.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = quantile(&sorted, 0.5);
+        let centered = replicate.mapv(|x| x - median);
+        let std = empirical_bayes_std(&centered.view(), &order, half_window);
+        for (z, (fc, sd)) in column.iter_mut().zip(centered.iter().zip(std.iter())) {
+            *z = if *sd > 0. { fc / sd } else { 0. };
+        }
+    }
+    zscores
+}
+
+/// Aggregates sgRNA fold changes to genes with drugZ
+///
+/// The z-scores of every sgRNA of a gene in every replicate are summed and normalized by the
+/// square root of their number. The normalized z-scores are compared to a standard normal
+/// distribution for depletion (`low`) and enrichment (`high`) and each side is corrected
+/// separately.
+///
+/// # Arguments
+/// * `fold_changes` - the log2 fold changes of each replicate (sgRNAs x replicates)
+/// * `control_means` - the normalized control mean of each sgRNA
+pub fn drugz(
+    fold_changes: &Array2<f64>,
+    control_means: &Array1<f64>,
+    gene_names: &[String],
+    half_window: usize,
+    correction: Procedure,
+) -> Result<DrugZResult> {
+    if fold_changes.nrows() != control_means.len() || fold_changes.nrows() != gene_names.len() {
+        bail!("The fold changes, control means, and gene names must describe the same sgRNAs")
+    }
+    if fold_changes.nrows() < 2 || half_window == 0 {
+        bail!("drugZ requires at least two sgRNAs and a positive half window")
+    }
+
+    let zscores = sgrna_zscores(fold_changes, control_means, half_window);
+    let (genes, gene_sgrnas) = group_indices(gene_names);
+    let norm_z = gene_sgrnas
+        .iter()
+        .map(|sgrnas| {
+            let values = zscores.select(Axis(0), sgrnas);
+            values.sum() / (values.len() as f64).sqrt()
+        })
+        .collect::<Array1<f64>>();
+
+    let normal = Normal::standard();
+    let pvalues_low = norm_z.mapv(|z| normal.cdf(z));
+    let pvalues_high = norm_z.mapv(|z| normal.sf(z));
+    let fdr_low = Array1::from(adjust(pvalues_low.as_slice().unwrap(), correction));
+    let fdr_high = Array1::from(adjust(pvalues_high.as_slice().unwrap(), correction));
+
+    Ok(DrugZResult {
+        genes,
+        norm_z,
+        pvalues_low,
+        pvalues_high,
+        fdr_low,
+        fdr_high,
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::{array, Array1};
+
+    #[test]
+    fn test_empirical_bayes_std() {
+        let fold_changes = array![1., -1., 2., -2., 10., -10.];
+        let order = (0..6).collect::<Vec<_>>();
+        let std = empirical_bayes_std(&fold_changes.view(), &order, 1);
+
+        // the edges use the first and last windows of three
+        let first = (1f64 + 1. + 4. - (2f64).powi(2) / 3.) / 2.;
+        assert!((std[0] - first.sqrt()).abs() < 1e-12);
+        assert_eq!(std[0], std[1]);
+        assert!(std[5] > std[1]);
+        assert_eq!(std[4], std[5]);
+    }
+
+    #[test]
+    fn test_drugz() -> Result<()> {
+        let gene_names = ["a", "a", "b", "b", "c", "c", "d", "d"]
+            .map(|x| x.to_string())
+            .to_vec();
+        let fold_changes = array![
+            [-3.0, -2.8],
+            [-2.9, -3.1],
+            [0.1, -0.1],
+            [-0.2, 0.2],
+            [0.0, 0.1],
+            [0.2, -0.1],
+            [2.8, 3.0],
+            [3.1, 2.9],
+        ];
+        let control_means = Array1::from_elem(8, 100.);
+        let results = drugz(
+            &fold_changes,
+            &control_means,
+            &gene_names,
+            10,
+            Procedure::BenjaminiHochberg,
+        )?;
+        assert_eq!(results.genes(), &["a", "b", "c", "d"]);
+        assert!(results.norm_z()[0] < 0.);
+        assert!(results.norm_z()[3] > 0.);
+        assert!(results.pvalues_low()[0] < results.pvalues_low()[1]);
+        assert!(results.pvalues_high()[3] < results.pvalues_high()[2]);
+        assert!((results.pvalues_low()[0] + results.pvalues_high()[0] - 1.).abs() < 1e-12);
+
+        assert!(drugz(
+            &fold_changes,
+            &control_means,
+            &gene_names[..4],
+            10,
+            Procedure::BenjaminiHochberg
+        )
+        .is_err());
+        Ok(())
+    }
+}
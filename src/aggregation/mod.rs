@@ -1,11 +1,23 @@
+mod bagel;
+mod combination;
 mod compute_aggregation;
+mod consensus;
+mod drugz;
+mod performance;
 mod results;
+mod second_best;
 mod utils;
 
+pub use bagel::{bayes_factors, BayesFactors};
 use clap::ValueEnum;
+pub use combination::{combine_pvalues, Combination};
 pub use compute_aggregation::compute_aggregation;
+pub use consensus::{run_consensus, Consensus};
+pub use drugz::{drugz, DrugZResult};
 use geopagg::WeightConfig;
+pub use performance::{screen_performance, ReferenceSets, ScreenPerformance};
 pub use results::AggregationResult;
+pub use second_best::second_best_aggregation;
 
 /// Enum describing aggregation procedure selection
 #[derive(ValueEnum, Clone, Debug, PartialEq)]
@@ -20,10 +32,29 @@ pub enum GeneAggregationSelection {
     /// GeoPAGG Method
     #[value(name = "geopagg")]
     GeoPAGG,
+
+    /// BAGEL Method, i.e. Bayes factors from essential and non-essential training genes
+    Bagel,
+
+    /// drugZ Method, i.e. summed sgRNA z-scores with an empirical-Bayes variance
+    #[value(name = "drugz")]
+    DrugZ,
+
+    /// Fisher's combined probability of the sgRNA p-values
+    Fisher,
+
+    /// Weighted Stouffer's Z-method on the sgRNA p-values
+    Stouffer,
+
+    /// Cauchy combination test (ACAT) of the sgRNA p-values
+    Acat,
+
+    /// Second-best sgRNA and median log fold change against random non-targeting groups
+    SecondBest,
 }
 
 /// Enum describing the different gene aggregation procedures and their associated configurations.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum GeneAggregation<'a> {
     AlpaRRA {
         alpha: f64,
@@ -45,6 +76,66 @@ pub enum GeneAggregation<'a> {
         use_product: bool,
         zscore_threshold: Option<f64>,
     },
+    Bagel {
+        n_bootstraps: usize,
+        fdr: f64,
+    },
+    DrugZ {
+        half_window: usize,
+        fdr: f64,
+    },
+    Fisher {
+        fdr: f64,
+    },
+    Stouffer {
+        weights: StoufferWeights,
+        fdr: f64,
+    },
+    Acat {
+        fdr: f64,
+    },
+    SecondBest {
+        token: &'a str,
+        n_draws: usize,
+        fdr: f64,
+    },
+}
+
+impl GeneAggregation<'_> {
+    /// The short name of the method used in output columns
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AlpaRRA { .. } => "rra",
+            Self::Inc { .. } => "inc",
+            Self::GeoPAGG { .. } => "geopagg",
+            Self::Bagel { .. } => "bagel",
+            Self::DrugZ { .. } => "drugz",
+            Self::Fisher { .. } => "fisher",
+            Self::Stouffer { .. } => "stouffer",
+            Self::Acat { .. } => "acat",
+            Self::SecondBest { .. } => "second_best",
+        }
+    }
+
+    /// The FDR threshold used to call hits
+    pub fn fdr(&self) -> f64 {
+        match self {
+            Self::AlpaRRA { fdr, .. }
+            | Self::Inc { fdr, .. }
+            | Self::GeoPAGG { fdr, .. }
+            | Self::Bagel { fdr, .. }
+            | Self::DrugZ { fdr, .. }
+            | Self::Fisher { fdr }
+            | Self::Stouffer { fdr, .. }
+            | Self::Acat { fdr }
+            | Self::SecondBest { fdr, .. } => *fdr,
+        }
+    }
+
+    /// Whether the method is trained on the essential and non-essential reference sets
+    pub fn uses_reference_sets(&self) -> bool {
+        matches!(self, Self::Bagel { .. })
+    }
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug, Default)]
@@ -55,6 +146,20 @@ pub enum GeoPAGGWeightConfigEnum {
     Balanced,
 }
 
+/// Enum describing the sgRNA weights of Stouffer's method
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum StoufferWeights {
+    /// Square root of the sgRNA base mean
+    #[default]
+    BaseMean,
+
+    /// User-supplied sgRNA efficacy scores
+    Efficacy,
+
+    /// Equal weights for all sgRNAs
+    Uniform,
+}
+
 #[cfg(test)]
 mod testing {
     use super::{GeneAggregation, GeneAggregationSelection};
@@ -0,0 +1,264 @@
+use anyhow::{bail, Result};
+use hashbrown::HashSet;
+use ndarray::Array1;
+use polars::prelude::*;
+use std::path::PathBuf;
+
+use super::AggregationResult;
+use crate::{
+    io::{load_sgrna_list, write_table, OutputFormat},
+    norm::average_ranks,
+};
+
+/// Reference sets of core-essential and non-essential genes used to evaluate a screen
+#[derive(Debug, Clone)]
+pub struct ReferenceSets {
+    essential: HashSet<String>,
+    nonessential: HashSet<String>,
+}
+impl ReferenceSets {
+    pub fn new(essential: Vec<String>, nonessential: Vec<String>) -> Result<Self> {
+        let essential = essential.into_iter().collect::<HashSet<_>>();
+        let nonessential = nonessential.into_iter().collect::<HashSet<_>>();
+        if let Some(gene) = essential.intersection(&nonessential).next() {
+            bail!("Gene ({gene}) is listed as both essential and non-essential")
+        }
+        Ok(Self {
+            essential,
+            nonessential,
+        })
+    }
+
+    pub fn essential(&self) -> &HashSet<String> {
+        &self.essential
+    }
+
+    pub fn nonessential(&self) -> &HashSet<String> {
+        &self.nonessential
+    }
+
+    /// Reads the reference sets from files with one gene per line
+    pub fn from_paths(essential: &str, nonessential: &str) -> Result<Self> {
+        Self::new(
+            load_sgrna_list(PathBuf::from(essential))?,
+            load_sgrna_list(PathBuf::from(nonessential))?,
+        )
+    }
+}
+
+/// How well the gene results of a screen separate the essential from the non-essential
+/// reference genes
+#[derive(Debug, Clone)]
+pub struct ScreenPerformance {
+    n_essential: usize,
+    n_nonessential: usize,
+    auroc_log2fc: f64,
+    auprc_log2fc: f64,
+    auroc_fdr: f64,
+    auprc_fdr: f64,
+    nnmd: f64,
+    fdr: f64,
+    essential_recall: f64,
+}
+impl ScreenPerformance {
+    pub fn auroc_log2fc(&self) -> f64 {
+        self.auroc_log2fc
+    }
+
+    pub fn auprc_log2fc(&self) -> f64 {
+        self.auprc_log2fc
+    }
+
+    pub fn nnmd(&self) -> f64 {
+        self.nnmd
+    }
+
+    pub fn essential_recall(&self) -> f64 {
+        self.essential_recall
+    }
+
+    /// Writes the metrics as a single row to `<prefix>.performance.tsv`
+    pub fn write(&self, prefix: &str) -> Result<()> {
+        let mut df = df!(
+            "n_essential" => [self.n_essential as u64],
+            "n_nonessential" => [self.n_nonessential as u64],
+            "auroc_log2fc" => [self.auroc_log2fc],
+            "auprc_log2fc" => [self.auprc_log2fc],
+            "auroc_fdr" => [self.auroc_fdr],
+            "auprc_fdr" => [self.auprc_fdr],
+            "nnmd" => [self.nnmd],
+            "fdr" => [self.fdr],
+            "essential_recall" => [self.essential_recall],
+        )?;
+        write_table(
+            &mut df,
+            Some(format!("{prefix}.performance.tsv")),
+            OutputFormat::Tsv,
+        )
+    }
+}
+
+/// Calculates the area under the ROC curve of scores where larger values indicate the
+/// positive class
+///
+/// Equivalent to the Mann-Whitney U statistic scaled by the number of pairs.
+fn auroc(positive: &[f64], negative: &[f64]) -> f64 {
+    let ranks = average_ranks(&[positive, negative].concat());
+    let n_pos = positive.len() as f64;
+    let n_neg = negative.len() as f64;
+    let rank_sum = ranks[..positive.len()].iter().sum::<f64>();
+    (rank_sum - n_pos * (n_pos + 1.) / 2.) / (n_pos * n_neg)
+}
+
+/// Calculates the area under the precision-recall curve as the average precision of
+/// scores where larger values indicate the positive class
+///
+/// Tied scores are ranked with the negatives first.
+fn auprc(positive: &[f64], negative: &[f64]) -> f64 {
+    let mut scored = positive
+        .iter()
+        .map(|x| (*x, true))
+        .chain(negative.iter().map(|x| (*x, false)))
+        .collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut n_true = 0.;
+    let mut precision_sum = 0.;
+    for (idx, (_, is_positive)) in scored.iter().enumerate() {
+        if *is_positive {
+            n_true += 1.;
+            precision_sum += n_true / (idx as f64 + 1.);
+        }
+    }
+    precision_sum / positive.len() as f64
+}
+
+/// Calculates the null-normalized mean difference of the essential genes from the
+/// non-essential genes
+///
+/// ```text
+/// NNMD = (mean(essential) - mean(nonessential)) / sd(nonessential)
+/// ```
+fn nnmd(positive: &[f64], negative: &[f64]) -> f64 {
+    let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+    let null_mean = mean(negative);
+    let null_var = negative
+        .iter()
+        .map(|x| (x - null_mean).powi(2))
+        .sum::<f64>()
+        / (negative.len() as f64 - 1.);
+    (mean(positive) - null_mean) / null_var.sqrt()
+}
+
+/// Evaluates how well the gene results separate the essential from the non-essential
+/// reference genes.
+///
+/// Essential genes are expected to deplete, so the negative log2 fold change and the
+/// negative depletion FDR (`fdr_low`) are used as scores. The recall is the fraction of
+/// essential genes with a depletion FDR below the threshold.
+pub fn screen_performance(
+    results: &AggregationResult,
+    sets: &ReferenceSets,
+    fdr: f64,
+) -> Result<ScreenPerformance> {
+    let mut essential = Vec::new();
+    let mut nonessential = Vec::new();
+    for (idx, gene) in results.genes().iter().enumerate() {
+        if sets.essential.contains(gene) {
+            essential.push(idx);
+        } else if sets.nonessential.contains(gene) {
+            nonessential.push(idx);
+        }
+    }
+    if essential.is_empty() {
+        bail!("None of the essential reference genes were found in the gene results")
+    }
+    if nonessential.len() < 2 {
+        bail!("Fewer than two non-essential reference genes were found in the gene results")
+    }
+
+    let select =
+        |values: &Array1<f64>, idx: &[usize]| idx.iter().map(|i| values[*i]).collect::<Vec<_>>();
+    let negate = |values: &[f64]| values.iter().map(|x| -x).collect::<Vec<_>>();
+    let lfc_essential = select(results.gene_log2_fc(), &essential);
+    let lfc_nonessential = select(results.gene_log2_fc(), &nonessential);
+    let fdr_essential = select(results.fdr_low(), &essential);
+    let fdr_nonessential = select(results.fdr_low(), &nonessential);
+
+    // essential genes deplete so lower values are scored higher
+    let (lfc_pos, lfc_neg) = (negate(&lfc_essential), negate(&lfc_nonessential));
+    let (fdr_pos, fdr_neg) = (negate(&fdr_essential), negate(&fdr_nonessential));
+
+    let n_recovered = fdr_essential.iter().filter(|x| **x < fdr).count();
+    Ok(ScreenPerformance {
+        n_essential: essential.len(),
+        n_nonessential: nonessential.len(),
+        auroc_log2fc: auroc(&lfc_pos, &lfc_neg),
+        auprc_log2fc: auprc(&lfc_pos, &lfc_neg),
+        auroc_fdr: auroc(&fdr_pos, &fdr_neg),
+        auprc_fdr: auprc(&fdr_pos, &fdr_neg),
+        nnmd: nnmd(&lfc_essential, &lfc_nonessential),
+        fdr,
+        essential_recall: n_recovered as f64 / essential.len() as f64,
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_auroc() {
+        assert_eq!(auroc(&[3., 4.], &[1., 2.]), 1.);
+        assert_eq!(auroc(&[1., 2.], &[3., 4.]), 0.);
+        assert_eq!(auroc(&[1., 3.], &[2., 4.]), 0.25);
+        assert_eq!(auroc(&[1.], &[1.]), 0.5);
+    }
+
+    #[test]
+    fn test_auprc() {
+        assert_eq!(auprc(&[3., 4.], &[1., 2.]), 1.);
+
+        // ranking: pos, neg, pos, neg
+        let ap = auprc(&[4., 2.], &[3., 1.]);
+        assert!((ap - (1. + 2. / 3.) / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_nnmd() {
+        let value = nnmd(&[-3., -3.], &[-1., 0., 1.]);
+        assert!((value + 3.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_screen_performance() -> Result<()> {
+        let to_strings = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let results = AggregationResult::builder()
+            .genes(to_strings(&["e1", "e2", "n1", "n2", "other"]))
+            .gene_fc(array![0.25, 0.5, 1.0, 1.1, 0.1])
+            .pvalues_low(array![0.01, 0.02, 0.6, 0.7, 0.01])
+            .pvalues_high(array![0.99, 0.98, 0.4, 0.3, 0.99])
+            .fdr_low(array![0.02, 0.2, 0.7, 0.7, 0.02])
+            .fdr_high(array![1., 1., 0.7, 0.7, 1.])
+            .aggregation_score_low(array![0.01, 0.02, 0.6, 0.7, 0.01])
+            .aggregation_score_high(array![0.99, 0.98, 0.4, 0.3, 0.99])
+            .build();
+        let sets = ReferenceSets::new(to_strings(&["e1", "e2"]), to_strings(&["n1", "n2"]))?;
+        let performance = screen_performance(&results, &sets, 0.1)?;
+        assert_eq!(performance.auroc_log2fc(), 1.);
+        assert_eq!(performance.auprc_log2fc(), 1.);
+        assert_eq!(performance.essential_recall(), 0.5);
+        assert!(performance.nnmd() < 0.);
+
+        let missing = ReferenceSets::new(to_strings(&["x"]), to_strings(&["n1", "n2"]))?;
+        assert!(screen_performance(&results, &missing, 0.1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_sets() {
+        let to_strings = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert!(ReferenceSets::new(to_strings(&["a", "b"]), to_strings(&["c"])).is_ok());
+        assert!(ReferenceSets::new(to_strings(&["a", "b"]), to_strings(&["b"])).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use adjustp::{adjust, Procedure};
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use ndarray::Array1;
+use rand::{seq::index::sample, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::utils::group_indices;
+use crate::{io::is_non_targeting, norm::quantile};
+
+/// Second-best sgRNA scores, median log fold changes, and NTC-derived p-values of every gene
+#[derive(Debug)]
+pub struct SecondBestResult {
+    genes: Vec<String>,
+    second_low: Array1<f64>,
+    second_high: Array1<f64>,
+    median_logfc: Array1<f64>,
+    pvalues_low: Array1<f64>,
+    pvalues_high: Array1<f64>,
+    fdr_low: Array1<f64>,
+    fdr_high: Array1<f64>,
+}
+impl SecondBestResult {
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    /// The second-lowest sgRNA log fold change of each gene
+    pub fn second_low(&self) -> &Array1<f64> {
+        &self.second_low
+    }
+
+    /// The second-highest sgRNA log fold change of each gene
+    pub fn second_high(&self) -> &Array1<f64> {
+        &self.second_high
+    }
+
+    /// The median sgRNA log fold change of each gene
+    pub fn median_logfc(&self) -> &Array1<f64> {
+        &self.median_logfc
+    }
+
+    pub fn pvalues_low(&self) -> &Array1<f64> {
+        &self.pvalues_low
+    }
+
+    pub fn pvalues_high(&self) -> &Array1<f64> {
+        &self.pvalues_high
+    }
+
+    pub fn fdr_low(&self) -> &Array1<f64> {
+        &self.fdr_low
+    }
+
+    pub fn fdr_high(&self) -> &Array1<f64> {
+        &self.fdr_high
+    }
+}
+
+/// Returns the second-lowest and second-highest values of a sorted slice
+///
+/// Groups with a single value return that value on both sides.
+fn second_best(sorted: &[f64]) -> (f64, f64) {
+    let n = sorted.len();
+    let rank = 1.min(n - 1);
+    (sorted[rank], sorted[n - 1 - rank])
+}
+
+/// Draws the second-lowest and second-highest values of random NTC groups of a given size
+///
+/// Groups are drawn without replacement unless they are larger than the number of NTCs. The
+/// returned null distributions are sorted.
+fn null_distribution(
+    ntc_logfc: &[f64],
+    size: usize,
+    n_draws: usize,
+    rng: &mut ChaCha8Rng,
+) -> (Vec<f64>, Vec<f64>) {
+    let mut null_low = Vec::with_capacity(n_draws);
+    let mut null_high = Vec::with_capacity(n_draws);
+    let mut group = Vec::with_capacity(size);
+    for _ in 0..n_draws {
+        group.clear();
+        if size <= ntc_logfc.len() {
+            group.extend(
+                sample(rng, ntc_logfc.len(), size)
+                    .into_iter()
+                    .map(|idx| ntc_logfc[idx]),
+            );
+        } else {
+            group.extend((0..size).map(|_| ntc_logfc[rng.gen_range(0..ntc_logfc.len())]));
+        }
+        group.sort_by(|a, b| a.total_cmp(b));
+        let (low, high) = second_best(&group);
+        null_low.push(low);
+        null_high.push(high);
+    }
+    null_low.sort_by(|a, b| a.total_cmp(b));
+    null_high.sort_by(|a, b| a.total_cmp(b));
+    (null_low, null_high)
+}
+
+/// Empirical p-value of a score against a sorted null with a pseudocount
+///
+/// Depletions count the null values at or below the score and enrichments the null values
+/// at or above it.
+fn empirical_pvalue(sorted_null: &[f64], score: f64, low: bool) -> f64 {
+    let count = if low {
+        sorted_null.partition_point(|x| *x <= score)
+    } else {
+        sorted_null.len() - sorted_null.partition_point(|x| *x < score)
+    };
+    (count + 1) as f64 / (sorted_null.len() + 1) as f64
+}
+
+/// Scores every gene by its second-best sgRNA and its median sgRNA log fold change
+///
+/// The non-targeting control sgRNAs (sgRNA names containing the token) are the null: for every
+/// gene size, `n_draws` random groups of NTCs of that size are scored the same way, and the
+/// p-values are the fraction of groups at least as extreme as the gene. NTCs are not
+/// counted towards their genes.
+pub fn second_best_aggregation(
+    logfc: &Array1<f64>,
+    gene_names: &[String],
+    sgrna_names: &[String],
+    token: &str,
+    n_draws: usize,
+    correction: Procedure,
+    seed: u64,
+) -> Result<SecondBestResult> {
+    if logfc.len() != gene_names.len() || logfc.len() != sgrna_names.len() {
+        bail!("The log fold changes, gene names, and sgRNA names must describe the same sgRNAs")
+    }
+    if token.is_empty() {
+        bail!("The second-best null requires a non-empty Non-Targeting Token")
+    }
+    if n_draws == 0 {
+        bail!("The second-best null requires at least one draw")
+    }
+
+    let ntc = sgrna_names
+        .iter()
+        .map(|sgrna| is_non_targeting(sgrna, token))
+        .collect::<Vec<_>>();
+    let ntc_logfc = (0..logfc.len())
+        .filter(|idx| ntc[*idx])
+        .map(|idx| logfc[idx])
+        .collect::<Vec<_>>();
+    if ntc_logfc.is_empty() {
+        bail!("Non-Targeting Token ({token}) not found in any sgrna names - unable to build the second-best null")
+    }
+
+    let (all_genes, all_sgrnas) = group_indices(gene_names);
+    let gene_groups = all_genes
+        .into_iter()
+        .zip(all_sgrnas)
+        .map(|(gene, sgrnas)| {
+            let targeting = sgrnas
+                .into_iter()
+                .filter(|idx| !ntc[*idx])
+                .collect::<Vec<_>>();
+            (gene, targeting)
+        })
+        .filter(|(_, sgrnas)| !sgrnas.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut sizes = gene_groups
+        .iter()
+        .map(|(_, sgrnas)| sgrnas.len())
+        .collect::<Vec<_>>();
+    sizes.sort_unstable();
+    sizes.dedup();
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let nulls = sizes
+        .into_iter()
+        .map(|size| (size, null_distribution(&ntc_logfc, size, n_draws, &mut rng)))
+        .collect::<HashMap<_, _>>();
+
+    let n_genes = gene_groups.len();
+    let mut genes = Vec::with_capacity(n_genes);
+    let mut second_low = Array1::zeros(n_genes);
+    let mut second_high = Array1::zeros(n_genes);
+    let mut median_logfc = Array1::zeros(n_genes);
+    let mut pvalues_low = Array1::zeros(n_genes);
+    let mut pvalues_high = Array1::zeros(n_genes);
+    for (idx, (gene, sgrnas)) in gene_groups.into_iter().enumerate() {
+        let mut values = sgrnas.iter().map(|x| logfc[*x]).collect::<Vec<_>>();
+        values.sort_by(|a, b| a.total_cmp(b));
+        let (low, high) = second_best(&values);
+        let (null_low, null_high) = &nulls[&values.len()];
+
+        genes.push(gene);
+        second_low[idx] = low;
+        second_high[idx] = high;
+        median_logfc[idx] = quantile(&values, 0.5);
+        pvalues_low[idx] = empirical_pvalue(null_low, low, true);
+        pvalues_high[idx] = empirical_pvalue(null_high, high, false);
+    }
+    let fdr_low = Array1::from(adjust(pvalues_low.as_slice().unwrap(), correction));
+    let fdr_high = Array1::from(adjust(pvalues_high.as_slice().unwrap(), correction));
+
+    Ok(SecondBestResult {
+        genes,
+        second_low,
+        second_high,
+        median_logfc,
+        pvalues_low,
+        pvalues_high,
+        fdr_low,
+        fdr_high,
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_second_best() {
+        assert_eq!(second_best(&[-3., -1., 0., 2.]), (-1., 0.));
+        assert_eq!(second_best(&[1.5]), (1.5, 1.5));
+    }
+
+    #[test]
+    fn test_empirical_pvalue() {
+        let null = [-2., -1., 0., 1., 2.];
+        assert_eq!(empirical_pvalue(&null, -1., true), 3. / 6.);
+        assert_eq!(empirical_pvalue(&null, -5., true), 1. / 6.);
+        assert_eq!(empirical_pvalue(&null, 1., false), 3. / 6.);
+        assert_eq!(empirical_pvalue(&null, 5., false), 1. / 6.);
+    }
+
+    #[test]
+    fn test_second_best_aggregation() -> Result<()> {
+        let mut gene_names = ["a", "a", "a", "b", "b", "b", "c", "c", "c"]
+            .map(|x| x.to_string())
+            .to_vec();
+        let mut logfc = vec![-3.0, -2.5, -2.8, -4.0, 0.1, -0.1, 2.5, 3.0, 2.8];
+        let mut sgrna_names = gene_names
+            .iter()
+            .enumerate()
+            .map(|(idx, gene)| format!("{gene}_{idx}"))
+            .collect::<Vec<_>>();
+        for idx in 0..50 {
+            // NTCs are recognized by their sgRNA names regardless of their gene names
+            gene_names.push(format!("control_{}", idx % 5));
+            sgrna_names.push(format!("non-targeting_{idx}"));
+            logfc.push((idx as f64 - 25.) / 50.);
+        }
+        let logfc = Array1::from(logfc);
+        let results = second_best_aggregation(
+            &logfc,
+            &gene_names,
+            &sgrna_names,
+            "non-targeting",
+            1000,
+            Procedure::BenjaminiHochberg,
+            0,
+        )?;
+        assert_eq!(results.genes(), &["a", "b", "c"]);
+        assert_eq!(results.second_low(), &array![-2.8, -0.1, 2.8]);
+        assert_eq!(results.median_logfc(), &array![-2.8, -0.1, 2.8]);
+
+        // a single outlier sgRNA does not make a hit
+        assert!(results.pvalues_low()[0] < 0.01);
+        assert!(results.pvalues_low()[1] > 0.1);
+        assert!(results.pvalues_high()[2] < 0.01);
+
+        for token in ["missing", ""] {
+            assert!(second_best_aggregation(
+                &logfc,
+                &gene_names,
+                &sgrna_names,
+                token,
+                1000,
+                Procedure::BenjaminiHochberg,
+                0
+            )
+            .is_err());
+        }
+        Ok(())
+    }
+}
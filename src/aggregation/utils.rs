@@ -1,5 +1,5 @@
 use crate::utils::logging::Logger;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use ndarray::Array1;
 
 /// Return all indices where values are above zero
@@ -14,6 +14,32 @@ pub fn mask_zeros(array: &Array1<f64>, logger: &Logger) -> HashSet<usize> {
     mask
 }
 
+/// Return the sorted indices where values are above zero without logging
+pub fn nonzero_indices(array: &Array1<f64>) -> Vec<usize> {
+    array
+        .iter()
+        .enumerate()
+        .filter(|(_idx, x)| **x > 0.)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Groups the sgRNA indices of every gene in order of first appearance
+pub fn group_indices(gene_names: &[String]) -> (Vec<String>, Vec<Vec<usize>>) {
+    let mut genes: Vec<String> = Vec::new();
+    let mut indices: Vec<Vec<usize>> = Vec::new();
+    let mut positions = HashMap::new();
+    for (idx, gene) in gene_names.iter().enumerate() {
+        let position = *positions.entry(gene.as_str()).or_insert_with(|| {
+            genes.push(gene.clone());
+            indices.push(Vec::new());
+            genes.len() - 1
+        });
+        indices[position].push(idx);
+    }
+    (genes, indices)
+}
+
 /// Select from vector where indices are in the mask
 pub fn select_from_mask<T: Clone>(array: &[T], mask: &[usize]) -> Vec<T> {
     mask.iter().map(|x| array[*x].clone()).collect::<Vec<T>>()
@@ -84,7 +110,7 @@ pub fn num_unique(names: &[String]) -> usize {
 
 #[cfg(test)]
 mod testing {
-    use super::{calculate_empirical_alpha, filter_zeros, mask_zeros};
+    use super::{calculate_empirical_alpha, filter_zeros, group_indices, mask_zeros};
     use crate::{
         aggregation::utils::{select_from_mask, select_from_mask_array, set_alpha_threshold},
         utils::logging::Logger,
@@ -192,6 +218,14 @@ mod testing {
         assert_eq!(ppf.len(), nonzero.len());
     }
 
+    #[test]
+    fn test_group_indices() {
+        let names = ["b", "a", "b", "c", "a"].map(|x| x.to_string());
+        let (genes, indices) = group_indices(&names);
+        assert_eq!(genes, vec!["b", "a", "c"]);
+        assert_eq!(indices, vec![vec![0, 2], vec![1, 4], vec![3]]);
+    }
+
     #[test]
     fn test_set_alpha_threshold() {
         let alpha = 0.25;
@@ -1,6 +1,7 @@
 use crate::{
-    aggregation::{GeneAggregationSelection, GeoPAGGWeightConfigEnum},
-    enrich::TestStrategy,
+    aggregation::{GeneAggregationSelection, GeoPAGGWeightConfigEnum, StoufferWeights},
+    enrich::{GlmTest, NtcCalibration, TestStrategy},
+    io::OutputFormat,
     model::ModelChoice,
     norm::Normalization,
     utils::Adjustment,
@@ -32,16 +33,52 @@ pub struct Cli {
 #[clap(next_help_heading = "Input Arguments")]
 pub struct InputArgs {
     /// Filepath of the input count matrix
+    ///
+    /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+    /// Use `-` to read from stdin.
     #[arg(short, long)]
     pub input: String,
 
     /// Labels for Control Samples
-    #[arg(short, long, num_args=1.., required=true)]
+    ///
+    /// Interpreted as regular expressions over the count matrix headers, or as
+    /// condition names if a design file is provided.
+    #[arg(short, long, num_args=1.., required_unless_present = "contrasts")]
     pub controls: Vec<String>,
 
     /// Labels for Treatment Samples
-    #[arg(short, long, num_args=1.., required=true)]
+    ///
+    /// Interpreted as regular expressions over the count matrix headers, or as
+    /// condition names if a design file is provided.
+    #[arg(short, long, num_args=1.., required_unless_present = "contrasts")]
     pub treatments: Vec<String>,
+
+    /// Filepath of a design file (sample sheet)
+    ///
+    /// Requires a `sample` and `condition` column, optionally a `replicate`
+    /// column, and treats all other columns as covariates.
+    #[arg(short, long)]
+    pub design: Option<String>,
+
+    /// Filepath of a contrasts file to run multiple comparisons at once
+    ///
+    /// Requires a `contrast`, `control`, and `treatment` column where groups may list
+    /// multiple comma-separated labels. Results of each contrast are written to
+    /// `<prefix>.<contrast>.*`.
+    #[arg(long, conflicts_with_all = ["controls", "treatments"])]
+    pub contrasts: Option<String>,
+
+    /// Column name of the sgRNA identifiers in the count matrix
+    ///
+    /// [default: first column]
+    #[arg(long)]
+    pub sgrna_col: Option<String>,
+
+    /// Column name of the gene identifiers in the count matrix
+    ///
+    /// [default: second column]
+    #[arg(long)]
+    pub gene_col: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -49,11 +86,25 @@ pub struct InputArgs {
 pub struct DiffAbundanceArgs {
     /// Count normalization configuration
     ///
-    /// If high numbers of zeros are encountered the normalization
-    /// method will default to `total` normalization.
+    /// If every sgRNA has a zero in some sample the median-ratio
+    /// method will fall back to `poscounts` normalization.
     #[arg(short, long, default_value = "median-ratio")]
     pub norm: Normalization,
 
+    /// Filepath of a table of per-sample size factors (`sample`, `factor`)
+    ///
+    /// Bypasses normalization and divides the counts of each sample by its factor. The
+    /// `<prefix>.size_factors.tsv` written by a previous run can be provided as is.
+    #[arg(long)]
+    pub size_factors: Option<String>,
+
+    /// Exit with an error instead of falling back from median-ratio normalization
+    ///
+    /// By default median-ratio falls back to `poscounts` and then `total` normalization
+    /// with a warning if no sgRNAs are free of zeros.
+    #[arg(long)]
+    pub strict_norm: bool,
+
     /// Least squares model choice
     #[arg(short, long, default_value = "wols")]
     pub model_choice: ModelChoice,
@@ -65,6 +116,26 @@ pub struct DiffAbundanceArgs {
     /// Sample testing strategy
     #[arg(short = 'S', long, default_value = "cm")]
     pub strategy: TestStrategy,
+
+    /// Design file covariates to include in the GLM (only used if strategy is glm)
+    #[arg(long, num_args=1..)]
+    pub covariates: Vec<String>,
+
+    /// Hypothesis test of the GLM coefficients (only used if strategy is glm)
+    #[arg(long, default_value = "wald")]
+    pub glm_test: GlmTest,
+
+    /// Calibrate the sgRNA p-values against the non-targeting controls (matched by `--ntc-token`)
+    ///
+    /// The uncalibrated p-values are reported as `pvalue_low_raw` and `pvalue_high_raw`.
+    #[arg(long, default_value = "none")]
+    pub ntc_calibration: NtcCalibration,
+
+    /// Filepath of a list of control sgRNA names (one per line) for control normalization
+    ///
+    /// [default: sgRNAs matching `--ntc-token`]
+    #[arg(long)]
+    pub control_sgrnas: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -123,6 +194,62 @@ pub struct GeopaggArgs {
     pub zscore_threshold: Option<f64>,
 }
 
+#[derive(Parser, Debug)]
+#[clap(next_help_heading = "BAGEL Arguments")]
+pub struct BagelArgs {
+    /// Number of bootstrap iterations over the training genes
+    #[arg(long, default_value = "100")]
+    pub bagel_bootstraps: usize,
+}
+
+#[derive(Parser, Debug)]
+#[clap(next_help_heading = "drugZ Arguments")]
+pub struct DrugzArgs {
+    /// Number of sgRNAs on either side of an sgRNA (by control abundance) used to estimate
+    /// its fold change variance
+    #[arg(long, default_value = "500")]
+    pub drugz_half_window: usize,
+}
+
+#[derive(Parser, Debug)]
+#[clap(next_help_heading = "Second-Best Arguments")]
+pub struct SecondBestArgs {
+    /// Number of random non-targeting groups drawn for each gene size
+    #[arg(long, default_value = "10000")]
+    pub second_best_draws: usize,
+}
+
+#[derive(Parser, Debug)]
+#[clap(next_help_heading = "Stouffer Arguments")]
+pub struct StoufferArgs {
+    /// sgRNA weights of Stouffer's method
+    #[arg(long, default_value = "base-mean")]
+    pub stouffer_weights: StoufferWeights,
+
+    /// Filepath of the sgRNA efficacy scores (a table with columns `sgrna` and `efficacy`)
+    ///
+    /// Only used by Stouffer's method with efficacy weights
+    #[arg(long, required_if_eq("stouffer_weights", "efficacy"))]
+    pub sgrna_efficacy: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[clap(next_help_heading = "Sample Structure Arguments")]
+pub struct SampleStructureArgs {
+    /// Write the PCA and hierarchical clustering of the samples on their log2 normalized counts
+    ///
+    /// Written to <prefix>.sample_pca.tsv, <prefix>.sample_pca_variance.tsv,
+    /// <prefix>.sample_distances.tsv, and <prefix>.sample_clustering.tsv
+    #[arg(long)]
+    pub sample_pca: bool,
+
+    /// Warn about samples that are closer to another group than to their own group
+    ///
+    /// The outlier samples are written to <prefix>.sample_outliers.tsv
+    #[arg(long)]
+    pub warn_outliers: bool,
+}
+
 #[derive(Parser, Debug)]
 #[clap(next_help_heading = "Miscellaneous Arguments")]
 pub struct MiscArgs {
@@ -149,6 +276,27 @@ pub struct MiscArgs {
     /// Number of threads to use (defaults to all available)
     #[arg(short = 'T', long)]
     pub threads: Option<usize>,
+
+    /// File format of the sgRNA, gene, and hit results
+    #[arg(long, default_value = "tsv")]
+    pub output_format: OutputFormat,
+
+    /// Additionally write the gene results with MAGeCK `gene_summary.txt` columns
+    ///
+    /// Written to <prefix>.gene_summary.txt
+    #[arg(long)]
+    pub mageck_output: bool,
+
+    /// Filepath of a list of core-essential genes (one per line)
+    ///
+    /// Together with `--nonessential-genes` the separation of the reference sets is
+    /// written to <prefix>.performance.tsv. These are the training genes of BAGEL.
+    #[arg(long, requires = "nonessential_genes", required_if_eq("agg", "bagel"))]
+    pub essential_genes: Option<String>,
+
+    /// Filepath of a list of non-essential genes (one per line)
+    #[arg(long, requires = "essential_genes")]
+    pub nonessential_genes: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -202,8 +350,12 @@ pub enum Commands {
         diff_args: DiffAbundanceArgs,
 
         /// Gene aggregation configuration
-        #[arg(short = 'g', long, default_value = "rra")]
-        agg: GeneAggregationSelection,
+        ///
+        /// Multiple comma-separated methods are combined in a consensus written to
+        /// <prefix>.consensus.tsv and <prefix>.consensus_agreement.tsv; the first method
+        /// writes the gene results and hits
+        #[arg(short = 'g', long, default_value = "rra", value_delimiter = ',')]
+        agg: Vec<GeneAggregationSelection>,
 
         /// RRA arguments
         #[clap(flatten)]
@@ -217,6 +369,259 @@ pub enum Commands {
         #[clap(flatten)]
         geopagg: GeopaggArgs,
 
+        /// BAGEL arguments
+        #[clap(flatten)]
+        bagel: BagelArgs,
+
+        /// drugZ arguments
+        #[clap(flatten)]
+        drugz: DrugzArgs,
+
+        /// Stouffer arguments
+        #[clap(flatten)]
+        stouffer: StoufferArgs,
+
+        /// Second-best arguments
+        #[clap(flatten)]
+        second_best: SecondBestArgs,
+
+        /// Sample structure arguments
+        #[clap(flatten)]
+        structure: SampleStructureArgs,
+
+        /// Misc arguments
+        #[clap(flatten)]
+        misc: MiscArgs,
+
+        /// Skip performing gene aggregation
+        #[clap(long)]
+        skip_agg: bool,
+    },
+
+    /// Perform a time-course analysis of sgRNA trajectories across multiple timepoints
+    Timecourse {
+        /// Filepath of the input count matrix
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
+        #[arg(short, long)]
+        input: String,
+
+        /// Filepath of a design file (sample sheet)
+        #[arg(short, long)]
+        design: String,
+
+        /// Numeric design file column of sample times (e.g. days or population doublings)
+        #[arg(long, default_value = "time")]
+        time_column: String,
+
+        /// Design conditions to include in the time-course
+        ///
+        /// [default: all samples of the design file]
+        #[arg(short, long, num_args=1..)]
+        conditions: Vec<String>,
+
+        /// Output filename prefix
+        ///
+        /// sgRNA results will be written to <prefix>.sgrna_results.tsv
+        ///
+        /// gene results will be written to <prefix>.gene_results.tsv
+        ///
+        /// hits will be written to <prefix>.hits.tsv
+        #[arg(short = 'o', long, default_value = "./results")]
+        prefix: String,
+
+        /// Count normalization configuration
+        #[arg(short, long, default_value = "median-ratio")]
+        norm: Normalization,
+
+        /// Filepath of a table of per-sample size factors (`sample`, `factor`)
+        ///
+        /// Bypasses normalization and divides the counts of each sample by its factor.
+        #[arg(long)]
+        size_factors: Option<String>,
+
+        /// Exit with an error instead of falling back from median-ratio normalization
+        #[arg(long)]
+        strict_norm: bool,
+
+        /// Filepath of a list of control sgRNA names (one per line) for control normalization
+        ///
+        /// [default: sgRNAs matching `--ntc-token`]
+        #[arg(long)]
+        control_sgrnas: Option<String>,
+
+        /// Least squares model choice
+        #[arg(short, long, default_value = "wols")]
+        model_choice: ModelChoice,
+
+        /// Minimum Base Mean of the earliest timepoint to consider
+        #[arg(short = 'M', long, default_value = "100")]
+        min_base_mean: f64,
+
+        /// Gene aggregation configuration
+        ///
+        /// Multiple comma-separated methods are combined in a consensus written to
+        /// <prefix>.consensus.tsv and <prefix>.consensus_agreement.tsv; the first method
+        /// writes the gene results and hits
+        #[arg(short = 'g', long, default_value = "rra", value_delimiter = ',')]
+        agg: Vec<GeneAggregationSelection>,
+
+        /// RRA arguments
+        #[clap(flatten)]
+        rra: RraArgs,
+
+        /// INC arguments
+        #[clap(flatten)]
+        inc: IncArgs,
+
+        /// GeoPAGG arguments
+        #[clap(flatten)]
+        geopagg: GeopaggArgs,
+
+        /// BAGEL arguments
+        #[clap(flatten)]
+        bagel: BagelArgs,
+
+        /// drugZ arguments
+        #[clap(flatten)]
+        drugz: DrugzArgs,
+
+        /// Stouffer arguments
+        #[clap(flatten)]
+        stouffer: StoufferArgs,
+
+        /// Second-best arguments
+        #[clap(flatten)]
+        second_best: SecondBestArgs,
+
+        /// Sample structure arguments
+        #[clap(flatten)]
+        structure: SampleStructureArgs,
+
+        /// Misc arguments
+        #[clap(flatten)]
+        misc: MiscArgs,
+
+        /// Skip performing gene aggregation
+        #[clap(long)]
+        skip_agg: bool,
+    },
+
+    /// Perform an interaction (difference-of-differences) analysis between two screen arms
+    ///
+    /// Tests whether the effect of the query arm (e.g. drug vs T0) differs from the effect
+    /// of the reference arm (e.g. DMSO vs T0).
+    Interaction {
+        /// Filepath of the input count matrix
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
+        #[arg(short, long)]
+        input: String,
+
+        /// Filepath of a design file (sample sheet)
+        ///
+        /// If provided all sample labels are interpreted as condition names.
+        #[arg(short, long)]
+        design: Option<String>,
+
+        /// Labels for the control samples shared by both arms (e.g. T0)
+        #[arg(short, long, num_args=1.., required = true)]
+        controls: Vec<String>,
+
+        /// Labels for the reference arm samples (e.g. DMSO)
+        #[arg(short, long, num_args=1.., required = true)]
+        reference: Vec<String>,
+
+        /// Labels for the query arm samples (e.g. drug)
+        #[arg(short = 'Q', long, num_args=1.., required = true)]
+        query: Vec<String>,
+
+        /// Labels for separate control samples of the query arm
+        ///
+        /// [default: the shared control samples]
+        #[arg(long, num_args=1..)]
+        query_controls: Option<Vec<String>>,
+
+        /// Output filename prefix
+        ///
+        /// sgRNA results will be written to <prefix>.sgrna_results.tsv
+        ///
+        /// gene results will be written to <prefix>.gene_results.tsv
+        ///
+        /// hits will be written to <prefix>.hits.tsv
+        #[arg(short = 'o', long, default_value = "./results")]
+        prefix: String,
+
+        /// Count normalization configuration
+        #[arg(short, long, default_value = "median-ratio")]
+        norm: Normalization,
+
+        /// Filepath of a table of per-sample size factors (`sample`, `factor`)
+        ///
+        /// Bypasses normalization and divides the counts of each sample by its factor.
+        #[arg(long)]
+        size_factors: Option<String>,
+
+        /// Exit with an error instead of falling back from median-ratio normalization
+        #[arg(long)]
+        strict_norm: bool,
+
+        /// Filepath of a list of control sgRNA names (one per line) for control normalization
+        ///
+        /// [default: sgRNAs matching `--ntc-token`]
+        #[arg(long)]
+        control_sgrnas: Option<String>,
+
+        /// Least squares model choice
+        #[arg(short, long, default_value = "wols")]
+        model_choice: ModelChoice,
+
+        /// Minimum Base Mean of the control samples to consider
+        #[arg(short = 'M', long, default_value = "100")]
+        min_base_mean: f64,
+
+        /// Gene aggregation configuration
+        ///
+        /// Multiple comma-separated methods are combined in a consensus written to
+        /// <prefix>.consensus.tsv and <prefix>.consensus_agreement.tsv; the first method
+        /// writes the gene results and hits
+        #[arg(short = 'g', long, default_value = "rra", value_delimiter = ',')]
+        agg: Vec<GeneAggregationSelection>,
+
+        /// RRA arguments
+        #[clap(flatten)]
+        rra: RraArgs,
+
+        /// INC arguments
+        #[clap(flatten)]
+        inc: IncArgs,
+
+        /// GeoPAGG arguments
+        #[clap(flatten)]
+        geopagg: GeopaggArgs,
+
+        /// BAGEL arguments
+        #[clap(flatten)]
+        bagel: BagelArgs,
+
+        /// drugZ arguments
+        #[clap(flatten)]
+        drugz: DrugzArgs,
+
+        /// Stouffer arguments
+        #[clap(flatten)]
+        stouffer: StoufferArgs,
+
+        /// Second-best arguments
+        #[clap(flatten)]
+        second_best: SecondBestArgs,
+
+        /// Sample structure arguments
+        #[clap(flatten)]
+        structure: SampleStructureArgs,
+
         /// Misc arguments
         #[clap(flatten)]
         misc: MiscArgs,
@@ -229,6 +634,9 @@ pub enum Commands {
     /// Perform just the gene aggregation given sgRNA results
     Agg {
         /// Filepath of the input sgRNA results
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
         #[clap(short, long)]
         input: String,
 
@@ -241,8 +649,12 @@ pub enum Commands {
         prefix: String,
 
         /// Gene aggregation configuration
-        #[arg(short = 'g', long, default_value = "rra")]
-        agg: GeneAggregationSelection,
+        ///
+        /// Multiple comma-separated methods are combined in a consensus written to
+        /// <prefix>.consensus.tsv and <prefix>.consensus_agreement.tsv; the first method
+        /// writes the gene results and hits
+        #[arg(short = 'g', long, default_value = "rra", value_delimiter = ',')]
+        agg: Vec<GeneAggregationSelection>,
 
         /// RRA arguments
         #[clap(flatten)]
@@ -256,6 +668,22 @@ pub enum Commands {
         #[clap(flatten)]
         geopagg: GeopaggArgs,
 
+        /// BAGEL arguments
+        #[clap(flatten)]
+        bagel: BagelArgs,
+
+        /// drugZ arguments
+        #[clap(flatten)]
+        drugz: DrugzArgs,
+
+        /// Stouffer arguments
+        #[clap(flatten)]
+        stouffer: StoufferArgs,
+
+        /// Second-best arguments
+        #[clap(flatten)]
+        second_best: SecondBestArgs,
+
         /// Misc arguments
         #[clap(flatten)]
         misc: MiscArgs,
@@ -268,6 +696,9 @@ pub enum Commands {
     /// Resample the input count matrix with various parameterizations
     Resample {
         /// Filepath of the input count matrix
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
         #[clap(short, long)]
         input: String,
 
@@ -301,6 +732,156 @@ pub enum Commands {
         #[arg(short, long, num_args=1.., required = true)]
         samples: Vec<String>,
 
+        /// File format of the resampled count matrix
+        #[arg(long, default_value = "tsv")]
+        output_format: OutputFormat,
+
+        /// Quiet the logger
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Validate the input count matrix and report any problems
+    Validate {
+        /// Filepath of the input count matrix
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
+        #[arg(short, long)]
+        input: String,
+
+        /// Sample names whose counts should be validated
+        ///
+        /// [default: all columns other than the sgRNA and gene columns]
+        #[arg(short, long, num_args=1..)]
+        samples: Option<Vec<String>>,
+
+        /// Column name of the sgRNA identifiers in the count matrix
+        ///
+        /// [default: first column]
+        #[arg(long)]
+        sgrna_col: Option<String>,
+
+        /// Column name of the gene identifiers in the count matrix
+        ///
+        /// [default: second column]
+        #[arg(long)]
+        gene_col: Option<String>,
+
+        /// Non-targeting control token (an empty token skips the coverage check)
+        #[arg(long, default_value = "non-targeting")]
+        ntc_token: String,
+
+        /// Filepath to write the validation report
+        ///
+        /// [default: stdout]
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Quiet the logger
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Report library quality control metrics of the input count matrix
+    Qc {
+        /// Filepath of the input count matrix
+        ///
+        /// May be tab, comma, or whitespace delimited and gzip or zstd compressed.
+        /// Use `-` to read from stdin.
+        #[arg(short, long)]
+        input: String,
+
+        /// Filepath of a design file (sample sheet)
+        ///
+        /// Replicate correlations are only calculated between samples of the same
+        /// condition if provided.
+        #[arg(short, long)]
+        design: Option<String>,
+
+        /// Sample names to include
+        ///
+        /// [default: all design samples or all columns other than the sgRNA and gene columns]
+        #[arg(short, long, num_args=1..)]
+        samples: Option<Vec<String>>,
+
+        /// Column name of the sgRNA identifiers in the count matrix
+        ///
+        /// [default: first column]
+        #[arg(long)]
+        sgrna_col: Option<String>,
+
+        /// Column name of the gene identifiers in the count matrix
+        ///
+        /// [default: second column]
+        #[arg(long)]
+        gene_col: Option<String>,
+
+        /// Count normalization used for the replicate correlations
+        #[arg(short, long, default_value = "median-ratio")]
+        norm: Normalization,
+
+        /// Output filename prefix
+        ///
+        /// Sample metrics will be written to <prefix>.qc.tsv, replicate correlations to
+        /// <prefix>.qc_correlations.tsv, and a summary to <prefix>.qc.json
+        #[arg(short = 'o', long, default_value = "./results")]
+        prefix: String,
+
+        /// Quiet the logger
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Count sgRNA protospacers in FASTQ files against a library
+    Count {
+        /// Filepaths of the input FASTQ files
+        ///
+        /// May be gzip or zstd compressed. Use `-` to read from stdin.
+        #[arg(short, long, num_args=1.., required = true)]
+        input: Vec<String>,
+
+        /// Filepath of the sgRNA library
+        ///
+        /// Must contain the columns `sgrna`, `gene`, and `sequence`
+        #[arg(short, long)]
+        library: String,
+
+        /// Sample names of the FASTQ files in the same order as the inputs
+        ///
+        /// [default: the file names without their extensions]
+        #[arg(short, long, num_args=1..)]
+        samples: Option<Vec<String>>,
+
+        /// Output filename prefix
+        ///
+        /// Counts will be written to <prefix>.counts.tsv and mapping statistics to
+        /// <prefix>.count_stats.tsv
+        #[arg(short = 'o', long, default_value = "./results")]
+        prefix: String,
+
+        /// Offset of the protospacer from the start of each read
+        ///
+        /// [default: detected for each sample from the first reads]
+        #[arg(long)]
+        offset: Option<usize>,
+
+        /// Number of mismatches allowed against the library sequences
+        #[arg(short, long, default_value = "0", value_parser = clap::value_parser!(u8).range(0..=1))]
+        mismatches: u8,
+
+        /// Reverse complement the protospacer before matching against the library
+        #[arg(short, long)]
+        reverse_complement: bool,
+
+        /// File format of the count matrix
+        #[arg(long, default_value = "tsv")]
+        output_format: OutputFormat,
+
+        /// Number of threads to use (defaults to all available)
+        #[arg(short = 'T', long)]
+        threads: Option<usize>,
+
         /// Quiet the logger
         #[arg(short, long)]
         quiet: bool,
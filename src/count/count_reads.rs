@@ -0,0 +1,397 @@
+use anyhow::{anyhow, bail, Result};
+use bon::builder;
+use hashbrown::HashSet;
+use polars::prelude::*;
+use rayon::prelude::*;
+use std::path::Path;
+
+use super::{FastqReader, Library, LibraryMatch};
+use crate::{
+    io::{open_input, write_table, OutputFormat, STDIN_PATH},
+    utils::logging::Logger,
+};
+
+/// Number of reads at the start of each file used to detect the protospacer offset
+const OFFSET_DETECTION_READS: usize = 10_000;
+
+/// File suffixes removed from the FASTQ file names to build the default sample names
+const FASTQ_SUFFIXES: [&str; 4] = [".gz", ".zst", ".fastq", ".fq"];
+
+/// Read counts and mapping statistics of a single sample
+#[derive(Debug)]
+pub struct SampleCounts {
+    name: String,
+    offset: usize,
+    counts: Vec<i64>,
+    total_reads: usize,
+    exact_reads: usize,
+    mismatch_reads: usize,
+    ambiguous_reads: usize,
+}
+impl SampleCounts {
+    fn new(name: String, offset: usize, n_sgrnas: usize) -> Self {
+        Self {
+            name,
+            offset,
+            counts: vec![0; n_sgrnas],
+            total_reads: 0,
+            exact_reads: 0,
+            mismatch_reads: 0,
+            ambiguous_reads: 0,
+        }
+    }
+
+    fn add(&mut self, library_match: LibraryMatch) {
+        self.total_reads += 1;
+        match library_match {
+            LibraryMatch::Exact(idx) => {
+                self.counts[idx] += 1;
+                self.exact_reads += 1;
+            }
+            LibraryMatch::Mismatch(idx) => {
+                self.counts[idx] += 1;
+                self.mismatch_reads += 1;
+            }
+            LibraryMatch::Ambiguous => self.ambiguous_reads += 1,
+            LibraryMatch::Unmapped => {}
+        }
+    }
+
+    pub fn mapped_reads(&self) -> usize {
+        self.exact_reads + self.mismatch_reads
+    }
+
+    pub fn unmapped_reads(&self) -> usize {
+        self.total_reads - self.mapped_reads()
+    }
+
+    /// The fraction of reads assigned to an sgRNA
+    pub fn mapping_rate(&self) -> f64 {
+        if self.total_reads == 0 {
+            0.
+        } else {
+            self.mapped_reads() as f64 / self.total_reads as f64
+        }
+    }
+
+    /// The number of sgRNAs without any assigned reads
+    pub fn zero_count_sgrnas(&self) -> usize {
+        self.counts.iter().filter(|x| **x == 0).count()
+    }
+}
+
+fn complement(nt: u8) -> u8 {
+    match nt.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+/// Extracts the protospacer at the offset of a read into the buffer
+///
+/// The protospacer is reverse complemented if the reads are sequenced from the opposite
+/// strand of the library. Returns `None` if the read is too short.
+fn extract_protospacer<'a>(
+    read: &[u8],
+    offset: usize,
+    length: usize,
+    reverse_complement: bool,
+    buffer: &'a mut Vec<u8>,
+) -> Option<&'a [u8]> {
+    let window = read.get(offset..offset + length)?;
+    buffer.clear();
+    if reverse_complement {
+        buffer.extend(window.iter().rev().map(|nt| complement(*nt)));
+    } else {
+        buffer.extend(window.iter().map(u8::to_ascii_uppercase));
+    }
+    Some(buffer)
+}
+
+/// Detects the offset of the protospacer as the position with the most exact library
+/// matches across the reads
+fn detect_offset(reads: &[Vec<u8>], library: &Library, reverse_complement: bool) -> Option<usize> {
+    let length = library.length();
+    let max_length = reads.iter().map(|read| read.len()).max()?;
+    let mut votes = vec![0usize; (max_length + 1).saturating_sub(length)];
+    let mut buffer = Vec::with_capacity(length);
+    for read in reads {
+        for (offset, n_matches) in votes.iter_mut().enumerate() {
+            match extract_protospacer(read, offset, length, reverse_complement, &mut buffer) {
+                Some(protospacer) if library.contains(protospacer) => *n_matches += 1,
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+    votes
+        .iter()
+        .enumerate()
+        .filter(|(_, n_matches)| **n_matches > 0)
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+        .map(|(offset, _)| offset)
+}
+
+/// Counts the reads of a FASTQ file matching each sgRNA of the library
+///
+/// If no offset is provided it is detected from the first reads of the file, which are
+/// buffered so the file is only read once.
+fn count_sample(
+    path: &str,
+    name: String,
+    library: &Library,
+    offset: Option<usize>,
+    reverse_complement: bool,
+) -> Result<SampleCounts> {
+    let mut reader = FastqReader::new(open_input(Path::new(path))?);
+    let mut head = Vec::new();
+    let offset = match offset {
+        Some(offset) => offset,
+        None => {
+            while head.len() < OFFSET_DETECTION_READS {
+                match reader.next_sequence()? {
+                    Some(read) => head.push(read.to_vec()),
+                    None => break,
+                }
+            }
+            detect_offset(&head, library, reverse_complement).ok_or_else(|| {
+                anyhow!("Unable to detect the protospacer offset of {path} - no reads match the library exactly")
+            })?
+        }
+    };
+
+    let mut counts = SampleCounts::new(name, offset, library.len());
+    let mut buffer = Vec::with_capacity(library.length());
+    let mut assign = |read: &[u8], counts: &mut SampleCounts| {
+        let library_match = match extract_protospacer(
+            read,
+            offset,
+            library.length(),
+            reverse_complement,
+            &mut buffer,
+        ) {
+            Some(protospacer) => library.find(protospacer),
+            None => LibraryMatch::Unmapped,
+        };
+        counts.add(library_match);
+    };
+    for read in &head {
+        assign(read, &mut counts);
+    }
+    while let Some(read) = reader.next_sequence()? {
+        assign(read, &mut counts);
+    }
+    Ok(counts)
+}
+
+/// Builds the default sample name of a FASTQ file from its file name
+fn sample_name(path: &str) -> String {
+    let mut name = Path::new(path)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or(path);
+    for suffix in FASTQ_SUFFIXES {
+        name = name.strip_suffix(suffix).unwrap_or(name);
+    }
+    name.to_string()
+}
+
+/// Assigns the sample names of the FASTQ files and validates that they are unique
+fn sample_names(inputs: &[String], samples: Option<Vec<String>>) -> Result<Vec<String>> {
+    let names = match samples {
+        Some(samples) => {
+            if samples.len() != inputs.len() {
+                bail!(
+                    "Number of sample names ({}) does not match the number of FASTQ files ({})",
+                    samples.len(),
+                    inputs.len()
+                )
+            }
+            samples
+        }
+        None => {
+            if inputs.iter().any(|x| x == STDIN_PATH) {
+                bail!("Sample names must be provided when reading a FASTQ file from stdin")
+            }
+            inputs.iter().map(|x| sample_name(x)).collect()
+        }
+    };
+    validate_unique(&names)?;
+    Ok(names)
+}
+
+/// Ensures that every sample name is only used once
+fn validate_unique(names: &[String]) -> Result<()> {
+    let mut seen = HashSet::with_capacity(names.len());
+    for name in names {
+        if !seen.insert(name) {
+            bail!("Duplicate sample name found: {name}")
+        }
+    }
+    Ok(())
+}
+
+/// Builds the count matrix with the sgRNA and gene columns followed by the sample counts
+fn build_count_frame(library: &Library, samples: &[SampleCounts]) -> PolarsResult<DataFrame> {
+    let mut columns = vec![
+        Series::new("sgrna".into(), library.sgrnas()),
+        Series::new("gene".into(), library.genes()),
+    ];
+    columns.extend(
+        samples
+            .iter()
+            .map(|sample| Series::new(sample.name.as_str().into(), &sample.counts)),
+    );
+    DataFrame::new(columns)
+}
+
+/// Builds the table of the mapping statistics of each sample
+fn build_stats_frame(samples: &[SampleCounts]) -> PolarsResult<DataFrame> {
+    let collect = |f: fn(&SampleCounts) -> u64| samples.iter().map(f).collect::<Vec<_>>();
+    df!(
+        "sample" => samples.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+        "offset" => collect(|x| x.offset as u64),
+        "total_reads" => collect(|x| x.total_reads as u64),
+        "mapped_reads" => collect(|x| x.mapped_reads() as u64),
+        "exact_reads" => collect(|x| x.exact_reads as u64),
+        "mismatch_reads" => collect(|x| x.mismatch_reads as u64),
+        "ambiguous_reads" => collect(|x| x.ambiguous_reads as u64),
+        "unmapped_reads" => collect(|x| x.unmapped_reads() as u64),
+        "mapping_rate" => samples.iter().map(|x| x.mapping_rate()).collect::<Vec<_>>(),
+        "zero_count_sgrnas" => collect(|x| x.zero_count_sgrnas() as u64),
+    )
+}
+
+#[builder]
+pub fn count(
+    inputs: Vec<String>,
+    library: String,
+    samples: Option<Vec<String>>,
+    prefix: String,
+    offset: Option<usize>,
+    mismatches: u8,
+    reverse_complement: bool,
+    output_format: OutputFormat,
+    quiet: bool,
+) -> Result<()> {
+    let logger = Logger::from_quiet(quiet);
+    logger.start_counting();
+
+    let names = sample_names(&inputs, samples)?;
+    let library = Library::from_path(&library, mismatches)?;
+    logger.library_summary(library.len(), library.length(), mismatches);
+
+    let sample_counts = inputs
+        .par_iter()
+        .zip(names.into_par_iter())
+        .map(|(path, name)| count_sample(path, name, &library, offset, reverse_complement))
+        .collect::<Result<Vec<_>>>()?;
+    for sample in &sample_counts {
+        logger.sample_mapping(
+            &sample.name,
+            sample.offset,
+            sample.mapped_reads(),
+            sample.total_reads,
+        );
+    }
+
+    let mut count_frame = build_count_frame(&library, &sample_counts)?;
+    write_table(
+        &mut count_frame,
+        Some(format!("{prefix}.counts.{}", output_format.extension())),
+        output_format,
+    )?;
+    write_table(
+        &mut build_stats_frame(&sample_counts)?,
+        Some(format!("{prefix}.count_stats.tsv")),
+        OutputFormat::Tsv,
+    )
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    fn build_library(mismatches: u8) -> Result<Library> {
+        Library::new(
+            vec!["a_1".to_string(), "b_1".to_string()],
+            vec!["a".to_string(), "b".to_string()],
+            &["ACGTA".to_string(), "GGCCT".to_string()],
+            mismatches,
+        )
+    }
+
+    #[test]
+    fn test_extract_protospacer() {
+        let mut buffer = Vec::new();
+        let read = b"nnACGTAnn";
+        assert_eq!(
+            extract_protospacer(read, 2, 5, false, &mut buffer),
+            Some(b"ACGTA".as_slice())
+        );
+        assert_eq!(
+            extract_protospacer(read, 2, 5, true, &mut buffer),
+            Some(b"TACGT".as_slice())
+        );
+        assert_eq!(extract_protospacer(read, 5, 5, false, &mut buffer), None);
+    }
+
+    #[test]
+    fn test_detect_offset() -> Result<()> {
+        let library = build_library(0)?;
+        let reads = [
+            b"TTTACGTATT".to_vec(),
+            b"TTTGGCCTTT".to_vec(),
+            b"ACGTATTTTT".to_vec(),
+        ];
+        assert_eq!(detect_offset(&reads, &library, false), Some(3));
+        assert_eq!(detect_offset(&reads[2..], &library, false), Some(0));
+        assert_eq!(detect_offset(&[b"TTTTT".to_vec()], &library, false), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_names() -> Result<()> {
+        let inputs = vec!["dir/s1.fastq.gz".to_string(), "s2.fq".to_string()];
+        assert_eq!(sample_names(&inputs, None)?, vec!["s1", "s2"]);
+        assert!(sample_names(&inputs, Some(vec!["x".to_string()])).is_err());
+        assert!(sample_names(&inputs, Some(vec!["x".to_string(), "x".to_string()])).is_err());
+        assert!(sample_names(&[STDIN_PATH.to_string()], None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_sample() -> Result<()> {
+        let library = build_library(1)?;
+        let path = std::env::temp_dir().join(format!(
+            "crispr_screen_count_sample_{}.fastq",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "@r1\nTTACGTA\n+\nIIIIIII\n@r2\nTTACGTT\n+\nIIIIIII\n@r3\nTTGGCCT\n+\nIIIIIII\n@r4\nTTTTTTT\n+\nIIIIIII\n",
+        )?;
+        let counts = count_sample(
+            path.to_str().unwrap(),
+            "s1".to_string(),
+            &library,
+            None,
+            false,
+        );
+        std::fs::remove_file(&path)?;
+        let counts = counts?;
+        assert_eq!(counts.offset, 2);
+        assert_eq!(counts.counts, vec![2, 1]);
+        assert_eq!(counts.exact_reads, 2);
+        assert_eq!(counts.mismatch_reads, 1);
+        assert_eq!(counts.unmapped_reads(), 1);
+        assert_eq!(counts.mapping_rate(), 0.75);
+
+        let frame = build_count_frame(&library, &[counts])?;
+        assert_eq!(frame.shape(), (2, 3));
+        Ok(())
+    }
+}
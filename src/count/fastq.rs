@@ -0,0 +1,95 @@
+use anyhow::{bail, Result};
+use std::io::BufRead;
+
+/// Streaming reader of the sequences of a FASTQ file
+pub struct FastqReader<R: BufRead> {
+    reader: R,
+    header: Vec<u8>,
+    sequence: Vec<u8>,
+    separator: Vec<u8>,
+    quality: Vec<u8>,
+    n_records: usize,
+}
+impl<R: BufRead> FastqReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            header: Vec::new(),
+            sequence: Vec::new(),
+            separator: Vec::new(),
+            quality: Vec::new(),
+            n_records: 0,
+        }
+    }
+
+    /// Reads a single line into the buffer without its line ending and returns whether
+    /// anything was read
+    fn read_line(reader: &mut R, buffer: &mut Vec<u8>) -> Result<bool> {
+        buffer.clear();
+        if reader.read_until(b'\n', buffer)? == 0 {
+            return Ok(false);
+        }
+        while matches!(buffer.last(), Some(b'\n' | b'\r')) {
+            buffer.pop();
+        }
+        Ok(true)
+    }
+
+    /// Reads the next record and returns its sequence, or `None` at the end of the file
+    pub fn next_sequence(&mut self) -> Result<Option<&[u8]>> {
+        if !Self::read_line(&mut self.reader, &mut self.header)? {
+            return Ok(None);
+        }
+        self.n_records += 1;
+        if !self.header.starts_with(b"@") {
+            bail!(
+                "Malformed FASTQ record {} - header does not start with '@'",
+                self.n_records
+            )
+        }
+        if !Self::read_line(&mut self.reader, &mut self.sequence)?
+            || !Self::read_line(&mut self.reader, &mut self.separator)?
+            || !Self::read_line(&mut self.reader, &mut self.quality)?
+        {
+            bail!("Truncated FASTQ record {}", self.n_records)
+        }
+        if !self.separator.starts_with(b"+") {
+            bail!(
+                "Malformed FASTQ record {} - separator does not start with '+'",
+                self.n_records
+            )
+        }
+        if self.sequence.len() != self.quality.len() {
+            bail!(
+                "Malformed FASTQ record {} - sequence and quality lengths differ",
+                self.n_records
+            )
+        }
+        Ok(Some(&self.sequence))
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    #[test]
+    fn test_fastq_reader() -> Result<()> {
+        let contents = b"@r1\nACGT\n+\nIIII\r\n@r2\nTTGCA\n+r2\nIIIII\n";
+        let mut reader = FastqReader::new(contents.as_slice());
+        assert_eq!(reader.next_sequence()?, Some(b"ACGT".as_slice()));
+        assert_eq!(reader.next_sequence()?, Some(b"TTGCA".as_slice()));
+        assert_eq!(reader.next_sequence()?, None);
+
+        let truncated = b"@r1\nACGT\n+\n";
+        assert!(FastqReader::new(truncated.as_slice())
+            .next_sequence()
+            .is_err());
+
+        let malformed = b">r1\nACGT\n+\nIIII\n";
+        assert!(FastqReader::new(malformed.as_slice())
+            .next_sequence()
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{bail, Result};
+use hashbrown::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::io::{get_named_string_column, load_dataframe};
+
+/// Column name of the sgRNA identifiers in the library
+pub const LIBRARY_SGRNA_COLUMN: &str = "sgrna";
+
+/// Column name of the gene identifiers in the library
+pub const LIBRARY_GENE_COLUMN: &str = "gene";
+
+/// Column name of the protospacer sequences in the library
+pub const LIBRARY_SEQUENCE_COLUMN: &str = "sequence";
+
+/// Nucleotides substituted into a protospacer when searching for a single mismatch
+const NUCLEOTIDES: [u8; 4] = *b"ACGT";
+
+/// Outcome of matching a protospacer against the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryMatch {
+    /// The protospacer is identical to the sequence of the sgRNA
+    Exact(usize),
+
+    /// The protospacer differs from the sequence of the sgRNA at a single position
+    Mismatch(usize),
+
+    /// The protospacer is a single mismatch away from multiple sgRNAs
+    Ambiguous,
+
+    /// The protospacer does not match any sgRNA
+    Unmapped,
+}
+
+/// The sgRNA library with an index of the protospacer sequences
+#[derive(Debug)]
+pub struct Library {
+    sgrnas: Vec<String>,
+    genes: Vec<String>,
+    length: usize,
+    mismatches: u8,
+    index: HashMap<Vec<u8>, usize>,
+}
+impl Library {
+    /// Builds the library from the `sgrna`, `gene`, and `sequence` columns of a table
+    pub fn from_path(path: &str, mismatches: u8) -> Result<Self> {
+        let frame = load_dataframe(PathBuf::from(path))?;
+        let sgrnas = get_named_string_column(&frame, LIBRARY_SGRNA_COLUMN)?;
+        let genes = get_named_string_column(&frame, LIBRARY_GENE_COLUMN)?;
+        let sequences = get_named_string_column(&frame, LIBRARY_SEQUENCE_COLUMN)?;
+        Self::new(sgrnas, genes, &sequences, mismatches)
+    }
+
+    /// Builds the library and validates that all sequences are unique nucleotide strings of
+    /// the same length
+    pub fn new(
+        sgrnas: Vec<String>,
+        genes: Vec<String>,
+        sequences: &[String],
+        mismatches: u8,
+    ) -> Result<Self> {
+        let Some(first) = sequences.first() else {
+            bail!("The library does not contain any sgRNAs")
+        };
+        let length = first.len();
+
+        let mut names = HashSet::with_capacity(sgrnas.len());
+        let mut index = HashMap::with_capacity(sequences.len());
+        for (idx, (sgrna, sequence)) in sgrnas.iter().zip(sequences.iter()).enumerate() {
+            let sequence = sequence.to_ascii_uppercase().into_bytes();
+            if sequence.len() != length {
+                bail!(
+                    "Library sequences must all be the same length: {sgrna} has length {} but expected {length}",
+                    sequence.len()
+                )
+            }
+            if !sequence.iter().all(|nt| NUCLEOTIDES.contains(nt)) {
+                bail!("Library sequence of {sgrna} contains characters other than A, C, G, and T")
+            }
+            if !names.insert(sgrna.clone()) {
+                bail!("Duplicate sgRNA found in the library: {sgrna}")
+            }
+            if let Some(other) = index.insert(sequence, idx) {
+                bail!(
+                    "Duplicate sequence found in the library: {} and {sgrna}",
+                    sgrnas[other]
+                )
+            }
+        }
+
+        Ok(Self {
+            sgrnas,
+            genes,
+            length,
+            mismatches,
+            index,
+        })
+    }
+
+    /// Matches a protospacer against the library
+    ///
+    /// Exact matches take precedence and single mismatches are only searched for if enabled.
+    /// A protospacer a single mismatch away from more than one sgRNA is ambiguous and is not
+    /// assigned.
+    pub fn find(&self, protospacer: &[u8]) -> LibraryMatch {
+        if let Some(idx) = self.index.get(protospacer) {
+            return LibraryMatch::Exact(*idx);
+        }
+        if self.mismatches == 0 {
+            return LibraryMatch::Unmapped;
+        }
+
+        let mut hit = None;
+        let mut variant = protospacer.to_vec();
+        for pos in 0..variant.len() {
+            let original = variant[pos];
+            for nt in NUCLEOTIDES.iter().filter(|nt| **nt != original) {
+                variant[pos] = *nt;
+                match (hit, self.index.get(variant.as_slice())) {
+                    (None, Some(idx)) => hit = Some(*idx),
+                    (Some(prev), Some(idx)) if prev != *idx => return LibraryMatch::Ambiguous,
+                    _ => {}
+                }
+            }
+            variant[pos] = original;
+        }
+        hit.map_or(LibraryMatch::Unmapped, LibraryMatch::Mismatch)
+    }
+
+    /// Checks whether a protospacer is identical to a library sequence
+    pub fn contains(&self, protospacer: &[u8]) -> bool {
+        self.index.contains_key(protospacer)
+    }
+
+    pub fn sgrnas(&self) -> &[String] {
+        &self.sgrnas
+    }
+
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    /// The length of the protospacer sequences
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn len(&self) -> usize {
+        self.sgrnas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sgrnas.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    fn to_strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn build_library(mismatches: u8) -> Result<Library> {
+        Library::new(
+            to_strings(&["a_1", "a_2", "b_1"]),
+            to_strings(&["a", "a", "b"]),
+            &to_strings(&["AAAAA", "CCCCC", "aaaat"]),
+            mismatches,
+        )
+    }
+
+    #[test]
+    fn test_library_find() -> Result<()> {
+        let library = build_library(1)?;
+        assert_eq!(library.length(), 5);
+        assert_eq!(library.len(), 3);
+        assert_eq!(library.find(b"AAAAA"), LibraryMatch::Exact(0));
+        assert_eq!(library.find(b"AAAAT"), LibraryMatch::Exact(2));
+        assert_eq!(library.find(b"CCCGC"), LibraryMatch::Mismatch(1));
+        assert_eq!(library.find(b"CCNCC"), LibraryMatch::Mismatch(1));
+        assert_eq!(library.find(b"GGGGG"), LibraryMatch::Unmapped);
+
+        // a single mismatch from both `a_1` and `b_1`
+        assert_eq!(library.find(b"AAAAG"), LibraryMatch::Ambiguous);
+
+        let exact = build_library(0)?;
+        assert_eq!(exact.find(b"CCCGC"), LibraryMatch::Unmapped);
+        Ok(())
+    }
+
+    #[test]
+    fn test_library_validation() {
+        let build = |sgrnas: &[&str], sequences: &[&str]| {
+            Library::new(
+                to_strings(sgrnas),
+                to_strings(sgrnas),
+                &to_strings(sequences),
+                0,
+            )
+        };
+        assert!(build(&[], &[]).is_err());
+        assert!(build(&["a", "b"], &["AAAA", "CCC"]).is_err());
+        assert!(build(&["a", "b"], &["AAAA", "AAAA"]).is_err());
+        assert!(build(&["a", "a"], &["AAAA", "CCCC"]).is_err());
+        assert!(build(&["a"], &["AANA"]).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+mod count_reads;
+mod fastq;
+mod library;
+pub use count_reads::{count, SampleCounts};
+pub use fastq::FastqReader;
+pub use library::{Library, LibraryMatch};
@@ -1,46 +1,83 @@
 use crate::{
     aggregation::compute_aggregation,
-    enrich::enrichment_testing,
+    enrich::{
+        calibrate_ntc, enrichment_testing, interaction_enrichment_testing,
+        timecourse_enrichment_testing, EnrichmentResult, InteractionGroups, TestStrategy,
+    },
     io::{
-        get_string_column, match_headers_from_regex_set, to_ndarray, validate_ntc,
-        write_gene_frame, write_hit_list, write_sgrna_dataframe, Screenviz,
+        get_annotation_columns, pair_samples, select_sample_labels, to_ndarray, validate_counts,
+        validate_ntc, write_sgrna_dataframe, write_size_factors, Contrast, Design,
+    },
+    model::{control_variance, fit_mean_variance, model_mean_variance, DesignMatrix, LoggedOls},
+    norm::{
+        apply_size_factors, control_sgrna_indices, normalize_counts, Normalization,
+        NormalizedCounts,
     },
-    model::model_mean_variance,
-    norm::normalize_counts,
+    run_aggregation::write_aggregation_outputs,
+    sample_structure::{write_outliers, SampleStructure},
     utils::{config::Configuration, filter::filter_low_counts, logging::Logger},
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bon::builder;
+use hashbrown::HashMap;
+use ndarray::{s, Array1, Array2, Axis};
 use polars::prelude::*;
-use regex::Regex;
+use rayon::prelude::*;
+
+type StrategyInputs = (Option<Vec<(usize, usize)>>, Option<DesignMatrix>);
 
 /// Performs the `MAGeCK` Differential Expression and Gene Aggregation Algorithm
 pub fn mageck(
     frame: &DataFrame,
-    regex_controls: &[Regex],
-    regex_treatments: &[Regex],
+    control_labels: &[String],
+    treatment_labels: &[String],
+    design: Option<&Design>,
     config: &Configuration,
     logger: &Logger,
     skip_agg: bool,
 ) -> Result<()> {
-    let control_labels = match_headers_from_regex_set(frame, regex_controls)?;
-    let treatment_labels = match_headers_from_regex_set(frame, regex_treatments)?;
     let n_controls = control_labels.len();
-    let labels = [control_labels.clone(), treatment_labels.clone()].concat();
+    let labels = [control_labels, treatment_labels].concat();
 
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(frame, *config.sgrna_column(), *config.gene_column())?;
+    validate_inputs(frame, &labels, &sgrna_names, &gene_names, logger)?;
+    config
+        .aggregations()
+        .try_for_each(|agg| validate_ntc(&sgrna_names, agg))?;
     let count_matrix = to_ndarray(frame, &labels)?;
-    let sgrna_names = get_string_column(frame, 0);
-    let gene_names = get_string_column(frame, 1);
-    validate_ntc(&sgrna_names, config.aggregation())?;
+    let (pairs, design_matrix) = strategy_inputs(control_labels, treatment_labels, design, config)?;
 
     logger.start_mageck();
-    logger.group_names(&control_labels, &treatment_labels);
+    logger.group_names(control_labels, treatment_labels);
+    if let Some(pairs) = &pairs {
+        let named_pairs = pairs
+            .iter()
+            .map(|(c, t)| (control_labels[*c].clone(), treatment_labels[*t].clone()))
+            .collect::<Vec<_>>();
+        logger.sample_pairs(&named_pairs);
+    }
     logger.num_sgrnas(&sgrna_names);
     logger.num_genes(&gene_names);
     logger.norm_method(config.normalization());
     logger.aggregation_method(config.aggregation());
     logger.correction(*config.correction());
 
-    let normed_matrix = normalize_counts(&count_matrix, config.normalization(), logger);
+    let normed_matrix =
+        normalize(&count_matrix, &sgrna_names, &labels, config, logger)?.into_matrix();
+    let groups = labels
+        .iter()
+        .enumerate()
+        .map(|(idx, _)| {
+            if idx < n_controls {
+                "control"
+            } else {
+                "treatment"
+            }
+            .to_string()
+        })
+        .collect::<Vec<_>>();
+    sample_structure(&normed_matrix, &labels, &groups, config, logger)?;
 
     // Filter Low Counts
     let (filt_matrix, filt_sgrna_names, filt_gene_names) = filter_low_counts()
@@ -53,50 +90,590 @@ pub fn mageck(
         .call();
 
     // Mean-Variance Modeling
-    let adj_var = model_mean_variance(&filt_matrix, n_controls, config.model_choice(), logger);
+    let (adj_var, model) =
+        model_mean_variance(&filt_matrix, n_controls, config.model_choice(), logger);
 
-    // sgRNA Ranking (Enrichment)
-    let sgrna_results = enrichment_testing(
+    test_and_aggregate()
+        .normed_matrix(&filt_matrix)
+        .sgrna_names(&filt_sgrna_names)
+        .gene_names(&filt_gene_names)
+        .n_controls(n_controls)
+        .adj_var(&adj_var)
+        .model(&model)
+        .maybe_pairs(pairs.as_deref())
+        .maybe_design_matrix(design_matrix.as_ref())
+        .config(config)
+        .logger(logger)
+        .skip_agg(skip_agg)
+        .call()
+}
+
+/// A contrast with its labels resolved against the count matrix
+struct ResolvedContrast<'a> {
+    contrast: &'a Contrast,
+    control_labels: Vec<String>,
+    treatment_labels: Vec<String>,
+    pairs: Option<Vec<(usize, usize)>>,
+    design_matrix: Option<DesignMatrix>,
+}
+impl ResolvedContrast<'_> {
+    /// Key of the mean-variance model shared between contrasts.
+    ///
+    /// The model is fit on the control samples alone unless there is only a single
+    /// control, in which case the treatment samples are included in the fit.
+    fn model_key(&self) -> Vec<String> {
+        if self.control_labels.len() == 1 {
+            [&self.control_labels[..], &self.treatment_labels[..]].concat()
+        } else {
+            self.control_labels.clone()
+        }
+    }
+}
+
+/// Performs the `MAGeCK` Differential Expression and Gene Aggregation Algorithm for
+/// multiple contrasts of the same count matrix.
+///
+/// All samples used by any contrast are normalized together once, and the mean-variance
+/// model is fit once for each distinct control group. The contrasts are then tested in
+/// parallel and each writes its results to `<prefix>.<contrast>.*`.
+pub fn mageck_contrasts(
+    frame: &DataFrame,
+    contrasts: &[Contrast],
+    design: Option<&Design>,
+    config: &Configuration,
+    logger: &Logger,
+    skip_agg: bool,
+) -> Result<()> {
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(frame, *config.sgrna_column(), *config.gene_column())?;
+    config
+        .aggregations()
+        .try_for_each(|agg| validate_ntc(&sgrna_names, agg))?;
+
+    // resolve every contrast before any work is done so that errors are reported early
+    let resolved = contrasts
+        .iter()
+        .map(|contrast| {
+            let (control_labels, treatment_labels) =
+                select_sample_labels(frame, design, contrast.controls(), contrast.treatments())?;
+            let (pairs, design_matrix) =
+                strategy_inputs(&control_labels, &treatment_labels, design, config)?;
+            Ok(ResolvedContrast {
+                contrast,
+                control_labels,
+                treatment_labels,
+                pairs,
+                design_matrix,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // all samples used in any contrast in order of first appearance
+    let mut labels: Vec<String> = vec![];
+    resolved
+        .iter()
+        .flat_map(|x| x.control_labels.iter().chain(x.treatment_labels.iter()))
+        .for_each(|x| {
+            if !labels.contains(x) {
+                labels.push(x.clone());
+            }
+        });
+    validate_inputs(frame, &labels, &sgrna_names, &gene_names, logger)?;
+
+    logger.start_mageck();
+    logger.num_contrasts(resolved.len());
+    logger.num_sgrnas(&sgrna_names);
+    logger.num_genes(&gene_names);
+    logger.norm_method(config.normalization());
+    logger.aggregation_method(config.aggregation());
+    logger.correction(*config.correction());
+
+    let count_matrix = to_ndarray(frame, &labels)?;
+    let normed_matrix =
+        normalize(&count_matrix, &sgrna_names, &labels, config, logger)?.into_matrix();
+
+    // samples are grouped by their condition or by the selection that first matched them
+    let groups = labels
+        .iter()
+        .map(|label| match design.and_then(|d| d.get(label)) {
+            Some(sample) => sample.condition().to_string(),
+            None => resolved
+                .iter()
+                .find_map(|x| {
+                    if x.control_labels.contains(label) {
+                        Some(x.contrast.controls().join(","))
+                    } else if x.treatment_labels.contains(label) {
+                        Some(x.contrast.treatments().join(","))
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    sample_structure(&normed_matrix, &labels, &groups, config, logger)?;
+
+    logger.start_contrasts();
+    let silent = Logger::new_silent();
+    let mut filtered = Vec::with_capacity(resolved.len());
+    let mut models: HashMap<Vec<String>, LoggedOls> = HashMap::new();
+    for contrast in &resolved {
+        logger.contrast_groups(
+            contrast.contrast.name(),
+            &contrast.control_labels,
+            &contrast.treatment_labels,
+        );
+        let n_controls = contrast.control_labels.len();
+        let columns = contrast
+            .control_labels
+            .iter()
+            .chain(contrast.treatment_labels.iter())
+            .map(|x| labels.iter().position(|l| l == x).unwrap())
+            .collect::<Vec<_>>();
+
+        // the filter only depends on the control samples
+        let filt = filter_low_counts()
+            .norm_matrix(&normed_matrix.select(Axis(1), &columns))
+            .sgrna_names(&sgrna_names)
+            .gene_names(&gene_names)
+            .min_base(*config.min_base_mean())
+            .n_controls(n_controls)
+            .logger(&silent)
+            .call();
+
+        let key = contrast.model_key();
+        if !models.contains_key(&key) {
+            let model = fit_mean_variance(&filt.0, n_controls, config.model_choice(), logger);
+            models.insert(key, model);
+        }
+        filtered.push(filt);
+    }
+
+    resolved
+        .par_iter()
+        .zip(filtered.par_iter())
+        .map(
+            |(contrast, (filt_matrix, filt_sgrna_names, filt_gene_names))| {
+                let n_controls = contrast.control_labels.len();
+                let model = &models[&contrast.model_key()];
+                let adj_var = control_variance(filt_matrix, n_controls, model);
+                let prefix = format!("{}.{}", config.prefix(), contrast.contrast.name());
+                test_and_aggregate()
+                    .normed_matrix(filt_matrix)
+                    .sgrna_names(filt_sgrna_names)
+                    .gene_names(filt_gene_names)
+                    .n_controls(n_controls)
+                    .adj_var(&adj_var)
+                    .model(model)
+                    .maybe_pairs(contrast.pairs.as_deref())
+                    .maybe_design_matrix(contrast.design_matrix.as_ref())
+                    .config(&config.with_prefix(&prefix))
+                    .logger(&silent)
+                    .skip_agg(skip_agg)
+                    .call()
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+    Ok(())
+}
+
+/// Performs a time-course differential abundance analysis and gene aggregation
+///
+/// Samples are expected to be sorted by their time and the samples of the earliest
+/// timepoint are used as the baseline for filtering and mean-variance modeling.
+pub fn mageck_timecourse(
+    frame: &DataFrame,
+    sample_labels: &[String],
+    times: &[f64],
+    config: &Configuration,
+    logger: &Logger,
+    skip_agg: bool,
+) -> Result<()> {
+    let n_baseline = times.iter().filter(|x| **x == times[0]).count();
+    if n_baseline == times.len() {
+        bail!("Time-course analysis requires samples from at least two timepoints")
+    }
+    let baseline_labels = &sample_labels[..n_baseline];
+    let later_labels = &sample_labels[n_baseline..];
+
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(frame, *config.sgrna_column(), *config.gene_column())?;
+    validate_inputs(frame, sample_labels, &sgrna_names, &gene_names, logger)?;
+    config
+        .aggregations()
+        .try_for_each(|agg| validate_ntc(&sgrna_names, agg))?;
+    let count_matrix = to_ndarray(frame, sample_labels)?;
+
+    logger.start_mageck();
+    logger.group_names(baseline_labels, later_labels);
+    logger.num_sgrnas(&sgrna_names);
+    logger.num_genes(&gene_names);
+    logger.norm_method(config.normalization());
+    logger.aggregation_method(config.aggregation());
+    logger.correction(*config.correction());
+
+    let normed_matrix =
+        normalize(&count_matrix, &sgrna_names, sample_labels, config, logger)?.into_matrix();
+    let groups = times.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    sample_structure(&normed_matrix, sample_labels, &groups, config, logger)?;
+
+    // Filter Low Counts
+    let (filt_matrix, filt_sgrna_names, filt_gene_names) = filter_low_counts()
+        .norm_matrix(&normed_matrix)
+        .sgrna_names(&sgrna_names)
+        .gene_names(&gene_names)
+        .min_base(*config.min_base_mean())
+        .n_controls(n_baseline)
+        .logger(logger)
+        .call();
+
+    // Mean-Variance Modeling
+    let (adj_var, model) =
+        model_mean_variance(&filt_matrix, n_baseline, config.model_choice(), logger);
+
+    // sgRNA Ranking (Slope)
+    let sgrna_results = timecourse_enrichment_testing(
         &filt_matrix,
-        &adj_var,
-        n_controls,
+        times,
+        &model,
+        n_baseline,
         *config.correction(),
-        *config.strategy(),
         logger,
     );
 
+    write_and_aggregate()
+        .sgrna_results(&sgrna_results)
+        .sgrna_names(&filt_sgrna_names)
+        .gene_names(&filt_gene_names)
+        .adj_var(&adj_var)
+        .config(config)
+        .logger(logger)
+        .skip_agg(skip_agg)
+        .call()
+}
+
+/// Performs an interaction (difference-of-differences) analysis between a query arm and a
+/// reference arm and aggregates the interaction to genes.
+///
+/// The control samples are used for filtering and mean-variance modeling. If no query
+/// controls are provided both arms are compared against the same controls.
+#[builder]
+pub fn mageck_interaction<'a>(
+    frame: &DataFrame,
+    control_labels: &[String],
+    reference_labels: &[String],
+    query_labels: &[String],
+    query_control_labels: Option<&[String]>,
+    config: &Configuration<'a>,
+    logger: &Logger,
+    skip_agg: bool,
+) -> Result<()> {
+    let groups = InteractionGroups::new(
+        control_labels.len(),
+        reference_labels.len(),
+        query_labels.len(),
+        query_control_labels.map(|x| x.len()),
+    );
+    let labels = [
+        control_labels,
+        reference_labels,
+        query_labels,
+        query_control_labels.unwrap_or_default(),
+    ]
+    .concat();
+    if let Some(shared) = labels
+        .iter()
+        .enumerate()
+        .find(|(idx, x)| labels[..*idx].contains(x))
+        .map(|(_, x)| x)
+    {
+        bail!("Sample ({shared}) was selected in multiple interaction groups")
+    }
+
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(frame, *config.sgrna_column(), *config.gene_column())?;
+    validate_inputs(frame, &labels, &sgrna_names, &gene_names, logger)?;
+    config
+        .aggregations()
+        .try_for_each(|agg| validate_ntc(&sgrna_names, agg))?;
+    let count_matrix = to_ndarray(frame, &labels)?;
+
+    logger.start_mageck();
+    logger.interaction_groups(
+        control_labels,
+        reference_labels,
+        query_labels,
+        query_control_labels,
+    );
+    logger.num_sgrnas(&sgrna_names);
+    logger.num_genes(&gene_names);
+    logger.norm_method(config.normalization());
+    logger.aggregation_method(config.aggregation());
+    logger.correction(*config.correction());
+
+    let normed_matrix =
+        normalize(&count_matrix, &sgrna_names, &labels, config, logger)?.into_matrix();
+    let sample_groups = [
+        ("control", control_labels.len()),
+        ("reference", reference_labels.len()),
+        ("query", query_labels.len()),
+        ("query_control", query_control_labels.map_or(0, |x| x.len())),
+    ]
+    .iter()
+    .flat_map(|(name, n)| std::iter::repeat_n(name.to_string(), *n))
+    .collect::<Vec<_>>();
+    sample_structure(&normed_matrix, &labels, &sample_groups, config, logger)?;
+
+    // Filter Low Counts
+    let (filt_matrix, filt_sgrna_names, filt_gene_names) = filter_low_counts()
+        .norm_matrix(&normed_matrix)
+        .sgrna_names(&sgrna_names)
+        .gene_names(&gene_names)
+        .min_base(*config.min_base_mean())
+        .n_controls(groups.n_control())
+        .logger(logger)
+        .call();
+
+    // Mean-Variance Modeling
+    let (adj_var, model) = model_mean_variance(
+        &filt_matrix,
+        groups.n_control(),
+        config.model_choice(),
+        logger,
+    );
+
+    // sgRNA Ranking (Interaction)
+    let sgrna_results =
+        interaction_enrichment_testing(&filt_matrix, &groups, &model, *config.correction(), logger);
+
+    write_and_aggregate()
+        .sgrna_results(&sgrna_results)
+        .sgrna_names(&filt_sgrna_names)
+        .gene_names(&filt_gene_names)
+        .adj_var(&adj_var)
+        .config(config)
+        .logger(logger)
+        .skip_agg(skip_agg)
+        .call()
+}
+
+/// Validates the count matrix of the selected samples before any statistics are calculated
+fn validate_inputs(
+    frame: &DataFrame,
+    sample_labels: &[String],
+    sgrna_names: &[String],
+    gene_names: &[String],
+    logger: &Logger,
+) -> Result<()> {
+    let report = validate_counts(frame, sample_labels, sgrna_names, gene_names, None)?;
+    logger.validation_report(&report);
+    report.into_result()
+}
+
+/// Normalizes the count matrix with the configured method and writes the size factors
+/// of each sample to `<prefix>.size_factors.tsv`
+///
+/// Control sgRNAs are only resolved if they are required by the normalization method.
+fn normalize(
+    count_matrix: &Array2<f64>,
+    sgrna_names: &[String],
+    sample_labels: &[String],
+    config: &Configuration,
+    logger: &Logger,
+) -> Result<NormalizedCounts> {
+    let normed = match (config.normalization(), config.size_factors()) {
+        (Normalization::Provided, Some(size_factors)) => {
+            apply_size_factors(count_matrix, size_factors.select(sample_labels)?, logger)
+        }
+        (Normalization::Control, _) => {
+            let controls =
+                control_sgrna_indices(sgrna_names, config.control_sgrnas(), config.ntc_token())?;
+            normalize_counts(
+                count_matrix,
+                config.normalization(),
+                Some(&controls),
+                *config.strict_normalization(),
+                logger,
+            )?
+        }
+        (normalization, _) => normalize_counts(
+            count_matrix,
+            normalization,
+            None,
+            *config.strict_normalization(),
+            logger,
+        )?,
+    };
+    write_size_factors(&normed, sample_labels, config.prefix())?;
+    Ok(normed)
+}
+
+/// Calculates the PCA and clustering of the normalized samples if either the output or the
+/// outlier warning is configured
+///
+/// Each sample is assigned to a group which is used to identify samples that are closer to
+/// another group than to their own. The outliers are written alongside the warnings.
+fn sample_structure(
+    normed_matrix: &Array2<f64>,
+    sample_labels: &[String],
+    groups: &[String],
+    config: &Configuration,
+    logger: &Logger,
+) -> Result<()> {
+    if !config.sample_pca() && !config.warn_outliers() {
+        return Ok(());
+    }
+    let structure = SampleStructure::new(normed_matrix, sample_labels, groups);
+    logger.sample_structure(structure.explained_variance());
+    if *config.warn_outliers() {
+        let outliers = structure.outliers();
+        logger.sample_outliers(&outliers);
+        write_outliers(&outliers, config.prefix())?;
+    }
+    if *config.sample_pca() {
+        structure.write(config.prefix())?;
+    }
+    Ok(())
+}
+
+/// Calculates the log fold change of each treatment sample against the mean of the controls
+fn replicate_log_fold_changes(normed_matrix: &Array2<f64>, n_controls: usize) -> Array2<f64> {
+    let control_means = normed_matrix
+        .slice(s![.., ..n_controls])
+        .mean_axis(Axis(1))
+        .expect("at least one control sample");
+    let mut log_fold_changes = normed_matrix.slice(s![.., n_controls..]).to_owned();
+    for (mut row, control) in log_fold_changes
+        .axis_iter_mut(Axis(0))
+        .zip(control_means.iter())
+    {
+        row.mapv_inplace(|x| ((x + 1.) / (control + 1.)).log2());
+    }
+    log_fold_changes
+}
+
+/// Builds the additional inputs required by the configured test strategy
+fn strategy_inputs(
+    control_labels: &[String],
+    treatment_labels: &[String],
+    design: Option<&Design>,
+    config: &Configuration,
+) -> Result<StrategyInputs> {
+    let pairs = match config.strategy() {
+        TestStrategy::Paired => Some(pair_samples(design, control_labels, treatment_labels)?),
+        _ => None,
+    };
+    let design_matrix = match (config.strategy(), design) {
+        (TestStrategy::Glm, Some(design)) => Some(DesignMatrix::from_design(
+            design,
+            control_labels,
+            treatment_labels,
+            config.covariates(),
+        )?),
+        (TestStrategy::Glm, None) => bail!("The GLM strategy requires a design file"),
+        _ => None,
+    };
+    Ok((pairs, design_matrix))
+}
+
+/// Tests the filtered and normalized sgRNAs, aggregates them to genes, and writes all results
+#[builder]
+fn test_and_aggregate<'a>(
+    normed_matrix: &Array2<f64>,
+    sgrna_names: &[String],
+    gene_names: &[String],
+    n_controls: usize,
+    adj_var: &Array1<f64>,
+    model: &LoggedOls,
+    pairs: Option<&[(usize, usize)]>,
+    design_matrix: Option<&DesignMatrix>,
+    config: &Configuration<'a>,
+    logger: &Logger,
+    skip_agg: bool,
+) -> Result<()> {
+    // sgRNA Ranking (Enrichment)
+    let sgrna_results = enrichment_testing()
+        .normed_matrix(normed_matrix)
+        .adj_var(adj_var)
+        .model(model)
+        .n_controls(n_controls)
+        .maybe_pairs(pairs)
+        .maybe_design_matrix(design_matrix)
+        .glm_test(*config.glm_test())
+        .correction(*config.correction())
+        .strategy(*config.strategy())
+        .logger(logger)
+        .call();
+    let sgrna_results = calibrate_ntc(
+        sgrna_results,
+        sgrna_names,
+        config.ntc_token(),
+        *config.ntc_calibration(),
+        *config.correction(),
+        logger,
+    )?
+    .with_replicate_log_fold_changes(replicate_log_fold_changes(normed_matrix, n_controls));
+
+    write_and_aggregate()
+        .sgrna_results(&sgrna_results)
+        .sgrna_names(sgrna_names)
+        .gene_names(gene_names)
+        .adj_var(adj_var)
+        .config(config)
+        .logger(logger)
+        .skip_agg(skip_agg)
+        .call()
+}
+
+/// Writes the sgRNA results, aggregates them to genes, and writes the gene results
+#[builder]
+fn write_and_aggregate<'a>(
+    sgrna_results: &EnrichmentResult,
+    sgrna_names: &[String],
+    gene_names: &[String],
+    adj_var: &Array1<f64>,
+    config: &Configuration<'a>,
+    logger: &Logger,
+    skip_agg: bool,
+) -> Result<()> {
     // Write sgRNA DataFrame
     write_sgrna_dataframe(
-        &filt_sgrna_names,
-        &filt_gene_names,
+        sgrna_names,
+        gene_names,
         adj_var.as_slice().unwrap(),
-        &sgrna_results,
+        sgrna_results,
         config.prefix(),
+        *config.output_format(),
     )?;
 
     if skip_agg {
         Ok(())
     } else {
         // Gene Ranking (Aggregation)
-        let aggregation_results = compute_aggregation(
-            config.aggregation(),
-            &sgrna_results,
-            &filt_gene_names,
-            logger,
-            *config.correction(),
-            *config.seed(),
-        )?;
-
-        // Build Gene DataFrame
-        write_gene_frame(&aggregation_results, config.prefix())?;
+        let sgrna_efficacy = config
+            .sgrna_efficacy()
+            .as_ref()
+            .map(|efficacy| efficacy.select(sgrna_names));
+        let aggregation_results = compute_aggregation()
+            .agg(config.aggregation())
+            .sgrna_results(sgrna_results)
+            .gene_names(gene_names)
+            .sgrna_names(sgrna_names)
+            .maybe_reference_sets(config.reference_sets().as_ref())
+            .maybe_sgrna_efficacy(sgrna_efficacy.as_ref())
+            .logger(logger)
+            .correction(*config.correction())
+            .seed(*config.seed())
+            .call()?;
 
-        // Write hit list
-        write_hit_list(&aggregation_results, config, logger)?;
-
-        // Write screenviz config
-        let screenviz = Screenviz::new(&aggregation_results, config);
-        screenviz.write(config.prefix())?;
-
-        Ok(())
+        write_aggregation_outputs(
+            &aggregation_results,
+            sgrna_results,
+            gene_names,
+            sgrna_names,
+            sgrna_efficacy.as_ref(),
+            config,
+            logger,
+        )
     }
 }
@@ -0,0 +1,246 @@
+use super::EnrichmentResult;
+use crate::{norm::median, utils::logging::Logger};
+use adjustp::Procedure;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use ndarray::Array1;
+use statrs::distribution::{ContinuousCDF, Normal};
+
+/// Scale factor of the median absolute deviation to a consistent estimate of the standard
+/// deviation of a normal distribution
+const MAD_SCALE: f64 = 1.4826;
+
+/// Calibration of the sgRNA p-values against the non-targeting controls
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum NtcCalibration {
+    /// Use the p-values of the negative binomial test as is
+    #[default]
+    None,
+
+    /// Rank the test statistic of each sgRNA within the non-targeting control statistics
+    Empirical,
+
+    /// Fit a normal null to the non-targeting control z-scores with the median and MAD
+    Normal,
+}
+
+/// Converts one-sided p-values to a signed z-score where depletions are negative
+///
+/// The smaller of the two p-values is used to avoid losing precision in the tails. Tied or
+/// missing p-values (e.g. failed fits reported as `(1, 1)`) carry no direction and map to zero,
+/// and the p-value is capped at one half so it cannot flip the sign of the z-score.
+fn to_zscores(
+    pvalues_low: &Array1<f64>,
+    pvalues_high: &Array1<f64>,
+    normal: &Normal,
+) -> Array1<f64> {
+    pvalues_low
+        .iter()
+        .zip(pvalues_high.iter())
+        .map(|(low, high)| {
+            if low.is_nan() || high.is_nan() || low == high {
+                0.
+            } else if low < high {
+                normal.inverse_cdf(low.clamp(f64::MIN_POSITIVE, 0.5))
+            } else {
+                -normal.inverse_cdf(high.clamp(f64::MIN_POSITIVE, 0.5))
+            }
+        })
+        .collect()
+}
+
+/// Calculates the empirical one-sided p-values of each z-score within the null z-scores
+///
+/// ```text
+/// p_low = (1 + #{null <= z}) / (1 + n_null)
+/// p_high = (1 + #{null >= z}) / (1 + n_null)
+/// ```
+fn empirical_pvalues(zscores: &Array1<f64>, null: &[f64]) -> (Array1<f64>, Array1<f64>) {
+    let mut sorted = null.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len() as f64;
+    let low = zscores.mapv(|z| {
+        let n_below = sorted.partition_point(|x| *x <= z) as f64;
+        (1. + n_below) / (1. + n)
+    });
+    let high = zscores.mapv(|z| {
+        let n_above = sorted.len() as f64 - sorted.partition_point(|x| *x < z) as f64;
+        (1. + n_above) / (1. + n)
+    });
+    (low, high)
+}
+
+/// Calculates the one-sided p-values of each z-score after standardizing with a normal
+/// null fit to the null z-scores by their median and median absolute deviation
+///
+/// Returns the p-values and the fit null location and scale.
+fn normal_pvalues(
+    zscores: &Array1<f64>,
+    null: &[f64],
+    normal: &Normal,
+) -> (Array1<f64>, Array1<f64>, f64, f64) {
+    let null = Array1::from_vec(null.to_vec());
+    let location = median(&null.view());
+    let deviations = null.mapv(|x| (x - location).abs());
+    let scale = (MAD_SCALE * median(&deviations.view())).max(f64::EPSILON);
+    let standardized = zscores.mapv(|z| (z - location) / scale);
+    (
+        standardized.mapv(|z| normal.cdf(z)),
+        standardized.mapv(|z| normal.sf(z)),
+        location,
+        scale,
+    )
+}
+
+/// Calibrates the sgRNA p-values using the non-targeting control sgRNAs as an empirical
+/// null distribution.
+///
+/// The raw p-values are kept on the result and the calibrated p-values replace them for
+/// all downstream steps.
+pub fn calibrate_ntc(
+    sgrna_results: EnrichmentResult,
+    sgrna_names: &[String],
+    token: &str,
+    calibration: NtcCalibration,
+    correction: Procedure,
+    logger: &Logger,
+) -> Result<EnrichmentResult> {
+    if calibration == NtcCalibration::None {
+        return Ok(sgrna_results);
+    }
+    if token.is_empty() {
+        bail!("Non-targeting control calibration requires a non-targeting token")
+    }
+
+    let normal = Normal::new(0., 1.).unwrap();
+    let zscores = to_zscores(
+        sgrna_results.pvalues_low(),
+        sgrna_results.pvalues_high(),
+        &normal,
+    );
+    let null = sgrna_names
+        .iter()
+        .zip(zscores.iter())
+        .filter(|(name, _)| name.contains(token))
+        .map(|(_, z)| *z)
+        .collect::<Vec<_>>();
+    if null.is_empty() {
+        bail!("Non-Targeting Token ({token}) not found in any sgrna names - unable to calibrate sgRNA p-values")
+    }
+    logger.ntc_calibration(calibration, null.len());
+
+    let (low, high) = match calibration {
+        NtcCalibration::Empirical => empirical_pvalues(&zscores, &null),
+        NtcCalibration::Normal => {
+            let (low, high, location, scale) = normal_pvalues(&zscores, &null, &normal);
+            logger.ntc_null_parameters(location, scale);
+            (low, high)
+        }
+        NtcCalibration::None => unreachable!(),
+    };
+    Ok(sgrna_results.calibrate(low, high, correction))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_to_zscores() {
+        let normal = Normal::new(0., 1.).unwrap();
+        let low = array![0.025, 0.5, 1.0];
+        let high = array![1.0, 0.5, 1e-200];
+        let z = to_zscores(&low, &high, &normal);
+        assert!((z[0] + 1.959964).abs() < 1e-5);
+        assert!(z[1].abs() < 1e-12);
+        assert!(z[2] > 20.);
+
+        // failed fits and ties carry no direction
+        let z = to_zscores(&array![1.0, 0.3, f64::NAN], &array![1.0, 0.3, 0.2], &normal);
+        assert_eq!(z.to_vec(), vec![0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_empirical_pvalues() {
+        let null = [-2., -1., 0., 1., 2.];
+        let (low, high) = empirical_pvalues(&array![-3., 0., 3.], &null);
+        assert_eq!(low, array![1. / 6., 4. / 6., 1.]);
+        assert_eq!(high, array![1., 4. / 6., 1. / 6.]);
+    }
+
+    #[test]
+    fn test_normal_pvalues() {
+        let normal = Normal::new(0., 1.).unwrap();
+
+        // a shifted null moves the location of the calibrated p-values
+        let null = [0., 1., 2., 3., 4.];
+        let (low, high, location, scale) = normal_pvalues(&array![2.], &null, &normal);
+        assert_eq!(location, 2.);
+        assert!((scale - MAD_SCALE).abs() < 1e-12);
+        assert!((low[0] - 0.5).abs() < 1e-12);
+        assert!((high[0] - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_calibrate_ntc() -> Result<()> {
+        let names = ["g1_a", "g1_b", "ntc_1", "ntc_2", "ntc_3"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        let result = EnrichmentResult::new(
+            array![1e-10, 0.9, 0.4, 0.5, 0.6],
+            array![1.0, 0.1, 0.6, 0.5, 0.4],
+            array![10., 10., 10., 10., 10.],
+            array![1., 12., 10., 10., 10.],
+            Procedure::BenjaminiHochberg,
+        );
+        let logger = Logger::new_silent();
+        let calibrated = calibrate_ntc(
+            result,
+            &names,
+            "ntc",
+            NtcCalibration::Empirical,
+            Procedure::BenjaminiHochberg,
+            &logger,
+        )?;
+        assert_eq!(calibrated.pvalues_low()[0], 0.25);
+        assert_eq!(calibrated.raw_pvalues_low().unwrap()[0], 1e-10);
+
+        // a failed fit is not called as the strongest depletion
+        let result = EnrichmentResult::new(
+            array![1.0, 0.9, 0.4, 0.5, 0.6],
+            array![1.0, 0.1, 0.6, 0.5, 0.4],
+            array![10., 10., 10., 10., 10.],
+            array![1., 12., 10., 10., 10.],
+            Procedure::BenjaminiHochberg,
+        );
+        let calibrated = calibrate_ntc(
+            result,
+            &names,
+            "ntc",
+            NtcCalibration::Empirical,
+            Procedure::BenjaminiHochberg,
+            &logger,
+        )?;
+        assert_eq!(calibrated.pvalues_low()[0], 0.75);
+
+        let result = EnrichmentResult::new(
+            array![0.1, 0.2],
+            array![0.9, 0.8],
+            array![10., 10.],
+            array![1., 1.],
+            Procedure::BenjaminiHochberg,
+        );
+        assert!(calibrate_ntc(
+            result,
+            &names[..2],
+            "ntc",
+            NtcCalibration::Normal,
+            Procedure::BenjaminiHochberg,
+            &logger,
+        )
+        .is_err());
+        Ok(())
+    }
+}
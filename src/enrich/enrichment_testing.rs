@@ -1,10 +1,12 @@
-use super::{EnrichmentResult, TestStrategy};
+use super::{glm_testing::glm_enrichment_testing, EnrichmentResult, GlmTest, TestStrategy};
 use crate::{
+    model::{DesignMatrix, LoggedOls},
     norm::median,
     utils::logging::Logger,
     utils::math::{negative_log_sum, normalize, weighted_geometric_mean},
 };
 use adjustp::Procedure;
+use bon::builder;
 use ndarray::{s, stack, Array1, Array2, Axis, Zip};
 use statrs::function::beta;
 
@@ -55,23 +57,23 @@ fn set_zero_to_minimum(array: &Array1<f64>, minimum: f64) -> Array1<f64> {
 }
 
 /// Sets all values in an array equal to 0.0 to the minimum nonzero value in the array
-fn set_zero_to_minimum_nonzero(array: &Array1<f64>) -> Array1<f64> {
+pub fn set_zero_to_minimum_nonzero(array: &Array1<f64>) -> Array1<f64> {
     let minimum = get_nonzero_minimum(array);
     set_zero_to_minimum(array, minimum)
 }
 
 /// Calculates the median of each row in an array
-fn row_median(array: &Array2<f64>) -> Array1<f64> {
+pub fn row_median(array: &Array2<f64>) -> Array1<f64> {
     array.map_axis(Axis(1), |x| median(&x))
 }
 
 /// Selects the first `n_controls` columns from an array
-fn select_controls(array: &Array2<f64>, n_controls: usize) -> Array2<f64> {
+pub fn select_controls(array: &Array2<f64>, n_controls: usize) -> Array2<f64> {
     array.slice(s![.., ..n_controls]).to_owned()
 }
 
 /// Selects the last `n_treatments` columns from an array
-fn select_treatments(array: &Array2<f64>, n_controls: usize) -> Array2<f64> {
+pub fn select_treatments(array: &Array2<f64>, n_controls: usize) -> Array2<f64> {
     array.slice(s![.., n_controls..]).to_owned()
 }
 
@@ -183,14 +185,67 @@ pub fn median_enrichment_testing(
     EnrichmentResult::new(low, high, control_means, treatment_means, correction)
 }
 
+/// Performs enrichment testing of each treatment sample against its matched control sample.
+///
+/// The negative binomial parameters of each pair are calculated from the counts of the
+/// matched control sample and the variance predicted by the mean-variance model at those
+/// counts. The per-pair p-values are then aggregated with a geometric mean.
+pub fn paired_enrichment_testing(
+    normed_matrix: &Array2<f64>,
+    model: &LoggedOls,
+    n_controls: usize,
+    pairs: &[(usize, usize)],
+    correction: Procedure,
+    logger: &Logger,
+) -> EnrichmentResult {
+    let (low, high): (Vec<_>, Vec<_>) = pairs
+        .iter()
+        .map(|(c_idx, t_idx)| {
+            let control = set_zero_to_minimum_nonzero(&normed_matrix.column(*c_idx).to_owned());
+            let treatment = normed_matrix.column(n_controls + t_idx).to_owned();
+            let pair_var = model.predict(&control);
+            let param_r = calculate_r(&control, &pair_var);
+            let param_p = calculate_p(&control, &pair_var);
+            (
+                map_enrichment(&treatment, &param_r, &param_p, false),
+                map_enrichment(&treatment, &param_r, &param_p, true),
+            )
+        })
+        .unzip();
+    logger.num_sample_pairs(pairs.len());
+
+    let low_views = low.iter().map(|x| x.view()).collect::<Vec<_>>();
+    let high_views = high.iter().map(|x| x.view()).collect::<Vec<_>>();
+    let weights = Array1::ones(pairs.len());
+    let low = weighted_geometric_mean(&stack(Axis(1), &low_views).unwrap(), &weights);
+    let high = weighted_geometric_mean(&stack(Axis(1), &high_views).unwrap(), &weights);
+
+    // Adjust p-values to set zeros to the minimum non-zero value
+    let low = set_zero_to_minimum_nonzero(&low);
+    let high = set_zero_to_minimum_nonzero(&high);
+
+    let control_means = row_median(&select_controls(normed_matrix, n_controls));
+    let treatment_means = row_median(&select_treatments(normed_matrix, n_controls));
+    EnrichmentResult::new(low, high, control_means, treatment_means, correction)
+}
+
 /// Performs enrichment testing using a negative binomial distribution
 ///
 /// Samples are first split into control and treatment groups, then the median of each sgRNA
 /// is calculated for each group.
+///
+/// The paired strategy requires the `(control, treatment)` index pairs where treatment
+/// indices are relative to the first treatment column, and the GLM strategy requires the
+/// design matrix of the samples.
+#[builder]
 pub fn enrichment_testing(
     normed_matrix: &Array2<f64>,
     adj_var: &Array1<f64>,
+    model: &LoggedOls,
     n_controls: usize,
+    pairs: Option<&[(usize, usize)]>,
+    design_matrix: Option<&DesignMatrix>,
+    #[builder(default)] glm_test: GlmTest,
     correction: Procedure,
     strategy: TestStrategy,
     logger: &Logger,
@@ -217,6 +272,23 @@ pub fn enrichment_testing(
         TestStrategy::CountMedian => {
             median_enrichment_testing(normed_matrix, adj_var, n_controls, correction)
         }
+        TestStrategy::Paired => paired_enrichment_testing(
+            normed_matrix,
+            model,
+            n_controls,
+            pairs.expect("Paired testing requires matched sample pairs"),
+            correction,
+            logger,
+        ),
+        TestStrategy::Glm => glm_enrichment_testing(
+            normed_matrix,
+            model,
+            design_matrix.expect("GLM testing requires a design matrix"),
+            n_controls,
+            glm_test,
+            correction,
+            logger,
+        ),
     }
 }
 
@@ -332,6 +404,39 @@ mod testing {
             .for_each(|(e, r)| assert!((e - r).abs() < 1e-08));
     }
 
+    #[test]
+    fn test_paired_enrichment_testing() {
+        use crate::{
+            model::{LoggedOls, ModelChoice},
+            utils::logging::Logger,
+        };
+        use adjustp::Procedure;
+
+        let logger = Logger::new_silent();
+        let means = ndarray::Array1::linspace(10., 1000., 50);
+        let variances = &means * 2. + &means.mapv(|x| x * x * 0.05);
+        let model = LoggedOls::fit(&means, &variances, &ModelChoice::Ols, &logger);
+
+        // controls [0, 1], treatments [2, 3] where the first sgRNA is depleted
+        let matrix = ndarray::arr2(&[
+            [500., 800., 10., 16.],
+            [500., 800., 500., 800.],
+            [500., 800., 480., 820.],
+        ]);
+        let pairs = [(0, 0), (1, 1)];
+        let result = super::paired_enrichment_testing(
+            &matrix,
+            &model,
+            2,
+            &pairs,
+            Procedure::BenjaminiHochberg,
+            &logger,
+        );
+        assert!(result.pvalues_low()[0] < 0.01);
+        assert!(result.pvalues_low()[1] > 0.1);
+        assert!(result.pvalues_high()[0] > 0.9);
+    }
+
     #[test]
     fn test_set_zero_to_minimum_nonzero() {
         let x = ndarray::arr1(&[1., 2., 3., 0., 0., 1.]);
@@ -0,0 +1,202 @@
+use super::{
+    enrichment_testing::{
+        row_median, select_controls, select_treatments, set_zero_to_minimum_nonzero,
+    },
+    Coefficient, EnrichmentResult, GlmTest,
+};
+use crate::{
+    model::{moments_dispersion, DesignMatrix, LoggedOls, NegativeBinomialGlm},
+    utils::logging::Logger,
+};
+use adjustp::Procedure;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use rayon::prelude::*;
+use statrs::distribution::{ChiSquared, ContinuousCDF, Normal};
+use std::f64::consts::LN_2;
+
+/// Prior degrees of freedom given to the trended dispersion when shrinking the
+/// per-sgRNA dispersion estimates
+const DISPERSION_PRIOR_DF: f64 = 10.0;
+
+/// Per-sgRNA test results of every coefficient
+struct SgrnaFit {
+    estimates: Vec<f64>,
+    stderrs: Vec<f64>,
+    pvalues: Vec<f64>,
+    pvalue_low: f64,
+    pvalue_high: f64,
+}
+impl SgrnaFit {
+    fn failed(n_coef: usize) -> Self {
+        Self {
+            estimates: vec![f64::NAN; n_coef],
+            stderrs: vec![f64::NAN; n_coef],
+            pvalues: vec![1.0; n_coef],
+            pvalue_low: 1.0,
+            pvalue_high: 1.0,
+        }
+    }
+}
+
+/// Shrinks the moment estimate of the dispersion towards the trended dispersion on the
+/// log scale weighted by their respective degrees of freedom
+fn shrink_dispersion(moments: f64, trend: f64, df: usize) -> f64 {
+    let df = df as f64;
+    let moments = moments.max(1e-8);
+    ((df * moments.ln() + DISPERSION_PRIOR_DF * trend.ln()) / (df + DISPERSION_PRIOR_DF)).exp()
+}
+
+/// Removes a column from a design matrix
+fn drop_column(matrix: &Array2<f64>, idx: usize) -> Array2<f64> {
+    let keep = (0..matrix.ncols())
+        .filter(|x| *x != idx)
+        .collect::<Vec<_>>();
+    matrix.select(Axis(1), &keep)
+}
+
+/// Fits the full model for a single sgRNA and tests each coefficient
+fn fit_sgrna(
+    y: &ArrayView1<f64>,
+    design: &DesignMatrix,
+    reduced: &[Array2<f64>],
+    model: &LoggedOls,
+    test: GlmTest,
+) -> SgrnaFit {
+    let n_coef = design.names().len();
+    let glm = NegativeBinomialGlm::new(design.matrix());
+    let trend = model.dispersion(y.mean().unwrap_or(0.));
+    let df = y.len() - n_coef;
+
+    let Some(initial) = glm.fit(y, trend) else {
+        return SgrnaFit::failed(n_coef);
+    };
+    let dispersion = shrink_dispersion(moments_dispersion(y, initial.mu(), df), trend, df);
+    let Some(fit) = glm.fit(y, dispersion) else {
+        return SgrnaFit::failed(n_coef);
+    };
+
+    let normal = Normal::new(0., 1.).unwrap();
+    let chisq = ChiSquared::new(1.).unwrap();
+    let pvalues = (0..n_coef)
+        .map(|idx| match test {
+            GlmTest::Wald => {
+                let z = fit.coefficients()[idx] / fit.standard_errors()[idx];
+                2. * normal.cdf(-z.abs())
+            }
+            // the intercept is not reported so its reduced model is not fit
+            GlmTest::Lrt if idx == 0 => 1.0,
+            GlmTest::Lrt => match NegativeBinomialGlm::new(&reduced[idx]).fit(y, dispersion) {
+                Some(reduced_fit) => {
+                    let stat = 2. * (fit.log_likelihood() - reduced_fit.log_likelihood());
+                    chisq.sf(stat.max(0.))
+                }
+                None => 1.0,
+            },
+        })
+        .map(|p| if p.is_nan() { 1.0 } else { p })
+        .collect::<Vec<_>>();
+
+    // split the two-sided test of the treatment coefficient into one-sided p-values
+    let t_idx = design.treatment_index();
+    let (pvalue_low, pvalue_high) = match test {
+        GlmTest::Wald => {
+            let z = fit.coefficients()[t_idx] / fit.standard_errors()[t_idx];
+            (normal.cdf(z), normal.sf(z))
+        }
+        GlmTest::Lrt => {
+            let half = pvalues[t_idx] / 2.;
+            if fit.coefficients()[t_idx] < 0. {
+                (half, 1. - half)
+            } else {
+                (1. - half, half)
+            }
+        }
+    };
+
+    SgrnaFit {
+        estimates: fit.coefficients().mapv(|x| x / LN_2).to_vec(),
+        stderrs: fit.standard_errors().mapv(|x| x / LN_2).to_vec(),
+        pvalues,
+        pvalue_low: if pvalue_low.is_nan() { 1.0 } else { pvalue_low },
+        pvalue_high: if pvalue_high.is_nan() {
+            1.0
+        } else {
+            pvalue_high
+        },
+    }
+}
+
+/// Performs enrichment testing with a negative binomial generalized linear model.
+///
+/// Each sgRNA is fit independently by IRLS with a dispersion that shrinks the
+/// method-of-moments estimate towards the trend of the mean-variance model. The
+/// treatment coefficient is used for the one-sided p-values and every non-intercept
+/// coefficient is reported alongside the results.
+pub fn glm_enrichment_testing(
+    normed_matrix: &Array2<f64>,
+    model: &LoggedOls,
+    design: &DesignMatrix,
+    n_controls: usize,
+    test: GlmTest,
+    correction: Procedure,
+    logger: &Logger,
+) -> EnrichmentResult {
+    logger.glm_parameters(test, design.names());
+    let reduced = (0..design.names().len())
+        .map(|idx| drop_column(design.matrix(), idx))
+        .collect::<Vec<_>>();
+
+    let fits = normed_matrix
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .map(|y| fit_sgrna(&y, design, &reduced, model, test))
+        .collect::<Vec<_>>();
+
+    let low = set_zero_to_minimum_nonzero(&fits.iter().map(|x| x.pvalue_low).collect());
+    let high = set_zero_to_minimum_nonzero(&fits.iter().map(|x| x.pvalue_high).collect());
+
+    // report all coefficients except the intercept
+    let coefficients = design
+        .names()
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(idx, name)| {
+            Coefficient::new(
+                name.clone(),
+                fits.iter()
+                    .map(|x| x.estimates[idx])
+                    .collect::<Array1<f64>>(),
+                fits.iter().map(|x| x.stderrs[idx]).collect::<Array1<f64>>(),
+                fits.iter().map(|x| x.pvalues[idx]).collect::<Array1<f64>>(),
+            )
+        })
+        .collect();
+
+    let control_means = row_median(&select_controls(normed_matrix, n_controls));
+    let treatment_means = row_median(&select_treatments(normed_matrix, n_controls));
+    EnrichmentResult::new(low, high, control_means, treatment_means, correction)
+        .with_coefficients(coefficients)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_shrink_dispersion() {
+        // no residual information returns the trend
+        assert!((shrink_dispersion(0.5, 0.1, 0) - 0.1).abs() < 1e-12);
+
+        // equal degrees of freedom is the geometric mean
+        let shrunk = shrink_dispersion(0.4, 0.1, DISPERSION_PRIOR_DF as usize);
+        assert!((shrunk - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_drop_column() {
+        let m = array![[1., 2., 3.], [4., 5., 6.]];
+        assert_eq!(drop_column(&m, 1), array![[1., 3.], [4., 6.]]);
+    }
+}
@@ -0,0 +1,249 @@
+use super::{
+    enrichment_testing::{row_median, set_zero_to_minimum_nonzero},
+    Coefficient, EnrichmentResult,
+};
+use crate::{model::LoggedOls, utils::logging::Logger};
+use adjustp::Procedure;
+use ndarray::{Array1, Array2, Axis};
+use statrs::distribution::{ContinuousCDF, Normal};
+use std::f64::consts::LN_2;
+
+/// Pseudocount added to the group medians before the log transform
+const PSEUDOCOUNT: f64 = 0.5;
+
+/// Column layout of the sample groups of an interaction analysis.
+///
+/// Columns are expected in the order: control, reference, query, and (optionally) a
+/// separate control of the query arm. If no query control is provided both arms share
+/// the same control.
+#[derive(Debug, Clone)]
+pub struct InteractionGroups {
+    control: Vec<usize>,
+    reference: Vec<usize>,
+    query: Vec<usize>,
+    query_control: Option<Vec<usize>>,
+}
+impl InteractionGroups {
+    pub fn new(
+        n_control: usize,
+        n_reference: usize,
+        n_query: usize,
+        n_query_control: Option<usize>,
+    ) -> Self {
+        let reference_start = n_control;
+        let query_start = reference_start + n_reference;
+        let query_control_start = query_start + n_query;
+        Self {
+            control: (0..n_control).collect(),
+            reference: (reference_start..query_start).collect(),
+            query: (query_start..query_control_start).collect(),
+            query_control: n_query_control
+                .map(|n| (query_control_start..query_control_start + n).collect()),
+        }
+    }
+
+    pub fn n_control(&self) -> usize {
+        self.control.len()
+    }
+}
+
+/// Log2 median abundance of a sample group and the variance of that estimate
+struct GroupEstimate {
+    medians: Array1<f64>,
+    log2_medians: Array1<f64>,
+    log2_var: Array1<f64>,
+}
+impl GroupEstimate {
+    /// Estimates the log2 group median of each sgRNA with a variance derived from the
+    /// mean-variance model by the delta method:
+    ///
+    /// ```text
+    /// var(log2(x)) ~ var(x) / (n * (x * ln(2))^2)
+    /// ```
+    fn new(normed_matrix: &Array2<f64>, columns: &[usize], model: &LoggedOls) -> Self {
+        let medians = row_median(&normed_matrix.select(Axis(1), columns));
+        let shifted = medians.mapv(|x| x + PSEUDOCOUNT);
+        let log2_var =
+            model.predict(&shifted) / (&shifted * &shifted * LN_2.powi(2) * columns.len() as f64);
+        Self {
+            log2_medians: shifted.mapv(f64::log2),
+            medians,
+            log2_var,
+        }
+    }
+}
+
+/// Estimate, standard error, and z-score of a log2 difference
+struct Difference {
+    estimate: Array1<f64>,
+    stderr: Array1<f64>,
+    zscore: Array1<f64>,
+}
+impl Difference {
+    fn new(estimate: Array1<f64>, variance: Array1<f64>) -> Self {
+        let stderr = variance.mapv(f64::sqrt);
+        let zscore = &estimate / &stderr;
+        Self {
+            estimate,
+            stderr,
+            zscore,
+        }
+    }
+
+    fn into_coefficient(self, name: &str, normal: &Normal) -> Coefficient {
+        let pvalue = self.zscore.mapv(|z| 2. * normal.cdf(-z.abs()));
+        Coefficient::new(name.to_string(), self.estimate, self.stderr, pvalue)
+    }
+}
+
+/// Performs enrichment testing of the interaction (difference-of-differences) between a
+/// query arm and a reference arm.
+///
+/// The interaction of each sgRNA is the log2 fold change of the query arm over its control
+/// minus the log2 fold change of the reference arm over its control. The variance of each
+/// log2 group median is derived from the mean-variance model and the one-sided p-values
+/// describe a negative (depletion) or positive (enrichment) interaction. If both arms share
+/// a control it cancels out of the interaction.
+///
+/// The log2 fold change of the result is the interaction and the log2 fold changes of each
+/// arm are reported alongside the results.
+pub fn interaction_enrichment_testing(
+    normed_matrix: &Array2<f64>,
+    groups: &InteractionGroups,
+    model: &LoggedOls,
+    correction: Procedure,
+    logger: &Logger,
+) -> EnrichmentResult {
+    logger.start_differential_abundance();
+
+    let control = GroupEstimate::new(normed_matrix, &groups.control, model);
+    let reference = GroupEstimate::new(normed_matrix, &groups.reference, model);
+    let query = GroupEstimate::new(normed_matrix, &groups.query, model);
+    let query_control = groups
+        .query_control
+        .as_ref()
+        .map(|columns| GroupEstimate::new(normed_matrix, columns, model));
+
+    let reference_lfc = Difference::new(
+        &reference.log2_medians - &control.log2_medians,
+        &reference.log2_var + &control.log2_var,
+    );
+    let (query_lfc, interaction) = match &query_control {
+        Some(query_control) => (
+            Difference::new(
+                &query.log2_medians - &query_control.log2_medians,
+                &query.log2_var + &query_control.log2_var,
+            ),
+            Difference::new(
+                &query.log2_medians - &query_control.log2_medians - &reference.log2_medians
+                    + &control.log2_medians,
+                &query.log2_var + &query_control.log2_var + &reference.log2_var + &control.log2_var,
+            ),
+        ),
+        None => (
+            Difference::new(
+                &query.log2_medians - &control.log2_medians,
+                &query.log2_var + &control.log2_var,
+            ),
+            Difference::new(
+                &query.log2_medians - &reference.log2_medians,
+                &query.log2_var + &reference.log2_var,
+            ),
+        ),
+    };
+
+    let normal = Normal::new(0., 1.).unwrap();
+    let pvalues_low = set_zero_to_minimum_nonzero(&interaction.zscore.mapv(|z| normal.cdf(z)));
+    let pvalues_high = set_zero_to_minimum_nonzero(&interaction.zscore.mapv(|z| normal.sf(z)));
+
+    EnrichmentResult::new(
+        pvalues_low,
+        pvalues_high,
+        reference.medians,
+        query.medians,
+        correction,
+    )
+    .with_log_fold_change(interaction.estimate.clone())
+    .with_coefficients(vec![
+        reference_lfc.into_coefficient("reference", &normal),
+        query_lfc.into_coefficient("query", &normal),
+        interaction.into_coefficient("interaction", &normal),
+    ])
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::model::ModelChoice;
+    use ndarray::array;
+
+    fn build_model() -> LoggedOls {
+        let means = Array1::linspace(10., 1000., 50);
+        let vars = means.mapv(|x: f64| x + 0.1 * x.powi(2));
+        LoggedOls::fit(&means, &vars, &ModelChoice::Ols, &Logger::new_silent())
+    }
+
+    #[test]
+    fn test_interaction_groups() {
+        let groups = InteractionGroups::new(2, 2, 3, Some(1));
+        assert_eq!(groups.control, vec![0, 1]);
+        assert_eq!(groups.reference, vec![2, 3]);
+        assert_eq!(groups.query, vec![4, 5, 6]);
+        assert_eq!(groups.query_control, Some(vec![7]));
+        assert_eq!(groups.n_control(), 2);
+    }
+
+    #[test]
+    fn test_interaction_shared_control() {
+        let model = build_model();
+
+        // control | reference | query
+        let normed_matrix = array![
+            [500., 500., 250., 250., 60., 60.],
+            [500., 500., 250., 250., 250., 250.],
+            [500., 500., 500., 500., 2000., 2000.],
+        ];
+        let groups = InteractionGroups::new(2, 2, 2, None);
+        let result = interaction_enrichment_testing(
+            &normed_matrix,
+            &groups,
+            &model,
+            Procedure::BenjaminiHochberg,
+            &Logger::new_silent(),
+        );
+
+        // equal effects in both arms have no interaction
+        assert!(result.log_fold_change()[1].abs() < 1e-12);
+        assert!(result.log_fold_change()[0] < 0.);
+        assert!(result.log_fold_change()[2] > 0.);
+        assert!(result.pvalues_low()[0] < result.pvalues_low()[1]);
+        assert!(result.pvalues_high()[2] < result.pvalues_high()[1]);
+
+        let names = result
+            .coefficients()
+            .iter()
+            .map(|x| x.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["reference", "query", "interaction"]);
+    }
+
+    #[test]
+    fn test_interaction_separate_controls() {
+        let model = build_model();
+
+        // control | reference | query | query control
+        let normed_matrix = array![[500., 250., 100., 200.], [500., 250., 400., 800.]];
+        let groups = InteractionGroups::new(1, 1, 1, Some(1));
+        let result = interaction_enrichment_testing(
+            &normed_matrix,
+            &groups,
+            &model,
+            Procedure::BenjaminiHochberg,
+            &Logger::new_silent(),
+        );
+
+        // both arms halve their abundance relative to their own control
+        assert!(result.log_fold_change()[0].abs() < 1e-2);
+        assert!(result.log_fold_change()[1].abs() < 1e-2);
+    }
+}
@@ -1,8 +1,15 @@
+mod calibration;
 mod enrichment_testing;
+mod glm_testing;
+mod interaction_testing;
 mod results;
+mod timecourse_testing;
+pub use calibration::{calibrate_ntc, NtcCalibration};
 use clap::ValueEnum;
 pub use enrichment_testing::enrichment_testing;
-pub use results::EnrichmentResult;
+pub use interaction_testing::{interaction_enrichment_testing, InteractionGroups};
+pub use results::{Coefficient, EnrichmentResult};
+pub use timecourse_testing::timecourse_enrichment_testing;
 
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
 pub enum TestStrategy {
@@ -19,4 +26,25 @@ pub enum TestStrategy {
     /// where the weights are calculated from the magnitude of negative-log p-values of each sample
     #[value(name = "wgm")]
     SampleWeightedGeometricMean,
+
+    /// Test each treatment sample against its matched control sample and then aggregate the
+    /// per-pair p-values with a geometric mean
+    #[value(name = "paired")]
+    Paired,
+
+    /// Fit a negative binomial generalized linear model per sgRNA using the design matrix
+    /// built from the design file (requires `--design`)
+    #[value(name = "glm")]
+    Glm,
+}
+
+/// Hypothesis test used for the coefficients of the generalized linear model
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum GlmTest {
+    /// Wald test of each coefficient
+    #[default]
+    Wald,
+
+    /// Likelihood-ratio test against the model without each coefficient
+    Lrt,
 }
@@ -1,5 +1,45 @@
 use adjustp::{adjust, Procedure};
-use ndarray::Array1;
+use ndarray::{Array1, Array2};
+
+/// Per-sgRNA estimates of a single model coefficient
+pub struct Coefficient {
+    name: String,
+    log2_estimate: Array1<f64>,
+    log2_stderr: Array1<f64>,
+    pvalue: Array1<f64>,
+}
+impl Coefficient {
+    pub fn new(
+        name: String,
+        log2_estimate: Array1<f64>,
+        log2_stderr: Array1<f64>,
+        pvalue: Array1<f64>,
+    ) -> Self {
+        Self {
+            name,
+            log2_estimate,
+            log2_stderr,
+            pvalue,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn log2_estimate(&self) -> &Array1<f64> {
+        &self.log2_estimate
+    }
+
+    pub fn log2_stderr(&self) -> &Array1<f64> {
+        &self.log2_stderr
+    }
+
+    pub fn pvalue(&self) -> &Array1<f64> {
+        &self.pvalue
+    }
+}
+
 pub struct EnrichmentResult {
     pvalues_low: Array1<f64>,
     pvalues_high: Array1<f64>,
@@ -11,6 +51,10 @@ pub struct EnrichmentResult {
     fold_change: Array1<f64>,
     log_fold_change: Array1<f64>,
     product: Array1<f64>,
+    coefficients: Vec<Coefficient>,
+    raw_pvalues_low: Option<Array1<f64>>,
+    raw_pvalues_high: Option<Array1<f64>>,
+    replicate_log_fold_changes: Option<Array2<f64>>,
 }
 impl EnrichmentResult {
     pub fn new(
@@ -37,9 +81,48 @@ impl EnrichmentResult {
             fold_change,
             log_fold_change,
             product,
+            coefficients: Vec::new(),
+            raw_pvalues_low: None,
+            raw_pvalues_high: None,
+            replicate_log_fold_changes: None,
         }
     }
 
+    /// Attaches per-coefficient model estimates to the result
+    pub fn with_coefficients(mut self, coefficients: Vec<Coefficient>) -> Self {
+        self.coefficients = coefficients;
+        self
+    }
+
+    /// Attaches the log fold change of each treatment replicate (sgRNAs x replicates)
+    pub fn with_replicate_log_fold_changes(mut self, log_fold_changes: Array2<f64>) -> Self {
+        self.replicate_log_fold_changes = Some(log_fold_changes);
+        self
+    }
+
+    /// Replaces the p-values with calibrated p-values and keeps the original p-values
+    pub fn calibrate(
+        mut self,
+        pvalues_low: Array1<f64>,
+        pvalues_high: Array1<f64>,
+        correction: Procedure,
+    ) -> Self {
+        self.pvalues_twosided = Self::calculate_twosided(&pvalues_low, &pvalues_high);
+        self.fdr = Self::calculate_fdr(&self.pvalues_twosided, correction);
+        self.product = Self::calculate_product(&self.log_fold_change, &self.pvalues_twosided);
+        self.raw_pvalues_low = Some(std::mem::replace(&mut self.pvalues_low, pvalues_low));
+        self.raw_pvalues_high = Some(std::mem::replace(&mut self.pvalues_high, pvalues_high));
+        self
+    }
+
+    /// Replaces the log fold change with a model estimate (e.g. a time-course slope)
+    pub fn with_log_fold_change(mut self, log_fold_change: Array1<f64>) -> Self {
+        self.fold_change = log_fold_change.mapv(|x| x.exp2());
+        self.product = Self::calculate_product(&log_fold_change, &self.pvalues_twosided);
+        self.log_fold_change = log_fold_change;
+        self
+    }
+
     fn calculate_twosided(pvalues_low: &Array1<f64>, pvalues_high: &Array1<f64>) -> Array1<f64> {
         pvalues_low
             .iter()
@@ -107,6 +190,25 @@ impl EnrichmentResult {
     pub fn product(&self) -> &Array1<f64> {
         &self.product
     }
+
+    pub fn coefficients(&self) -> &[Coefficient] {
+        &self.coefficients
+    }
+
+    /// Low-side p-values before calibration (if calibrated)
+    pub fn raw_pvalues_low(&self) -> Option<&Array1<f64>> {
+        self.raw_pvalues_low.as_ref()
+    }
+
+    /// High-side p-values before calibration (if calibrated)
+    pub fn raw_pvalues_high(&self) -> Option<&Array1<f64>> {
+        self.raw_pvalues_high.as_ref()
+    }
+
+    /// Log fold change of each treatment replicate against the control means (if attached)
+    pub fn replicate_log_fold_changes(&self) -> Option<&Array2<f64>> {
+        self.replicate_log_fold_changes.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +237,21 @@ mod testing {
         assert_eq!(result.treatment_means(), &arr1(&[2., 3., 4., 5., 6.]));
     }
 
+    #[test]
+    fn test_with_log_fold_change() {
+        let result = super::EnrichmentResult::new(
+            arr1(&[0.1, 0.2]),
+            arr1(&[0.9, 0.8]),
+            arr1(&[1., 2.]),
+            arr1(&[2., 3.]),
+            Procedure::BenjaminiHochberg,
+        )
+        .with_log_fold_change(arr1(&[-1., 2.]));
+        assert_eq!(result.log_fold_change(), &arr1(&[-1., 2.]));
+        assert_eq!(result.fold_change(), &arr1(&[0.5, 4.]));
+        assert!(result.product()[0] < 0.);
+    }
+
     #[test]
     fn test_calculate_twosided() {
         let pvalues_low = arr1(&[0.1, 0.2, 0.3, 0.4, 0.5]);
@@ -0,0 +1,151 @@
+use super::{
+    enrichment_testing::{row_median, set_zero_to_minimum_nonzero},
+    Coefficient, EnrichmentResult,
+};
+use crate::{model::LoggedOls, utils::logging::Logger};
+use adjustp::Procedure;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use statrs::distribution::{ContinuousCDF, Normal};
+use std::f64::consts::LN_2;
+
+/// Name of the coefficient describing the change in log2 abundance per unit time
+pub const SLOPE_COEFFICIENT: &str = "slope";
+
+/// Pseudocount added to the normalized counts before the log transform
+const PSEUDOCOUNT: f64 = 0.5;
+
+/// Fits the weighted least squares slope of the log2 counts over time
+///
+/// Each observation is weighted by the inverse of its log-scale variance which is
+/// approximated from the mean-variance model by the delta method:
+///
+/// ```text
+/// var(log2(x)) ~ var(x) / (x * ln(2))^2
+/// ```
+///
+/// Returns the slope and its standard error.
+fn weighted_slope(counts: &ArrayView1<f64>, times: &Array1<f64>, model: &LoggedOls) -> (f64, f64) {
+    let counts = counts.mapv(|x| x + PSEUDOCOUNT);
+    let log_counts = counts.mapv(f64::log2);
+    let weights = (&counts * &counts * LN_2.powi(2)) / model.predict(&counts);
+
+    let w_sum = weights.sum();
+    let t_mean = (&weights * times).sum() / w_sum;
+    let t_centered = times - t_mean;
+    let sxx = (&weights * &t_centered * &t_centered).sum();
+    let slope = (&weights * &t_centered * &log_counts).sum() / sxx;
+    (slope, (1. / sxx).sqrt())
+}
+
+/// Performs enrichment testing across multiple timepoints.
+///
+/// A slope of the log2 normalized counts over time is fit for each sgRNA with weights
+/// derived from the mean-variance model, and the one-sided p-values describe a negative
+/// (depletion) or positive (enrichment) slope. The samples are expected to be sorted by
+/// time with the `n_baseline` samples of the earliest timepoint first.
+///
+/// The log2 fold change of the result is the slope and the slope estimates are reported
+/// alongside the results.
+pub fn timecourse_enrichment_testing(
+    normed_matrix: &Array2<f64>,
+    times: &[f64],
+    model: &LoggedOls,
+    n_baseline: usize,
+    correction: Procedure,
+    logger: &Logger,
+) -> EnrichmentResult {
+    logger.start_differential_abundance();
+    logger.timepoints(times);
+
+    let times = Array1::from_vec(times.to_vec());
+    let (slopes, stderrs): (Vec<f64>, Vec<f64>) = normed_matrix
+        .axis_iter(Axis(0))
+        .map(|counts| weighted_slope(&counts, &times, model))
+        .unzip();
+    let slopes = Array1::from_vec(slopes);
+    let stderrs = Array1::from_vec(stderrs);
+
+    let normal = Normal::new(0., 1.).unwrap();
+    let zscores = &slopes / &stderrs;
+    let pvalues_low = set_zero_to_minimum_nonzero(&zscores.mapv(|z| normal.cdf(z)));
+    let pvalues_high = set_zero_to_minimum_nonzero(&zscores.mapv(|z| normal.sf(z)));
+    let pvalues = zscores.mapv(|z| 2. * normal.cdf(-z.abs()));
+
+    // the final timepoint is compared against the baseline for the group means
+    let last_time = times[times.len() - 1];
+    let last = (0..times.len())
+        .filter(|idx| times[*idx] == last_time)
+        .collect::<Vec<_>>();
+    let baseline_means =
+        row_median(&normed_matrix.select(Axis(1), &(0..n_baseline).collect::<Vec<_>>()));
+    let final_means = row_median(&normed_matrix.select(Axis(1), &last));
+
+    EnrichmentResult::new(
+        pvalues_low,
+        pvalues_high,
+        baseline_means,
+        final_means,
+        correction,
+    )
+    .with_log_fold_change(slopes.clone())
+    .with_coefficients(vec![Coefficient::new(
+        SLOPE_COEFFICIENT.to_string(),
+        slopes,
+        stderrs,
+        pvalues,
+    )])
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::model::ModelChoice;
+    use ndarray::array;
+
+    fn build_model() -> LoggedOls {
+        let means = Array1::linspace(10., 1000., 50);
+        let vars = means.mapv(|x: f64| x + 0.1 * x.powi(2));
+        LoggedOls::fit(&means, &vars, &ModelChoice::Ols, &Logger::new_silent())
+    }
+
+    #[test]
+    fn test_weighted_slope() {
+        let model = build_model();
+        let times = array![0., 7., 14.];
+
+        // counts halving every 7 units of time
+        let counts = array![800. - PSEUDOCOUNT, 400. - PSEUDOCOUNT, 200. - PSEUDOCOUNT];
+        let (slope, stderr) = weighted_slope(&counts.view(), &times, &model);
+        assert!((slope + 1. / 7.).abs() < 1e-10);
+        assert!(stderr > 0.);
+
+        // constant counts have no slope
+        let counts = array![100., 100., 100.];
+        let (slope, _) = weighted_slope(&counts.view(), &times, &model);
+        assert!(slope.abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_timecourse_enrichment_testing() {
+        let model = build_model();
+        let normed_matrix = array![
+            [500., 510., 250., 240., 120., 130.],
+            [500., 510., 505., 495., 500., 490.],
+            [500., 510., 1000., 990., 2000., 2100.],
+        ];
+        let times = [0., 0., 7., 7., 14., 14.];
+        let result = timecourse_enrichment_testing(
+            &normed_matrix,
+            &times,
+            &model,
+            2,
+            Procedure::BenjaminiHochberg,
+            &Logger::new_silent(),
+        );
+        assert!(result.log_fold_change()[0] < 0.);
+        assert!(result.log_fold_change()[2] > 0.);
+        assert!(result.pvalues_low()[0] < result.pvalues_low()[1]);
+        assert!(result.pvalues_high()[2] < result.pvalues_high()[1]);
+        assert_eq!(result.coefficients()[0].name(), SLOPE_COEFFICIENT);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{bail, Result};
+use hashbrown::HashSet;
+use polars::prelude::*;
+use std::path::PathBuf;
+
+use super::load_string_dataframe;
+
+/// Column name of the contrast identifiers in the contrasts file
+const NAME_COLUMN: &str = "contrast";
+
+/// Column name of the control labels in the contrasts file
+const CONTROL_COLUMN: &str = "control";
+
+/// Column name of the treatment labels in the contrasts file
+const TREATMENT_COLUMN: &str = "treatment";
+
+/// Separator between multiple labels of a single contrast group
+const LABEL_SEPARATOR: char = ',';
+
+/// A single control-vs-treatment comparison
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contrast {
+    name: String,
+    controls: Vec<String>,
+    treatments: Vec<String>,
+}
+impl Contrast {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Control labels (regular expressions or design conditions)
+    pub fn controls(&self) -> &[String] {
+        &self.controls
+    }
+
+    /// Treatment labels (regular expressions or design conditions)
+    pub fn treatments(&self) -> &[String] {
+        &self.treatments
+    }
+}
+
+/// A set of comparisons to be run against the same count matrix.
+///
+/// The contrasts file is a delimited text, parquet, or arrow ipc table with a header and the
+/// columns `contrast`, `control`, and `treatment`. Multiple labels can be provided for a
+/// group by separating them with commas (quoted in a comma-delimited file). Labels are
+/// interpreted the same as the `-c` and `-t` arguments.
+#[derive(Debug, Clone)]
+pub struct Contrasts {
+    contrasts: Vec<Contrast>,
+}
+impl Contrasts {
+    /// Reads a contrasts file from the provided path
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        Self::from_dataframe(&load_string_dataframe(path)?)
+    }
+
+    /// Builds the contrasts from a dataframe with string-valued columns
+    pub fn from_dataframe(frame: &DataFrame) -> Result<Self> {
+        for required in [NAME_COLUMN, CONTROL_COLUMN, TREATMENT_COLUMN] {
+            if !frame
+                .get_column_names()
+                .iter()
+                .any(|x| x.as_str() == required)
+            {
+                bail!("Contrasts file is missing the required column: {required}")
+            }
+        }
+        let names = frame.column(NAME_COLUMN)?.cast(&DataType::String)?;
+        let controls = frame.column(CONTROL_COLUMN)?.cast(&DataType::String)?;
+        let treatments = frame.column(TREATMENT_COLUMN)?.cast(&DataType::String)?;
+
+        let mut contrasts = Vec::with_capacity(frame.height());
+        for (idx, ((name, control), treatment)) in names
+            .str()?
+            .iter()
+            .zip(controls.str()?.iter())
+            .zip(treatments.str()?.iter())
+            .enumerate()
+        {
+            let name = name.unwrap_or_default().trim().to_string();
+            let controls = split_labels(control.unwrap_or_default());
+            let treatments = split_labels(treatment.unwrap_or_default());
+            if name.is_empty() || controls.is_empty() || treatments.is_empty() {
+                bail!(
+                    "Contrasts file row {} is missing a contrast, control, or treatment value",
+                    idx + 1
+                )
+            }
+            if name.contains(std::path::is_separator) {
+                bail!("Contrast name ({name}) cannot contain a path separator")
+            }
+            contrasts.push(Contrast {
+                name,
+                controls,
+                treatments,
+            });
+        }
+        if contrasts.is_empty() {
+            bail!("Contrasts file does not describe any contrasts")
+        }
+
+        let contrasts = Self { contrasts };
+        contrasts.validate_unique()?;
+        Ok(contrasts)
+    }
+
+    /// Ensures that every contrast name is only used once
+    fn validate_unique(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for contrast in &self.contrasts {
+            if !seen.insert(contrast.name()) {
+                bail!(
+                    "Contrast ({}) is listed multiple times in the contrasts file",
+                    contrast.name()
+                )
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contrasts(&self) -> &[Contrast] {
+        &self.contrasts
+    }
+}
+
+/// Splits a comma-separated group of labels
+fn split_labels(value: &str) -> Vec<String> {
+    value
+        .split(LABEL_SEPARATOR)
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    #[test]
+    fn test_contrasts_from_dataframe() -> Result<()> {
+        let frame = df!(
+            "contrast" => &["drug_vs_dmso", "late_vs_early"],
+            "control" => &["dmso", "T0, T7"],
+            "treatment" => &["drug", "T14"],
+        )?;
+        let contrasts = Contrasts::from_dataframe(&frame)?;
+        assert_eq!(contrasts.contrasts().len(), 2);
+        let late = &contrasts.contrasts()[1];
+        assert_eq!(late.name(), "late_vs_early");
+        assert_eq!(late.controls(), &["T0".to_string(), "T7".to_string()]);
+        assert_eq!(late.treatments(), &["T14".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_contrasts_duplicate_names() {
+        let frame = df!(
+            "contrast" => &["a", "a"],
+            "control" => &["low", "low"],
+            "treatment" => &["high", "mid"],
+        )
+        .unwrap();
+        assert!(Contrasts::from_dataframe(&frame).is_err());
+    }
+
+    #[test]
+    fn test_contrasts_invalid_values() {
+        let frame = df!(
+            "contrast" => &["a", "b/c"],
+            "control" => &["low", "low"],
+            "treatment" => &["high", "mid"],
+        )
+        .unwrap();
+        assert!(Contrasts::from_dataframe(&frame).is_err());
+
+        let frame = df!(
+            "contrast" => &["a"],
+            "control" => &[" , "],
+            "treatment" => &["high"],
+        )
+        .unwrap();
+        assert!(Contrasts::from_dataframe(&frame).is_err());
+    }
+}
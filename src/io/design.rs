@@ -0,0 +1,400 @@
+use anyhow::{bail, Result};
+use hashbrown::HashSet;
+use polars::prelude::*;
+use std::path::PathBuf;
+
+use super::load_string_dataframe;
+
+/// Column name of the sample identifiers in the design file
+const SAMPLE_COLUMN: &str = "sample";
+
+/// Column name of the experimental condition in the design file
+const CONDITION_COLUMN: &str = "condition";
+
+/// Column name of the (optional) replicate identifier in the design file
+const REPLICATE_COLUMN: &str = "replicate";
+
+/// A single row of the design file describing one column of the count matrix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesignSample {
+    sample: String,
+    condition: String,
+    replicate: Option<String>,
+    covariates: Vec<String>,
+}
+impl DesignSample {
+    pub fn sample(&self) -> &str {
+        &self.sample
+    }
+
+    pub fn condition(&self) -> &str {
+        &self.condition
+    }
+
+    pub fn replicate(&self) -> Option<&str> {
+        self.replicate.as_deref()
+    }
+
+    pub fn covariates(&self) -> &[String] {
+        &self.covariates
+    }
+}
+
+/// A sample sheet mapping count matrix columns to their condition, replicate, and any
+/// additional covariates.
+///
+/// The design file is a delimited text, parquet, or arrow ipc table with a header. The
+/// `sample` and `condition` columns are required, the `replicate` column is optional, and
+/// every other column is treated as a covariate.
+#[derive(Debug, Clone)]
+pub struct Design {
+    samples: Vec<DesignSample>,
+    covariate_names: Vec<String>,
+}
+impl Design {
+    /// Reads a design file from the provided path
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        Self::from_dataframe(&load_string_dataframe(path)?)
+    }
+
+    /// Builds a design from a dataframe with string-valued columns
+    pub fn from_dataframe(frame: &DataFrame) -> Result<Self> {
+        let headers = frame
+            .get_column_names()
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        for required in [SAMPLE_COLUMN, CONDITION_COLUMN] {
+            if !headers.iter().any(|x| x == required) {
+                bail!("Design file is missing the required column: {required}")
+            }
+        }
+
+        let sample_values = string_values(frame, SAMPLE_COLUMN)?;
+        let condition_values = string_values(frame, CONDITION_COLUMN)?;
+        let replicate_values = if headers.iter().any(|x| x == REPLICATE_COLUMN) {
+            Some(string_values(frame, REPLICATE_COLUMN)?)
+        } else {
+            None
+        };
+        let covariate_names = headers
+            .iter()
+            .filter(|x| ![SAMPLE_COLUMN, CONDITION_COLUMN, REPLICATE_COLUMN].contains(&x.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let covariate_values = covariate_names
+            .iter()
+            .map(|name| string_values(frame, name))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut samples = Vec::with_capacity(frame.height());
+        for idx in 0..frame.height() {
+            let (Some(sample), Some(condition)) =
+                (sample_values[idx].clone(), condition_values[idx].clone())
+            else {
+                bail!(
+                    "Design file row {} is missing a sample or condition value",
+                    idx + 1
+                )
+            };
+            let replicate = replicate_values.as_ref().and_then(|x| x[idx].clone());
+            let covariates = covariate_values
+                .iter()
+                .map(|x| x[idx].clone().unwrap_or_default())
+                .collect();
+            samples.push(DesignSample {
+                sample,
+                condition,
+                replicate,
+                covariates,
+            });
+        }
+
+        let design = Self {
+            samples,
+            covariate_names,
+        };
+        design.validate_unique()?;
+        Ok(design)
+    }
+
+    /// Ensures that every sample in the design is only described once
+    fn validate_unique(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for sample in &self.samples {
+            if !seen.insert(sample.sample()) {
+                bail!(
+                    "Sample ({}) is listed multiple times in the design file",
+                    sample.sample()
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the design against the headers of the count matrix
+    ///
+    /// Every sample in the design must be a column of the count matrix and must not be
+    /// one of the first two (sgRNA and gene) columns.
+    pub fn validate(&self, frame: &DataFrame) -> Result<()> {
+        let headers = frame.get_column_names();
+        let missing = self
+            .samples
+            .iter()
+            .filter(|x| !headers.iter().any(|h| h.as_str() == x.sample()))
+            .map(|x| x.sample().to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            bail!(
+                "The following design samples were not found in the count matrix: {}",
+                missing.join(", ")
+            )
+        }
+        for reserved in headers.iter().take(2) {
+            if self.samples.iter().any(|x| x.sample() == reserved.as_str()) {
+                bail!("Design sample ({reserved}) refers to an sgRNA or gene column of the count matrix")
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the samples belonging to any of the provided conditions in design order
+    pub fn select_conditions(&self, conditions: &[String]) -> Result<Vec<String>> {
+        for condition in conditions {
+            if !self.samples.iter().any(|x| x.condition() == condition) {
+                bail!("Condition ({condition}) not found in the design file")
+            }
+        }
+        Ok(self
+            .samples
+            .iter()
+            .filter(|x| conditions.iter().any(|c| c == x.condition()))
+            .map(|x| x.sample().to_string())
+            .collect())
+    }
+
+    /// Returns the samples of the provided conditions (or all samples if none are
+    /// provided) with their numeric time from the provided covariate column, sorted by time
+    pub fn select_times(
+        &self,
+        conditions: &[String],
+        time_column: &str,
+    ) -> Result<Vec<(String, f64)>> {
+        let Some(time_idx) = self.covariate_names.iter().position(|x| x == time_column) else {
+            bail!("Time column ({time_column}) is not a column of the design file")
+        };
+        let samples = if conditions.is_empty() {
+            self.samples
+                .iter()
+                .map(|x| x.sample().to_string())
+                .collect()
+        } else {
+            self.select_conditions(conditions)?
+        };
+        let mut times = samples
+            .into_iter()
+            .map(|sample| {
+                let value = &self.get(&sample).unwrap().covariates()[time_idx];
+                match value.parse::<f64>() {
+                    Ok(time) if time.is_finite() => Ok((sample, time)),
+                    _ => bail!(
+                        "Sample ({sample}) has a non-numeric time ({value}) in the design file"
+                    ),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        times.sort_by(|a, b| a.1.total_cmp(&b.1));
+        Ok(times)
+    }
+
+    /// Returns the design entry of the provided sample if it exists
+    pub fn get(&self, sample: &str) -> Option<&DesignSample> {
+        self.samples.iter().find(|x| x.sample() == sample)
+    }
+
+    pub fn samples(&self) -> &[DesignSample] {
+        &self.samples
+    }
+
+    pub fn covariate_names(&self) -> &[String] {
+        &self.covariate_names
+    }
+}
+
+/// Matches each treatment sample to a control sample
+///
+/// Samples are matched on their design replicate if the design provides replicates,
+/// otherwise controls and treatments are matched by their order. Returns
+/// `(control, treatment)` index pairs into the provided label slices.
+pub fn pair_samples(
+    design: Option<&Design>,
+    controls: &[String],
+    treatments: &[String],
+) -> Result<Vec<(usize, usize)>> {
+    let replicates = design.filter(|d| d.samples().iter().any(|x| x.replicate().is_some()));
+    if let Some(design) = replicates {
+        let replicate = |sample: &String| -> Result<&str> {
+            design
+                .get(sample)
+                .and_then(|x| x.replicate())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Sample ({sample}) is missing a replicate in the design file")
+                })
+        };
+        let control_replicates = controls.iter().map(replicate).collect::<Result<Vec<_>>>()?;
+        treatments
+            .iter()
+            .enumerate()
+            .map(|(t_idx, sample)| {
+                let rep = replicate(sample)?;
+                let matches = control_replicates
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == rep)
+                    .map(|(c_idx, _)| c_idx)
+                    .collect::<Vec<_>>();
+                match matches.as_slice() {
+                    [c_idx] => Ok((*c_idx, t_idx)),
+                    [] => bail!("No control sample shares replicate ({rep}) with treatment ({sample})"),
+                    _ => bail!("Multiple control samples share replicate ({rep}) with treatment ({sample})"),
+                }
+            })
+            .collect()
+    } else {
+        if controls.len() != treatments.len() {
+            bail!(
+                "Paired testing without design replicates requires an equal number of controls ({}) and treatments ({})",
+                controls.len(),
+                treatments.len()
+            )
+        }
+        Ok((0..controls.len()).map(|idx| (idx, idx)).collect())
+    }
+}
+
+/// Collects the values of a column as optional strings
+fn string_values(frame: &DataFrame, name: &str) -> Result<Vec<Option<String>>> {
+    let column = frame.column(name)?.cast(&DataType::String)?;
+    Ok(column
+        .str()?
+        .iter()
+        .map(|x| x.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))
+        .collect())
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    fn build_design_frame() -> DataFrame {
+        df!(
+            "sample" => &["low_1", "high_1", "low_2", "high_2"],
+            "condition" => &["low", "high", "low", "high"],
+            "replicate" => &["A", "A", "B", "B"],
+            "batch" => &["1", "1", "2", "2"],
+        )
+        .unwrap()
+    }
+
+    fn build_count_frame() -> DataFrame {
+        df!(
+            "sgrna" => &["s1", "s2"],
+            "gene" => &["g1", "g1"],
+            "low_1" => &[1i64, 2],
+            "high_1" => &[1i64, 2],
+            "low_2" => &[1i64, 2],
+            "high_2" => &[1i64, 2],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_design_from_dataframe() -> Result<()> {
+        let design = Design::from_dataframe(&build_design_frame())?;
+        assert_eq!(design.samples().len(), 4);
+        assert_eq!(design.covariate_names(), &["batch".to_string()]);
+        let sample = design.get("high_2").unwrap();
+        assert_eq!(sample.condition(), "high");
+        assert_eq!(sample.replicate(), Some("B"));
+        assert_eq!(sample.covariates(), &["2".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_select_conditions() -> Result<()> {
+        let design = Design::from_dataframe(&build_design_frame())?;
+        let controls = design.select_conditions(&["low".to_string()])?;
+        assert_eq!(controls, vec!["low_1", "low_2"]);
+        assert!(design.select_conditions(&["missing".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_validate() -> Result<()> {
+        let design = Design::from_dataframe(&build_design_frame())?;
+        design.validate(&build_count_frame())?;
+
+        let frame = build_count_frame().drop("high_2")?;
+        assert!(design.validate(&frame).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pair_samples_by_replicate() -> Result<()> {
+        let design = Design::from_dataframe(&build_design_frame())?;
+        let controls = vec!["low_2".to_string(), "low_1".to_string()];
+        let treatments = vec!["high_1".to_string(), "high_2".to_string()];
+        let pairs = pair_samples(Some(&design), &controls, &treatments)?;
+        assert_eq!(pairs, vec![(1, 0), (0, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pair_samples_by_order() -> Result<()> {
+        let controls = vec!["low_1".to_string(), "low_2".to_string()];
+        let treatments = vec!["high_1".to_string(), "high_2".to_string()];
+        let pairs = pair_samples(None, &controls, &treatments)?;
+        assert_eq!(pairs, vec![(0, 0), (1, 1)]);
+        assert!(pair_samples(None, &controls, &treatments[..1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_select_times() -> Result<()> {
+        let frame = df!(
+            "sample" => &["d7", "d0", "d14", "x"],
+            "condition" => &["t7", "t0", "t14", "other"],
+            "time" => &["7", "0", "14", "NA"],
+        )?;
+        let design = Design::from_dataframe(&frame)?;
+        let conditions = vec!["t14".to_string(), "t0".to_string(), "t7".to_string()];
+        let times = design.select_times(&conditions, "time")?;
+        assert_eq!(
+            times,
+            vec![
+                ("d0".to_string(), 0.),
+                ("d7".to_string(), 7.),
+                ("d14".to_string(), 14.)
+            ]
+        );
+        assert!(design.select_times(&[], "time").is_err());
+        assert!(design.select_times(&conditions, "missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_missing_condition_column() {
+        let frame = df!("sample" => &["a", "b"]).unwrap();
+        assert!(Design::from_dataframe(&frame).is_err());
+    }
+
+    #[test]
+    fn test_design_duplicate_samples() {
+        let frame = df!(
+            "sample" => &["a", "a"],
+            "condition" => &["low", "high"],
+        )
+        .unwrap();
+        assert!(Design::from_dataframe(&frame).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::Result;
 use polars::prelude::*;
 use std::{fs::File, io::BufWriter};
 
+use super::{write_frame, OutputFormat};
 use crate::{
     aggregation::{AggregationResult, GeneAggregation},
     utils::{config::Configuration, logging::Logger},
@@ -24,16 +25,16 @@ fn build_gene_frame(results: &AggregationResult) -> Result<DataFrame, PolarsErro
     )
 }
 
-pub fn write_gene_frame(results: &AggregationResult, prefix: &str) -> Result<(), PolarsError> {
+pub fn write_gene_frame(
+    results: &AggregationResult,
+    prefix: &str,
+    format: OutputFormat,
+) -> Result<(), PolarsError> {
     let mut df = build_gene_frame(results)?;
     df.sort_in_place(["fdr"], Default::default())?;
-    let writer = File::create(format!("{}.gene_results.tsv", prefix)).map(BufWriter::new)?;
-    CsvWriter::new(writer)
-        .with_separator(b'\t')
-        .include_header(true)
-        .with_quote_style(QuoteStyle::Never)
-        .with_float_scientific(Some(true))
-        .finish(&mut df)
+    let writer = File::create(format!("{}.gene_results.{}", prefix, format.extension()))
+        .map(BufWriter::new)?;
+    write_frame(writer, &mut df, format)
 }
 
 pub fn write_hit_list(
@@ -85,6 +86,25 @@ pub fn write_hit_list(
             let mask = df.column("fdr")?.lt(*fdr)?;
             df.filter(&mask)
         }
+        GeneAggregation::Bagel {
+            n_bootstraps: _,
+            fdr,
+        }
+        | GeneAggregation::DrugZ {
+            half_window: _,
+            fdr,
+        }
+        | GeneAggregation::Fisher { fdr }
+        | GeneAggregation::Stouffer { weights: _, fdr }
+        | GeneAggregation::Acat { fdr }
+        | GeneAggregation::SecondBest {
+            token: _,
+            n_draws: _,
+            fdr,
+        } => {
+            let mask = df.column("fdr")?.lt(*fdr)?;
+            df.filter(&mask)
+        }
     }?;
 
     let num_total = df.height();
@@ -103,11 +123,12 @@ pub fn write_hit_list(
     logger.hit_list(num_total, num_enrichments, num_depletions);
 
     df.sort_in_place(["fdr"], Default::default())?;
-    let writer = File::create(format!("{}.hit_list.tsv", config.prefix())).map(BufWriter::new)?;
-    CsvWriter::new(writer)
-        .with_separator(b'\t')
-        .include_header(true)
-        .with_quote_style(QuoteStyle::Never)
-        .with_float_scientific(Some(true))
-        .finish(&mut df)
+    let format = *config.output_format();
+    let writer = File::create(format!(
+        "{}.hit_list.{}",
+        config.prefix(),
+        format.extension()
+    ))
+    .map(BufWriter::new)?;
+    write_frame(writer, &mut df, format)
 }
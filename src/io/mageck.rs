@@ -0,0 +1,169 @@
+use anyhow::Result;
+use hashbrown::HashMap;
+use polars::prelude::*;
+use std::{fs::File, io::BufWriter};
+
+use super::{write_frame, OutputFormat};
+use crate::{aggregation::AggregationResult, cli::SgrnaColumns};
+
+/// Column name of the sgRNA identifiers in MAGeCK count tables
+pub const MAGECK_SGRNA_COLUMN: &str = "sgRNA";
+
+/// Column name of the gene identifiers in MAGeCK count tables and sgRNA summaries
+pub const MAGECK_GENE_COLUMN: &str = "Gene";
+
+/// Column names of a MAGeCK `sgrna_summary.txt` mapped to their `SgrnaColumns` counterpart
+const MAGECK_SUMMARY_COLUMNS: [(&str, &str); 4] = [
+    ("pvalue_low", "p.low"),
+    ("pvalue_high", "p.high"),
+    ("control_mean", "control_mean"),
+    ("treatment_mean", "treat_mean"),
+];
+
+/// Checks whether the dataframe has a column with the provided name
+fn has_column(frame: &DataFrame, name: &str) -> bool {
+    frame.get_column_names().iter().any(|x| x.as_str() == name)
+}
+
+/// Returns the MAGeCK sgRNA and gene column names if the dataframe is a MAGeCK count
+/// table
+pub fn mageck_count_columns(frame: &DataFrame) -> Option<(&'static str, &'static str)> {
+    if has_column(frame, MAGECK_SGRNA_COLUMN) && has_column(frame, MAGECK_GENE_COLUMN) {
+        Some((MAGECK_SGRNA_COLUMN, MAGECK_GENE_COLUMN))
+    } else {
+        None
+    }
+}
+
+/// Maps the columns of a MAGeCK `sgrna_summary.txt` onto the sgRNA columns.
+///
+/// Each configured column that is missing from the dataframe is replaced by its MAGeCK
+/// counterpart if that column is present. Returns whether any column was replaced.
+pub fn map_mageck_summary_columns(frame: &DataFrame, columns: &mut SgrnaColumns) -> bool {
+    let mageck = MAGECK_SUMMARY_COLUMNS
+        .iter()
+        .copied()
+        .collect::<HashMap<_, _>>();
+    let mut mapped = false;
+    for (key, column) in [
+        ("pvalue_low", &mut columns.pvalue_low),
+        ("pvalue_high", &mut columns.pvalue_high),
+        ("control_mean", &mut columns.control_mean),
+        ("treatment_mean", &mut columns.treatment_mean),
+        ("gene", &mut columns.gene),
+    ] {
+        let replacement = mageck.get(key).copied().unwrap_or(MAGECK_GENE_COLUMN);
+        if !has_column(frame, column) && has_column(frame, replacement) {
+            *column = replacement.to_string();
+            mapped = true;
+        }
+    }
+    mapped
+}
+
+/// Calculates the 1-based rank of each value in ascending order
+fn ascending_ranks(values: &[f64]) -> Vec<u32> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0; values.len()];
+    order
+        .iter()
+        .enumerate()
+        .for_each(|(rank, idx)| ranks[*idx] = rank as u32 + 1);
+    ranks
+}
+
+/// Builds a dataframe with the columns of a MAGeCK `gene_summary.txt`
+fn build_gene_summary(
+    results: &AggregationResult,
+    sgrna_genes: &[String],
+) -> Result<DataFrame, PolarsError> {
+    let mut num_sgrnas = HashMap::new();
+    sgrna_genes
+        .iter()
+        .for_each(|gene| *num_sgrnas.entry(gene.as_str()).or_insert(0u32) += 1);
+    let num = results
+        .genes()
+        .iter()
+        .map(|gene| num_sgrnas.get(gene.as_str()).copied().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let lfc = results.gene_log2_fc().to_vec();
+
+    df!(
+        "id" => results.genes(),
+        "num" => num,
+        "neg|score" => results.score_low().to_vec(),
+        "neg|p-value" => results.pvalues_low().to_vec(),
+        "neg|fdr" => results.fdr_low().to_vec(),
+        "neg|rank" => ascending_ranks(&results.pvalues_low().to_vec()),
+        "neg|lfc" => lfc.clone(),
+        "pos|score" => results.score_high().to_vec(),
+        "pos|p-value" => results.pvalues_high().to_vec(),
+        "pos|fdr" => results.fdr_high().to_vec(),
+        "pos|rank" => ascending_ranks(&results.pvalues_high().to_vec()),
+        "pos|lfc" => lfc,
+    )
+}
+
+/// Writes the gene results with the columns of a MAGeCK `gene_summary.txt` to
+/// `<prefix>.gene_summary.txt` (sorted by the depletion rank)
+pub fn write_gene_summary(
+    results: &AggregationResult,
+    sgrna_genes: &[String],
+    prefix: &str,
+) -> Result<(), PolarsError> {
+    let mut df = build_gene_summary(results, sgrna_genes)?;
+    df.sort_in_place(["neg|rank"], Default::default())?;
+    let writer = File::create(format!("{}.gene_summary.txt", prefix)).map(BufWriter::new)?;
+    write_frame(writer, &mut df, OutputFormat::Tsv)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    #[test]
+    fn test_map_mageck_summary_columns() -> Result<()> {
+        let frame = df!(
+            "sgrna" => &["s1"],
+            "Gene" => &["g1"],
+            "control_mean" => &[1.0],
+            "treat_mean" => &[2.0],
+            "p.low" => &[0.1],
+            "p.high" => &[0.9],
+        )?;
+        let mut columns = SgrnaColumns {
+            pvalue_low: "pvalue_low".to_string(),
+            pvalue_high: "pvalue_high".to_string(),
+            control_mean: "control".to_string(),
+            treatment_mean: "treatment".to_string(),
+            sgrna: "sgrna".to_string(),
+            gene: "gene".to_string(),
+        };
+        assert!(map_mageck_summary_columns(&frame, &mut columns));
+        assert_eq!(columns.pvalue_low, "p.low");
+        assert_eq!(columns.pvalue_high, "p.high");
+        assert_eq!(columns.control_mean, "control_mean");
+        assert_eq!(columns.treatment_mean, "treat_mean");
+        assert_eq!(columns.sgrna, "sgrna");
+        assert_eq!(columns.gene, "Gene");
+
+        // already matching columns are not remapped
+        assert!(!map_mageck_summary_columns(&frame, &mut columns));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mageck_count_columns() -> Result<()> {
+        let frame = df!("sgRNA" => &["s1"], "Gene" => &["g1"], "low_1" => &[1i64])?;
+        assert_eq!(mageck_count_columns(&frame), Some(("sgRNA", "Gene")));
+        let frame = df!("sgrna" => &["s1"], "gene" => &["g1"])?;
+        assert_eq!(mageck_count_columns(&frame), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ascending_ranks() {
+        assert_eq!(ascending_ranks(&[0.5, 0.1, 0.9]), vec![2, 1, 3]);
+    }
+}
@@ -1,12 +1,28 @@
+mod contrasts;
+mod design;
 mod gene_frame;
+mod mageck;
 mod screenviz;
+mod sgrna_efficacy;
 mod sgrna_frame;
+mod size_factors;
 mod utils;
+mod validation;
 
+pub use contrasts::{Contrast, Contrasts};
+pub use design::{pair_samples, Design, DesignSample};
 pub use gene_frame::{write_gene_frame, write_hit_list};
+pub use mageck::{map_mageck_summary_columns, write_gene_summary};
 pub use screenviz::Screenviz;
+pub use sgrna_efficacy::SgrnaEfficacy;
 pub use sgrna_frame::write_sgrna_dataframe;
+pub use size_factors::{write_size_factors, SizeFactors};
 pub use utils::{
-    build_regex_set, get_string_column, load_dataframe, match_headers_from_regex_set, to_ndarray,
-    validate_ntc, write_tsv,
+    build_regex_set, get_annotation_columns, get_named_string_column, get_string_column,
+    is_non_targeting, load_dataframe, load_sgrna_list, load_string_dataframe,
+    match_headers_from_regex_set, open_input, select_group_labels, select_sample_labels,
+    to_ndarray, validate_ntc, write_frame, write_table, OutputFormat, STDIN_PATH,
+};
+pub use validation::{
+    count_columns, validate_counts, Severity, ValidationCheck, ValidationIssue, ValidationReport,
 };
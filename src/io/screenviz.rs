@@ -16,6 +16,12 @@ enum Method {
     IncProd,
     IncPvalue,
     GeoPAGG,
+    Bagel,
+    DrugZ,
+    Fisher,
+    Stouffer,
+    Acat,
+    SecondBest,
 }
 impl Display for Method {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -24,6 +30,12 @@ impl Display for Method {
             Method::IncProd => write!(f, "inc-product"),
             Method::IncPvalue => write!(f, "inc-pvalue"),
             Method::GeoPAGG => write!(f, "geopagg"),
+            Method::Bagel => write!(f, "bagel"),
+            Method::DrugZ => write!(f, "drugz"),
+            Method::Fisher => write!(f, "fisher"),
+            Method::Stouffer => write!(f, "stouffer"),
+            Method::Acat => write!(f, "acat"),
+            Method::SecondBest => write!(f, "second-best"),
         }
     }
 }
@@ -68,6 +80,22 @@ impl Screenviz {
                 use_product: _,
                 zscore_threshold: _,
             } => Method::GeoPAGG,
+            GeneAggregation::Bagel {
+                n_bootstraps: _,
+                fdr: _,
+            } => Method::Bagel,
+            GeneAggregation::DrugZ {
+                half_window: _,
+                fdr: _,
+            } => Method::DrugZ,
+            GeneAggregation::Fisher { fdr: _ } => Method::Fisher,
+            GeneAggregation::Stouffer { weights: _, fdr: _ } => Method::Stouffer,
+            GeneAggregation::Acat { fdr: _ } => Method::Acat,
+            GeneAggregation::SecondBest {
+                token: _,
+                n_draws: _,
+                fdr: _,
+            } => Method::SecondBest,
         };
         let gene = "gene".to_string();
         let x = "log_fold_change".to_string();
@@ -99,6 +127,22 @@ impl Screenviz {
                 use_product: _,
                 zscore_threshold: _,
             } => "fdr".to_string(),
+            GeneAggregation::Bagel {
+                n_bootstraps: _,
+                fdr: _,
+            }
+            | GeneAggregation::DrugZ {
+                half_window: _,
+                fdr: _,
+            }
+            | GeneAggregation::Fisher { fdr: _ }
+            | GeneAggregation::Stouffer { weights: _, fdr: _ }
+            | GeneAggregation::Acat { fdr: _ }
+            | GeneAggregation::SecondBest {
+                token: _,
+                n_draws: _,
+                fdr: _,
+            } => "fdr".to_string(),
         };
 
         let (threshold, threshold_low, threshold_high, ntc_token) = match config.aggregation() {
@@ -127,6 +171,22 @@ impl Screenviz {
                 use_product: _,
                 zscore_threshold: _,
             } => (Some(*fdr), None, None, Some(String::from("amalgam"))),
+            GeneAggregation::Bagel {
+                n_bootstraps: _,
+                fdr,
+            }
+            | GeneAggregation::DrugZ {
+                half_window: _,
+                fdr,
+            }
+            | GeneAggregation::Fisher { fdr }
+            | GeneAggregation::Stouffer { weights: _, fdr }
+            | GeneAggregation::Acat { fdr }
+            | GeneAggregation::SecondBest {
+                token: _,
+                n_draws: _,
+                fdr,
+            } => (Some(*fdr), None, None, None),
         };
 
         Self {
@@ -145,7 +205,13 @@ impl Screenviz {
     pub fn write(&self, prefix: &str) -> Result<()> {
         let mut writer = File::create(format!("{prefix}.screenviz.yaml")).map(BufWriter::new)?;
         match self.method {
-            Method::AlphaRRA => {
+            Method::AlphaRRA
+            | Method::Bagel
+            | Method::DrugZ
+            | Method::Fisher
+            | Method::Stouffer
+            | Method::Acat
+            | Method::SecondBest => {
                 writeln!(writer, "method: {}", self.method)?;
                 writeln!(writer, "gene: {}", self.gene)?;
                 writeln!(writer, "x: {}", self.x)?;
@@ -229,6 +295,18 @@ mod testing {
             .build()
     }
 
+    fn build_config_drugz<'a>() -> Configuration<'a> {
+        let aggregation = GeneAggregation::DrugZ {
+            half_window: 500,
+            fdr: 0.1,
+        };
+        let prefix = "results";
+        Configuration::builder()
+            .aggregation(aggregation)
+            .prefix(prefix)
+            .build()
+    }
+
     fn build_results_rra() -> AggregationResult {
         let genes = vec!["gene1".to_string(), "gene2".to_string()];
         let gene_fc = Array1::from(vec![1.0, 2.0]);
@@ -347,4 +425,18 @@ mod testing {
         assert_eq!(screenviz.threshold_high, None);
         assert_eq!(screenviz.ntc_token, None);
     }
+
+    #[test]
+    fn test_screenviz_drugz() {
+        let config = build_config_drugz();
+        let results = build_results_rra();
+        let screenviz = Screenviz::new(&results, &config);
+        assert_eq!(screenviz.method, Method::DrugZ);
+        assert_eq!(screenviz.method.to_string(), "drugz");
+        assert_eq!(screenviz.z, "fdr");
+        assert_eq!(screenviz.threshold, Some(0.1));
+        assert_eq!(screenviz.threshold_low, None);
+        assert_eq!(screenviz.threshold_high, None);
+        assert_eq!(screenviz.ntc_token, None);
+    }
 }
@@ -0,0 +1,102 @@
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use ndarray::Array1;
+use polars::prelude::*;
+use std::path::PathBuf;
+
+use super::load_string_dataframe;
+
+/// Column name of the sgRNA identifiers in the efficacy file
+const SGRNA_COLUMN: &str = "sgrna";
+
+/// Column name of the efficacy scores in the efficacy file
+const EFFICACY_COLUMN: &str = "efficacy";
+
+/// User-supplied on-target efficacy scores for each sgRNA.
+///
+/// The efficacy file is a delimited text, parquet, or arrow ipc table with a header and the
+/// columns `sgrna` and `efficacy`. Scores must be non-negative and are used as relative weights, so any scale
+/// is accepted. Any other columns are ignored.
+#[derive(Debug, Clone)]
+pub struct SgrnaEfficacy {
+    scores: HashMap<String, f64>,
+}
+impl SgrnaEfficacy {
+    /// Reads an efficacy file from the provided path
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        Self::from_dataframe(&load_string_dataframe(path)?)
+    }
+
+    /// Builds the efficacy scores from a dataframe with string or numeric columns
+    pub fn from_dataframe(frame: &DataFrame) -> Result<Self> {
+        for required in [SGRNA_COLUMN, EFFICACY_COLUMN] {
+            if !frame
+                .get_column_names()
+                .iter()
+                .any(|x| x.as_str() == required)
+            {
+                bail!("sgRNA efficacy file is missing the required column: {required}")
+            }
+        }
+        let sgrnas = frame.column(SGRNA_COLUMN)?.cast(&DataType::String)?;
+        let scores = frame.column(EFFICACY_COLUMN)?.cast(&DataType::String)?;
+
+        let mut map = HashMap::with_capacity(frame.height());
+        for (idx, (sgrna, score)) in sgrnas.str()?.iter().zip(scores.str()?.iter()).enumerate() {
+            let (Some(sgrna), Some(score)) = (sgrna, score) else {
+                bail!("sgRNA efficacy file has an empty value on row {}", idx + 1)
+            };
+            let Ok(value) = score.trim().parse::<f64>() else {
+                bail!("Efficacy of sgRNA ({sgrna}) is not numeric: {score}")
+            };
+            if !value.is_finite() || value < 0. {
+                bail!("Efficacy of sgRNA ({sgrna}) must be non-negative: {score}")
+            }
+            if map.insert(sgrna.to_string(), value).is_some() {
+                bail!("Duplicate sgRNA found in efficacy file: {sgrna}")
+            }
+        }
+        if map.is_empty() {
+            bail!("Provided sgRNA efficacy file is empty")
+        }
+        Ok(Self { scores: map })
+    }
+
+    /// Selects the efficacy of the provided sgRNAs in order
+    ///
+    /// sgRNAs missing from the file are given the mean efficacy of the file.
+    pub fn select(&self, sgrna_names: &[String]) -> Array1<f64> {
+        let mean = self.scores.values().sum::<f64>() / self.scores.len() as f64;
+        sgrna_names
+            .iter()
+            .map(|name| self.scores.get(name).copied().unwrap_or(mean))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    #[test]
+    fn test_sgrna_efficacy() -> Result<()> {
+        let frame = df!(
+            "sgrna" => &["g1", "g2"],
+            "efficacy" => &["0.2", "0.6"],
+            "gene" => &["a", "a"],
+        )?;
+        let efficacy = SgrnaEfficacy::from_dataframe(&frame)?;
+        let selected = efficacy.select(&["g2".to_string(), "g3".to_string(), "g1".to_string()]);
+        assert_eq!(selected.to_vec(), vec![0.6, 0.4, 0.2]);
+
+        let invalid = df!("sgrna" => &["g1"], "efficacy" => &["-1"])?;
+        assert!(SgrnaEfficacy::from_dataframe(&invalid).is_err());
+
+        let duplicate = df!("sgrna" => &["g1", "g1"], "efficacy" => &["1", "2"])?;
+        assert!(SgrnaEfficacy::from_dataframe(&duplicate).is_err());
+
+        let missing = df!("sgrna" => &["g1"])?;
+        assert!(SgrnaEfficacy::from_dataframe(&missing).is_err());
+        Ok(())
+    }
+}
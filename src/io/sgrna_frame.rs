@@ -2,6 +2,7 @@ use anyhow::Result;
 use polars::prelude::*;
 use std::{fs::File, io::BufWriter};
 
+use super::{write_frame, OutputFormat};
 use crate::enrich::EnrichmentResult;
 
 fn build_sgrna_dataframe(
@@ -10,7 +11,7 @@ fn build_sgrna_dataframe(
     adj_var: &[f64],
     sgrna_results: &EnrichmentResult,
 ) -> Result<DataFrame, PolarsError> {
-    df!(
+    let mut frame = df!(
         "sgrna" => sgrna_names,
         "gene" => gene_names,
         "base" => sgrna_results.base_means().to_vec(),
@@ -23,7 +24,30 @@ fn build_sgrna_dataframe(
         "pvalue_twosided" => sgrna_results.pvalues_twosided().to_vec(),
         "fdr" => sgrna_results.fdr().to_vec(),
         "product" => sgrna_results.product().to_vec(),
-    )
+    )?;
+    if let (Some(low), Some(high)) = (
+        sgrna_results.raw_pvalues_low(),
+        sgrna_results.raw_pvalues_high(),
+    ) {
+        frame.with_column(Series::new("pvalue_low_raw".into(), low.to_vec()))?;
+        frame.with_column(Series::new("pvalue_high_raw".into(), high.to_vec()))?;
+    }
+    for coef in sgrna_results.coefficients() {
+        let name = coef.name();
+        frame.with_column(Series::new(
+            format!("{name}_log2fc").into(),
+            coef.log2_estimate().to_vec(),
+        ))?;
+        frame.with_column(Series::new(
+            format!("{name}_se").into(),
+            coef.log2_stderr().to_vec(),
+        ))?;
+        frame.with_column(Series::new(
+            format!("{name}_pvalue").into(),
+            coef.pvalue().to_vec(),
+        ))?;
+    }
+    Ok(frame)
 }
 
 pub fn write_sgrna_dataframe(
@@ -32,14 +56,11 @@ pub fn write_sgrna_dataframe(
     adj_var: &[f64],
     sgrna_results: &EnrichmentResult,
     prefix: &str,
+    format: OutputFormat,
 ) -> Result<(), PolarsError> {
     let mut df = build_sgrna_dataframe(sgrna_names, gene_names, adj_var, sgrna_results)?;
     df.sort_in_place(["fdr"], Default::default())?;
-    let writer = File::create(format!("{}.sgrna_results.tsv", prefix)).map(BufWriter::new)?;
-    CsvWriter::new(writer)
-        .with_separator(b'\t')
-        .include_header(true)
-        .with_quote_style(QuoteStyle::Never)
-        .with_float_scientific(Some(true))
-        .finish(&mut df)
+    let writer = File::create(format!("{}.sgrna_results.{}", prefix, format.extension()))
+        .map(BufWriter::new)?;
+    write_frame(writer, &mut df, format)
 }
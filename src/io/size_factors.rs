@@ -0,0 +1,171 @@
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use ndarray::Array1;
+use polars::prelude::*;
+use std::path::PathBuf;
+
+use super::{load_string_dataframe, write_table, OutputFormat};
+use crate::norm::NormalizedCounts;
+
+/// Column name of the normalization method in the size factors file
+const METHOD_COLUMN: &str = "method";
+
+/// Column name of the sample identifiers in the size factors file
+const SAMPLE_COLUMN: &str = "sample";
+
+/// Column name of the size factors in the size factors file
+const FACTOR_COLUMN: &str = "factor";
+
+/// Column name of the fraction of zero counts of each sample in the size factors file
+const ZERO_FRACTION_COLUMN: &str = "zero_fraction";
+
+/// Column name of the reason the requested normalization was replaced in the size factors file
+const FALLBACK_REASON_COLUMN: &str = "fallback_reason";
+
+/// User-supplied size factors for each sample.
+///
+/// The size factors file is a delimited text, parquet, or arrow ipc table with a header and the columns `sample`
+/// and `factor`. The counts of each sample are divided by its factor. Any other columns
+/// (such as the `method` column of a previous run's output) are ignored.
+#[derive(Debug, Clone)]
+pub struct SizeFactors {
+    factors: HashMap<String, f64>,
+}
+impl SizeFactors {
+    /// Reads a size factors file from the provided path
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        Self::from_dataframe(&load_string_dataframe(path)?)
+    }
+
+    /// Builds the size factors from a dataframe with string or numeric columns
+    pub fn from_dataframe(frame: &DataFrame) -> Result<Self> {
+        for required in [SAMPLE_COLUMN, FACTOR_COLUMN] {
+            if !frame
+                .get_column_names()
+                .iter()
+                .any(|x| x.as_str() == required)
+            {
+                bail!("Size factors file is missing the required column: {required}")
+            }
+        }
+        let samples = frame.column(SAMPLE_COLUMN)?.cast(&DataType::String)?;
+        let factors = frame.column(FACTOR_COLUMN)?.cast(&DataType::String)?;
+
+        let mut map = HashMap::with_capacity(frame.height());
+        for (idx, (sample, factor)) in samples.str()?.iter().zip(factors.str()?.iter()).enumerate()
+        {
+            let (Some(sample), Some(factor)) = (sample, factor) else {
+                bail!("Size factors file has an empty value on row {}", idx + 1)
+            };
+            let Ok(value) = factor.trim().parse::<f64>() else {
+                bail!("Size factor of sample ({sample}) is not numeric: {factor}")
+            };
+            if !value.is_finite() || value <= 0. {
+                bail!("Size factor of sample ({sample}) must be positive: {factor}")
+            }
+            if map.insert(sample.to_string(), value).is_some() {
+                bail!("Duplicate sample found in size factors file: {sample}")
+            }
+        }
+        Ok(Self { factors: map })
+    }
+
+    /// Selects the size factors of the provided samples in order
+    pub fn select(&self, sample_labels: &[String]) -> Result<Array1<f64>> {
+        sample_labels
+            .iter()
+            .map(|label| match self.factors.get(label) {
+                Some(factor) => Ok(*factor),
+                None => bail!("Sample ({label}) is missing from the size factors file"),
+            })
+            .collect()
+    }
+}
+
+/// Writes the size factors used for each sample to `<prefix>.size_factors.tsv`
+///
+/// The fallback reason is empty unless the requested normalization method was replaced.
+pub fn write_size_factors(
+    normed: &NormalizedCounts,
+    sample_labels: &[String],
+    prefix: &str,
+) -> Result<()> {
+    let method = normed.method().name();
+    let mut df = df!(
+        METHOD_COLUMN => vec![method; sample_labels.len()],
+        SAMPLE_COLUMN => sample_labels,
+        FACTOR_COLUMN => normed.size_factors().to_vec(),
+        ZERO_FRACTION_COLUMN => normed.zero_fractions().to_vec(),
+        FALLBACK_REASON_COLUMN => vec![normed.fallback(); sample_labels.len()],
+    )?;
+    write_table(
+        &mut df,
+        Some(format!("{prefix}.size_factors.tsv")),
+        OutputFormat::Tsv,
+    )
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::{
+        norm::{normalize_counts, Normalization},
+        utils::logging::Logger,
+    };
+    use ndarray::array;
+
+    #[test]
+    fn test_size_factors() -> Result<()> {
+        let frame = df!(
+            "method" => &["total", "total"],
+            "sample" => &["s1", "s2"],
+            "factor" => &["0.5", "2.0"],
+        )?;
+        let size_factors = SizeFactors::from_dataframe(&frame)?;
+        let selected = size_factors.select(&["s2".to_string(), "s1".to_string()])?;
+        assert_eq!(selected.to_vec(), vec![2.0, 0.5]);
+        assert!(size_factors.select(&["s3".to_string()]).is_err());
+
+        let invalid = df!("sample" => &["s1"], "factor" => &["0"])?;
+        assert!(SizeFactors::from_dataframe(&invalid).is_err());
+
+        let duplicate = df!("sample" => &["s1", "s1"], "factor" => &["1", "2"])?;
+        assert!(SizeFactors::from_dataframe(&duplicate).is_err());
+
+        let missing = df!("sample" => &["s1"])?;
+        assert!(SizeFactors::from_dataframe(&missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_size_factors() -> Result<()> {
+        let logger = Logger::new_silent();
+        let matrix = array![[0., 20.], [0., 20.], [20., 0.]];
+        let normed = normalize_counts(&matrix, &Normalization::MedianRatio, None, false, &logger)?;
+        let prefix = std::env::temp_dir()
+            .join(format!("crispr_screen_size_factors_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        write_size_factors(&normed, &["s1".to_string(), "s2".to_string()], &prefix)?;
+        let path = format!("{prefix}.size_factors.tsv");
+        let written = std::fs::read_to_string(&path)?;
+        let reloaded = SizeFactors::from_path(path.clone().into());
+        std::fs::remove_file(&path)?;
+
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "method\tsample\tfactor\tzero_fraction\tfallback_reason"
+        );
+        let reason = format!("\t{}", normed.fallback().unwrap());
+        assert!(lines[1].starts_with("poscounts\ts1\t") && lines[1].ends_with(&reason));
+
+        // the written table can be supplied as size factors
+        let selected = reloaded?.select(&["s1".to_string(), "s2".to_string()])?;
+        assert!(selected
+            .iter()
+            .zip(normed.size_factors().iter())
+            .all(|(a, b)| (a - b).abs() < 1e-12));
+        Ok(())
+    }
+}
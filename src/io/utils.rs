@@ -1,19 +1,173 @@
 use anyhow::{bail, Result};
+use clap::ValueEnum;
 use hashbrown::HashSet;
 use ndarray::Array2;
 use polars::prelude::*;
 use regex::Regex;
-use std::path::PathBuf;
-use std::{fs::File, io::Write};
+use std::path::{Path, PathBuf};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+};
 
+use super::{mageck::mageck_count_columns, Design};
 use crate::aggregation::GeneAggregation;
 
-pub fn load_dataframe(path: PathBuf) -> Result<DataFrame, PolarsError> {
-    CsvReadOptions::default()
-        .with_has_header(true)
-        .with_parse_options(CsvParseOptions::default().with_separator(b'\t'))
-        .try_into_reader_with_file_path(Some(path))?
-        .finish()
+/// Path used to read an input from stdin
+pub const STDIN_PATH: &str = "-";
+
+/// Magic bytes at the start of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Magic bytes at the start of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Magic bytes at the start of a Parquet file
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// Magic bytes at the start of an Arrow IPC file
+const IPC_MAGIC: &[u8] = b"ARROW1";
+
+/// File format of the written result tables
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tab-separated text
+    #[default]
+    Tsv,
+
+    /// Apache Parquet
+    Parquet,
+
+    /// Arrow IPC (Feather v2)
+    Ipc,
+}
+impl OutputFormat {
+    /// The file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Tsv => "tsv",
+            Self::Parquet => "parquet",
+            Self::Ipc => "arrow",
+        }
+    }
+}
+
+/// Opens a buffered reader over a file (or stdin if the path is `-`) which transparently
+/// decompresses gzip and zstd inputs
+pub fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let mut reader: Box<dyn BufRead> = if path.as_os_str() == STDIN_PATH {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(reader)?,
+        )))
+    } else {
+        Ok(reader)
+    }
+}
+
+/// Reads the contents of a file (or stdin if the path is `-`) and transparently
+/// decompresses gzip and zstd inputs
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    open_input(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Delimiter of a text table detected from its header line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delimiter {
+    Tab,
+    Comma,
+    Whitespace,
+}
+
+/// Detects the delimiter from the header line, preferring tabs over commas over whitespace
+fn sniff_delimiter(contents: &[u8]) -> Delimiter {
+    let header = contents.split(|x| *x == b'\n').next().unwrap_or_default();
+    if header.contains(&b'\t') {
+        Delimiter::Tab
+    } else if header.contains(&b',') {
+        Delimiter::Comma
+    } else {
+        Delimiter::Whitespace
+    }
+}
+
+/// Rewrites runs of whitespace as single tabs so the table can be parsed as a TSV
+fn whitespace_to_tabs(contents: &[u8]) -> Vec<u8> {
+    String::from_utf8_lossy(contents)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .flat_map(|line| {
+            let mut row = line.split_whitespace().collect::<Vec<_>>().join("\t");
+            row.push('\n');
+            row.into_bytes()
+        })
+        .collect()
+}
+
+/// Loads a table from a file or stdin (`-`).
+///
+/// Parquet and Arrow IPC files are detected by their magic bytes. Otherwise the table is
+/// read as delimited text: gzip and zstd compressed inputs are decompressed transparently
+/// and the delimiter (tab, comma, or whitespace) is detected from the header line.
+pub fn load_dataframe(path: PathBuf) -> Result<DataFrame> {
+    load_table(path, false)
+}
+
+/// Loads a table the same way as [`load_dataframe`] but keeps every delimited text column
+/// as strings, so identifiers such as `01` are not reformatted by type inference
+pub fn load_string_dataframe(path: PathBuf) -> Result<DataFrame> {
+    load_table(path, true)
+}
+
+fn load_table(path: PathBuf, as_strings: bool) -> Result<DataFrame> {
+    let contents = read_input(&path)?;
+    if contents.starts_with(PARQUET_MAGIC) {
+        return Ok(ParquetReader::new(Cursor::new(contents)).finish()?);
+    }
+    if contents.starts_with(IPC_MAGIC) {
+        return Ok(IpcReader::new(Cursor::new(contents)).finish()?);
+    }
+    let (contents, separator) = match sniff_delimiter(&contents) {
+        Delimiter::Tab => (contents, b'\t'),
+        Delimiter::Comma => (contents, b','),
+        Delimiter::Whitespace => (whitespace_to_tabs(&contents), b'\t'),
+    };
+    let options = CsvReadOptions::default().with_has_header(true);
+    let options = if as_strings {
+        options.with_infer_schema_length(Some(0))
+    } else {
+        options
+    };
+    let frame = options
+        .with_parse_options(CsvParseOptions::default().with_separator(separator))
+        .into_reader_with_file_handle(Cursor::new(contents))
+        .finish()?;
+    Ok(frame)
+}
+
+/// Loads a list of sgRNA names with one name per line
+pub fn load_sgrna_list(path: PathBuf) -> Result<Vec<String>> {
+    let names = std::fs::read_to_string(path)?
+        .lines()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        bail!("Provided sgRNA list is empty")
+    }
+    Ok(names)
 }
 
 /// Build a regex set from a list of strings
@@ -56,6 +210,46 @@ pub fn match_headers_from_regex_set(
     Ok(set)
 }
 
+/// Resolves the sample labels of a single group of the count matrix
+///
+/// If a design is provided the labels are treated as condition names and the matching
+/// samples are returned in design order, otherwise the labels are treated as regular
+/// expressions over the dataframe headers.
+pub fn select_group_labels(
+    dataframe: &DataFrame,
+    design: Option<&Design>,
+    labels: &[String],
+) -> Result<Vec<String>> {
+    if let Some(design) = design {
+        design.validate(dataframe)?;
+        design.select_conditions(labels)
+    } else {
+        match_headers_from_regex_set(dataframe, &build_regex_set(labels)?)
+    }
+}
+
+/// Resolves the control and treatment sample labels of the count matrix
+///
+/// See [`select_group_labels`] for how labels are interpreted.
+pub fn select_sample_labels(
+    dataframe: &DataFrame,
+    design: Option<&Design>,
+    controls: &[String],
+    treatments: &[String],
+) -> Result<(Vec<String>, Vec<String>)> {
+    let control_labels = select_group_labels(dataframe, design, controls)?;
+    let treatment_labels = select_group_labels(dataframe, design, treatments)?;
+    if let Some(shared) = control_labels.iter().find(|x| treatment_labels.contains(x)) {
+        bail!("Sample ({shared}) was selected as both a control and a treatment")
+    }
+    Ok((control_labels, treatment_labels))
+}
+
+/// Whether an sgRNA is a non-targeting control, i.e. its name contains the token
+pub fn is_non_targeting(sgrna: &str, token: &str) -> bool {
+    sgrna.contains(token)
+}
+
 pub fn validate_ntc(sgrna_names: &[String], config: &GeneAggregation) -> Result<()> {
     match config {
         GeneAggregation::Inc {
@@ -64,8 +258,13 @@ pub fn validate_ntc(sgrna_names: &[String], config: &GeneAggregation) -> Result<
             group_size: _,
             n_draws: _,
             use_product: _,
+        }
+        | GeneAggregation::SecondBest {
+            token,
+            n_draws: _,
+            fdr: _,
         } => {
-            if sgrna_names.iter().any(|x| x.contains(token)) {
+            if sgrna_names.iter().any(|x| is_non_targeting(x, token)) {
                 Ok(())
             } else {
                 bail!("Non-Targeting Token ({token}) not found in any sgrna names - please use RRA or update the provided token.")
@@ -79,7 +278,7 @@ pub fn validate_ntc(sgrna_names: &[String], config: &GeneAggregation) -> Result<
             zscore_threshold: _,
         } => {
             if let Some(token) = token {
-                if sgrna_names.iter().any(|x| x.contains(token)) {
+                if sgrna_names.iter().any(|x| is_non_targeting(x, token)) {
                     Ok(())
                 } else {
                     bail!("Non-Targeting Token ({token}) not found in any sgrna names - please use RRA or update the provided token.")
@@ -102,6 +301,57 @@ pub fn get_string_column(dataframe: &DataFrame, idx: usize) -> Vec<String> {
         .collect()
 }
 
+/// Selects a string column by name
+///
+/// The column must exist and hold string identifiers, which catches a count column being
+/// provided by mistake.
+pub fn get_named_string_column(dataframe: &DataFrame, name: &str) -> Result<Vec<String>> {
+    let Ok(column) = dataframe.column(name) else {
+        bail!("Column ({name}) not found in the input table")
+    };
+    if !matches!(column.dtype(), DataType::String) {
+        bail!(
+            "Column ({name}) must contain string identifiers but has type {} - is it a count column?",
+            column.dtype()
+        )
+    }
+    Ok(column
+        .str()?
+        .iter()
+        .map(|x| x.unwrap_or_default().to_string())
+        .collect())
+}
+
+/// Selects the sgRNA and gene columns of a count table
+///
+/// Columns are selected by name if provided. Otherwise the `sgRNA` and `Gene` columns of
+/// a MAGeCK count table are used if present, and the first column is taken as the sgRNA
+/// and the second as the gene if not.
+pub fn get_annotation_columns(
+    dataframe: &DataFrame,
+    sgrna_column: Option<&str>,
+    gene_column: Option<&str>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let (sgrna_column, gene_column) = match (sgrna_column, gene_column) {
+        (None, None) => mageck_count_columns(dataframe).unzip(),
+        columns => columns,
+    };
+    if let (Some(sgrna), Some(gene)) = (sgrna_column, gene_column) {
+        if sgrna == gene {
+            bail!("The sgRNA and gene columns must be different: {sgrna}")
+        }
+    }
+    let sgrna_names = match sgrna_column {
+        Some(name) => get_named_string_column(dataframe, name)?,
+        None => get_string_column(dataframe, 0),
+    };
+    let gene_names = match gene_column {
+        Some(name) => get_named_string_column(dataframe, name)?,
+        None => get_string_column(dataframe, 1),
+    };
+    Ok((sgrna_names, gene_names))
+}
+
 /// Converts a DataFrame to an ndarray with f64 values.
 pub fn to_ndarray(dataframe: &DataFrame, labels: &[String]) -> PolarsResult<Array2<f64>> {
     let mut array = Array2::zeros((dataframe.height(), labels.len()));
@@ -155,15 +405,32 @@ fn match_output(path: Option<String>) -> Result<Box<dyn Write>> {
     }
 }
 
-/// Writes a dataframe to a file or stdout
-pub fn write_tsv(dataframe: &mut DataFrame, path: Option<String>) -> Result<()> {
+/// Writes a dataframe in the provided format
+pub fn write_frame<W: Write>(
+    writer: W,
+    dataframe: &mut DataFrame,
+    format: OutputFormat,
+) -> Result<(), PolarsError> {
+    match format {
+        OutputFormat::Tsv => CsvWriter::new(writer)
+            .with_separator(b'\t')
+            .include_header(true)
+            .with_quote_style(QuoteStyle::Never)
+            .with_float_scientific(Some(true))
+            .finish(dataframe),
+        OutputFormat::Parquet => ParquetWriter::new(writer).finish(dataframe).map(|_| ()),
+        OutputFormat::Ipc => IpcWriter::new(writer).finish(dataframe),
+    }
+}
+
+/// Writes a dataframe to a file or stdout in the provided format
+pub fn write_table(
+    dataframe: &mut DataFrame,
+    path: Option<String>,
+    format: OutputFormat,
+) -> Result<()> {
     let writer = match_output(path)?;
-    CsvWriter::new(writer)
-        .with_separator(b'\t')
-        .include_header(true)
-        .with_quote_style(QuoteStyle::Never)
-        .with_float_scientific(Some(true))
-        .finish(dataframe)?;
+    write_frame(writer, dataframe, format)?;
     Ok(())
 }
 
@@ -172,6 +439,110 @@ mod testing {
     use super::*;
     use anyhow::Result;
 
+    #[test]
+    fn test_get_annotation_columns() -> Result<()> {
+        let frame = df!(
+            "id" => &["1", "2"],
+            "gene" => &["g1", "g1"],
+            "sgrna" => &["s1", "s2"],
+            "low_1" => &[1i64, 2],
+        )?;
+        let (sgrnas, genes) = get_annotation_columns(&frame, Some("sgrna"), Some("gene"))?;
+        assert_eq!(sgrnas, vec!["s1", "s2"]);
+        assert_eq!(genes, vec!["g1", "g1"]);
+
+        let (sgrnas, genes) = get_annotation_columns(&frame, None, None)?;
+        assert_eq!(sgrnas, vec!["1", "2"]);
+        assert_eq!(genes, vec!["g1", "g1"]);
+
+        assert!(get_annotation_columns(&frame, Some("missing"), None).is_err());
+        assert!(get_annotation_columns(&frame, Some("low_1"), None).is_err());
+        assert!(get_annotation_columns(&frame, Some("gene"), Some("gene")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sniff_delimiter() {
+        assert_eq!(sniff_delimiter(b"sgrna\tgene\ta,b\n"), Delimiter::Tab);
+        assert_eq!(sniff_delimiter(b"sgrna,gene,a\n"), Delimiter::Comma);
+        assert_eq!(sniff_delimiter(b"sgrna  gene a\n"), Delimiter::Whitespace);
+        assert_eq!(
+            whitespace_to_tabs(b"sgrna  gene a\ns1 g1   10\n\n"),
+            b"sgrna\tgene\ta\ns1\tg1\t10\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_load_formatted_dataframe() -> Result<()> {
+        let csv = b"sgrna,gene,low_1,high_1\ns1,g1,10,20\ns2,g1,30,40\n";
+        let dir = std::env::temp_dir();
+        let stem = format!("crispr_screen_test_counts_{}", std::process::id());
+
+        let gz_path = dir.join(format!("{stem}.csv.gz"));
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz_path)?, flate2::Compression::default());
+        encoder.write_all(csv)?;
+        encoder.finish()?;
+
+        let zst_path = dir.join(format!("{stem}.csv.zst"));
+        std::fs::write(&zst_path, zstd::encode_all(csv.as_slice(), 0)?)?;
+
+        let mut frame = load_dataframe(gz_path.clone())?;
+        let parquet_path = dir.join(format!("{stem}.parquet"));
+        write_frame(
+            File::create(&parquet_path)?,
+            &mut frame,
+            OutputFormat::Parquet,
+        )?;
+        let ipc_path = dir.join(format!("{stem}.arrow"));
+        write_frame(File::create(&ipc_path)?, &mut frame, OutputFormat::Ipc)?;
+
+        let paths = [gz_path, zst_path, parquet_path, ipc_path];
+        let frames = paths
+            .iter()
+            .map(|path| load_dataframe(path.clone()))
+            .collect::<Vec<_>>();
+        let strings = load_string_dataframe(paths[0].clone());
+        for path in paths {
+            std::fs::remove_file(path)?;
+        }
+        assert_eq!(strings?.column("low_1")?.dtype(), &DataType::String);
+        for frame in frames {
+            let frame = frame?;
+            assert_eq!(frame.shape(), (2, 4));
+            assert_eq!(to_ndarray(&frame, &["high_1".to_string()])?[[1, 0]], 40.);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn select_sample_labels_from_regex() -> Result<()> {
+        let frame = df!(
+            "sgrna" => &["s1", "s2"],
+            "gene" => &["g1", "g1"],
+            "low_2" => &[1i64, 2],
+            "low_1" => &[1i64, 2],
+            "high_1" => &[1i64, 2],
+        )?;
+        let (controls, treatments) = select_sample_labels(
+            &frame,
+            None,
+            &["low_.*".to_string()],
+            &["high_1".to_string()],
+        )?;
+        assert_eq!(controls, vec!["low_1", "low_2"]);
+        assert_eq!(treatments, vec!["high_1"]);
+
+        let overlap = select_sample_labels(
+            &frame,
+            None,
+            &["low_.*".to_string()],
+            &["low_1".to_string()],
+        );
+        assert!(overlap.is_err());
+        Ok(())
+    }
+
     #[test]
     fn mixed_type_dataframe_to_ndarray() -> Result<()> {
         let frame = df!(
@@ -0,0 +1,445 @@
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use polars::prelude::*;
+use std::fmt::Display;
+
+use super::mageck::mageck_count_columns;
+
+/// Maximum number of problems listed in the error message of an invalid count matrix
+const MAX_REPORTED_ISSUES: usize = 10;
+
+/// Text values that are reported as missing rather than non-numeric counts
+const MISSING_TOKENS: [&str; 6] = ["", "NA", "N/A", "NaN", "nan", "null"];
+
+/// Severity of a problem found in the count matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The analysis cannot be run on the count matrix
+    Error,
+
+    /// The analysis can be run but the results may be affected
+    Warning,
+}
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// The checks performed on a count matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidationCheck {
+    /// The sgRNA name was already used in a previous row
+    DuplicateSgrna,
+
+    /// The count is null or NaN
+    MissingValue,
+
+    /// The count could not be parsed as a number
+    NonNumericValue,
+
+    /// The count is negative
+    NegativeCount,
+
+    /// The count is not an integer
+    FractionalCount,
+
+    /// Every count of the sample is zero
+    EmptySample,
+
+    /// The gene is only targeted by a single sgRNA
+    SingleSgrnaGene,
+
+    /// No sgRNA names contain the non-targeting control token
+    NtcCoverage,
+}
+impl ValidationCheck {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DuplicateSgrna => "duplicate-sgrna",
+            Self::MissingValue => "missing-value",
+            Self::NonNumericValue => "non-numeric-value",
+            Self::NegativeCount => "negative-count",
+            Self::FractionalCount => "fractional-count",
+            Self::EmptySample => "empty-sample",
+            Self::SingleSgrnaGene => "single-sgrna-gene",
+            Self::NtcCoverage => "ntc-coverage",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::FractionalCount | Self::SingleSgrnaGene | Self::NtcCoverage => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// A single problem found in the count matrix
+///
+/// Rows are numbered from 1 at the first sgRNA (the header is not counted).
+#[derive(Debug, Clone)]
+pub struct ValidationIssue {
+    check: ValidationCheck,
+    row: Option<usize>,
+    column: Option<String>,
+    message: String,
+}
+impl ValidationIssue {
+    fn new(
+        check: ValidationCheck,
+        row: Option<usize>,
+        column: Option<&str>,
+        message: String,
+    ) -> Self {
+        Self {
+            check,
+            row: row.map(|idx| idx + 1),
+            column: column.map(|x| x.to_string()),
+            message,
+        }
+    }
+
+    pub fn check(&self) -> ValidationCheck {
+        self.check
+    }
+
+    pub fn row(&self) -> Option<usize> {
+        self.row
+    }
+
+    pub fn column(&self) -> Option<&str> {
+        self.column.as_deref()
+    }
+}
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}", self.check.name(), self.check.severity().name())?;
+        if let Some(row) = self.row {
+            write!(f, ", row {row}")?;
+        }
+        if let Some(column) = &self.column {
+            write!(f, ", column {column}")?;
+        }
+        write!(f, "]: {}", self.message)
+    }
+}
+
+/// All problems found in a count matrix
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    issues: Vec<ValidationIssue>,
+}
+impl ValidationReport {
+    fn push(
+        &mut self,
+        check: ValidationCheck,
+        row: Option<usize>,
+        column: Option<&str>,
+        message: String,
+    ) {
+        self.issues
+            .push(ValidationIssue::new(check, row, column, message));
+    }
+
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    pub fn n_errors(&self) -> usize {
+        self.issues
+            .iter()
+            .filter(|x| x.check.severity() == Severity::Error)
+            .count()
+    }
+
+    pub fn n_warnings(&self) -> usize {
+        self.issues.len() - self.n_errors()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.n_errors() == 0
+    }
+
+    /// The number of problems found by each check in the order they were first found
+    pub fn summary(&self) -> Vec<(ValidationCheck, usize)> {
+        let mut summary: Vec<(ValidationCheck, usize)> = Vec::new();
+        for issue in &self.issues {
+            match summary.iter_mut().find(|(check, _)| *check == issue.check) {
+                Some((_, n)) => *n += 1,
+                None => summary.push((issue.check, 1)),
+            }
+        }
+        summary
+    }
+
+    /// Errors if the count matrix has any problems that prevent the analysis
+    pub fn into_result(self) -> Result<()> {
+        let n_errors = self.n_errors();
+        if n_errors == 0 {
+            return Ok(());
+        }
+        let mut listed = self
+            .issues
+            .iter()
+            .filter(|x| x.check.severity() == Severity::Error)
+            .take(MAX_REPORTED_ISSUES)
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<_>>();
+        if n_errors > MAX_REPORTED_ISSUES {
+            listed.push(format!("  ... and {} more", n_errors - MAX_REPORTED_ISSUES));
+        }
+        bail!(
+            "Invalid count matrix - {n_errors} problem(s) found:\n{}",
+            listed.join("\n")
+        )
+    }
+
+    /// Builds a table of the problems with one row per problem
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "severity" => self.issues.iter().map(|x| x.check.severity().name()).collect::<Vec<_>>(),
+            "check" => self.issues.iter().map(|x| x.check.name()).collect::<Vec<_>>(),
+            "row" => self.issues.iter().map(|x| x.row.map(|r| r as u64)).collect::<Vec<_>>(),
+            "column" => self.issues.iter().map(|x| x.column.as_deref()).collect::<Vec<_>>(),
+            "message" => self.issues.iter().map(|x| x.message.as_str()).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Selects all columns other than the sgRNA and gene columns as the sample columns
+///
+/// The annotation columns are resolved the same way as in [`super::get_annotation_columns`].
+pub fn count_columns(
+    dataframe: &DataFrame,
+    sgrna_column: Option<&str>,
+    gene_column: Option<&str>,
+) -> Vec<String> {
+    let names = dataframe
+        .get_column_names()
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    let (sgrna_column, gene_column) = match (sgrna_column, gene_column) {
+        (None, None) => mageck_count_columns(dataframe).unzip(),
+        columns => columns,
+    };
+    let sgrna = sgrna_column.map_or(names.first(), |x| names.iter().find(|n| *n == x));
+    let gene = gene_column.map_or(names.get(1), |x| names.iter().find(|n| *n == x));
+    names
+        .iter()
+        .filter(|x| Some(*x) != sgrna && Some(*x) != gene)
+        .cloned()
+        .collect()
+}
+
+/// Checks the counts of a single sample column
+fn validate_sample(
+    dataframe: &DataFrame,
+    label: &str,
+    report: &mut ValidationReport,
+) -> Result<()> {
+    let column = dataframe.column(label)?;
+    let values = column.cast(&DataType::Float64)?;
+    let raw = column.cast(&DataType::String)?;
+    let mut n_fractional = 0;
+    let mut first_fractional = None;
+    let mut total = 0.;
+    for (idx, (value, raw)) in values.f64()?.iter().zip(raw.str()?.iter()).enumerate() {
+        match (value, raw) {
+            (_, None) => report.push(
+                ValidationCheck::MissingValue,
+                Some(idx),
+                Some(label),
+                "count is missing".to_string(),
+            ),
+            (None, Some(raw)) if MISSING_TOKENS.contains(&raw) => report.push(
+                ValidationCheck::MissingValue,
+                Some(idx),
+                Some(label),
+                format!("count is missing ({raw})"),
+            ),
+            (None, Some(raw)) => report.push(
+                ValidationCheck::NonNumericValue,
+                Some(idx),
+                Some(label),
+                format!("count ({raw}) is not a number"),
+            ),
+            (Some(x), _) if x.is_nan() => report.push(
+                ValidationCheck::MissingValue,
+                Some(idx),
+                Some(label),
+                "count is NaN".to_string(),
+            ),
+            (Some(x), _) if x < 0. => report.push(
+                ValidationCheck::NegativeCount,
+                Some(idx),
+                Some(label),
+                format!("count ({x}) is negative"),
+            ),
+            (Some(x), _) => {
+                if x.fract() != 0. {
+                    n_fractional += 1;
+                    first_fractional.get_or_insert(idx);
+                }
+                total += x;
+            }
+        }
+    }
+    if let Some(idx) = first_fractional {
+        report.push(
+            ValidationCheck::FractionalCount,
+            Some(idx),
+            Some(label),
+            format!("{n_fractional} count(s) are not integers - counts are expected to be raw read counts"),
+        );
+    }
+    if total == 0. {
+        report.push(
+            ValidationCheck::EmptySample,
+            None,
+            Some(label),
+            "sample has no counts".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Validates the count matrix before any statistics are calculated
+///
+/// Checks for duplicate sgRNA names, missing or non-numeric values, negative or
+/// fractional counts, empty samples, genes with a single sgRNA, and (if a token is
+/// provided) whether any non-targeting controls are present.
+pub fn validate_counts(
+    dataframe: &DataFrame,
+    sample_labels: &[String],
+    sgrna_names: &[String],
+    gene_names: &[String],
+    ntc_token: Option<&str>,
+) -> Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    let mut seen = HashMap::with_capacity(sgrna_names.len());
+    for (idx, name) in sgrna_names.iter().enumerate() {
+        if let Some(first) = seen.insert(name.as_str(), idx) {
+            report.push(
+                ValidationCheck::DuplicateSgrna,
+                Some(idx),
+                None,
+                format!("sgRNA ({name}) already found in row {}", first + 1),
+            );
+        }
+    }
+
+    for label in sample_labels {
+        validate_sample(dataframe, label, &mut report)?;
+    }
+
+    let mut gene_rows: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, gene) in gene_names.iter().enumerate() {
+        gene_rows.entry(gene.as_str()).or_default().push(idx);
+    }
+    let mut singletons = gene_rows
+        .into_iter()
+        .filter(|(gene, rows)| rows.len() == 1 && !ntc_token.is_some_and(|t| gene.contains(t)))
+        .map(|(gene, rows)| (rows[0], gene))
+        .collect::<Vec<_>>();
+    singletons.sort_unstable();
+    for (idx, gene) in singletons {
+        report.push(
+            ValidationCheck::SingleSgrnaGene,
+            Some(idx),
+            None,
+            format!("gene ({gene}) is only targeted by a single sgRNA"),
+        );
+    }
+
+    if let Some(token) = ntc_token {
+        if !sgrna_names.iter().any(|x| x.contains(token)) {
+            report.push(
+                ValidationCheck::NtcCoverage,
+                None,
+                None,
+                format!("Non-Targeting Token ({token}) not found in any sgrna names"),
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    fn to_strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_validate_counts() -> Result<()> {
+        let frame = df!(
+            "sgrna" => &["a_1", "a_2", "a_1", "b_1"],
+            "gene" => &["a", "a", "a", "b"],
+            "low" => &[Some(1.), None, Some(-2.), Some(1.5)],
+            "high" => &["1", "x", "NA", "4"],
+            "empty" => &[0, 0, 0, 0],
+        )?;
+        assert_eq!(
+            count_columns(&frame, None, None),
+            to_strings(&["low", "high", "empty"])
+        );
+
+        let labels = to_strings(&["low", "high", "empty"]);
+        let sgrnas = to_strings(&["a_1", "a_2", "a_1", "b_1"]);
+        let genes = to_strings(&["a", "a", "a", "b"]);
+        let report = validate_counts(&frame, &labels, &sgrnas, &genes, Some("ntc"))?;
+
+        let found = report
+            .issues()
+            .iter()
+            .map(|x| (x.check(), x.row(), x.column()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (ValidationCheck::DuplicateSgrna, Some(3), None),
+                (ValidationCheck::MissingValue, Some(2), Some("low")),
+                (ValidationCheck::NegativeCount, Some(3), Some("low")),
+                (ValidationCheck::FractionalCount, Some(4), Some("low")),
+                (ValidationCheck::NonNumericValue, Some(2), Some("high")),
+                (ValidationCheck::MissingValue, Some(3), Some("high")),
+                (ValidationCheck::EmptySample, None, Some("empty")),
+                (ValidationCheck::SingleSgrnaGene, Some(4), None),
+                (ValidationCheck::NtcCoverage, None, None),
+            ]
+        );
+        assert_eq!(report.n_errors(), 6);
+        assert_eq!(report.n_warnings(), 3);
+        assert_eq!(report.to_dataframe()?.height(), 9);
+        assert!(report.into_result().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_clean_counts() -> Result<()> {
+        let frame = df!(
+            "sgrna" => &["a_1", "a_2", "ntc_1"],
+            "gene" => &["a", "a", "ntc"],
+            "low" => &[1, 2, 3],
+        )?;
+        let report = validate_counts(
+            &frame,
+            &to_strings(&["low"]),
+            &to_strings(&["a_1", "a_2", "ntc_1"]),
+            &to_strings(&["a", "a", "ntc"]),
+            Some("ntc"),
+        )?;
+        assert!(report.issues().is_empty());
+        assert!(report.into_result().is_ok());
+        Ok(())
+    }
+}
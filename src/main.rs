@@ -3,8 +3,8 @@ use anyhow::Result;
 use bon::builder;
 use clap::Parser;
 use cli::{
-    Cli, Commands, DiffAbundanceArgs, GeopaggArgs, IncArgs, InputArgs, MiscArgs, RraArgs,
-    SgrnaColumns,
+    BagelArgs, Cli, Commands, DiffAbundanceArgs, DrugzArgs, GeopaggArgs, IncArgs, InputArgs,
+    MiscArgs, RraArgs, SampleStructureArgs, SecondBestArgs, SgrnaColumns, StoufferArgs,
 };
 use geopagg::WeightConfig;
 use log::LevelFilter;
@@ -13,50 +13,58 @@ use std::path::Path;
 
 pub mod aggregation;
 pub mod cli;
+pub mod count;
 pub mod differential_expression;
 pub mod enrich;
 pub mod io;
 pub mod model;
 pub mod norm;
+pub mod qc;
 pub mod resample;
 pub mod run_aggregation;
+pub mod sample_structure;
 pub mod utils;
 
-use aggregation::{GeneAggregation, GeneAggregationSelection, GeoPAGGWeightConfigEnum};
-use differential_expression::mageck;
-use io::{build_regex_set, load_dataframe};
+use aggregation::{
+    GeneAggregation, GeneAggregationSelection, GeoPAGGWeightConfigEnum, ReferenceSets,
+};
+use count::count;
+use differential_expression::{mageck, mageck_contrasts, mageck_interaction, mageck_timecourse};
+use enrich::{GlmTest, NtcCalibration, TestStrategy};
+use io::{
+    build_regex_set, count_columns, get_annotation_columns, load_dataframe, load_sgrna_list,
+    match_headers_from_regex_set, select_group_labels, select_sample_labels, validate_counts,
+    write_table, Contrasts, Design, OutputFormat, SgrnaEfficacy, SizeFactors, STDIN_PATH,
+};
+use model::ModelChoice;
+use norm::{Normalization, StrictNormalizationError};
+use qc::qc;
 use resample::resample;
 use utils::{config::Configuration, logging::Logger, Adjustment};
 
+/// Assigns and parameterizes the gene aggregation methods
+///
+/// Returns the first method, which writes the gene results, and any additional distinct
+/// methods which are combined with it in the consensus.
 #[builder]
-fn test(
-    input_args: InputArgs,
-    prefix: String,
-    diff_args: DiffAbundanceArgs,
-    agg: GeneAggregationSelection,
-    rra: RraArgs,
-    inc: IncArgs,
-    geopagg: GeopaggArgs,
-    misc: MiscArgs,
-    skip_agg: bool,
-) -> Result<()> {
-    // validate input path
-    let path = if Path::new(&input_args.input).exists() {
-        input_args.input
-    } else {
-        panic!("Provided Input Does Not Exist: {}", input_args.input)
-    };
-
-    // set rayon threads
-    if let Some(t) = misc.threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(t)
-            .build_global()
-            .unwrap();
+fn build_aggregation<'a>(
+    agg: Vec<GeneAggregationSelection>,
+    rra: &RraArgs,
+    inc: &IncArgs,
+    geopagg: &GeopaggArgs,
+    bagel: &BagelArgs,
+    drugz: &DrugzArgs,
+    stouffer: &StoufferArgs,
+    second_best: &SecondBestArgs,
+    misc: &'a MiscArgs,
+) -> (GeneAggregation<'a>, Vec<GeneAggregation<'a>>) {
+    let mut selections: Vec<GeneAggregationSelection> = Vec::with_capacity(agg.len());
+    for selection in agg {
+        if !selections.contains(&selection) {
+            selections.push(selection);
+        }
     }
-
-    // assign and parameterize gene aggregation method
-    let agg = match agg {
+    let mut aggregations = selections.into_iter().map(|agg| match agg {
         GeneAggregationSelection::RRA => GeneAggregation::AlpaRRA {
             alpha: rra.alpha,
             npermutations: rra.permutations,
@@ -92,47 +100,280 @@ fn test(
                 zscore_threshold: geopagg.zscore_threshold,
             }
         }
-    };
-
-    // create logger based on quiet option
-    let logger = if misc.quiet {
-        Logger::new_silent()
-    } else {
-        Logger::new()
-    };
+        GeneAggregationSelection::Bagel => GeneAggregation::Bagel {
+            n_bootstraps: bagel.bagel_bootstraps,
+            fdr: misc.fdr,
+        },
+        GeneAggregationSelection::DrugZ => GeneAggregation::DrugZ {
+            half_window: drugz.drugz_half_window,
+            fdr: misc.fdr,
+        },
+        GeneAggregationSelection::Fisher => GeneAggregation::Fisher { fdr: misc.fdr },
+        GeneAggregationSelection::Stouffer => GeneAggregation::Stouffer {
+            weights: stouffer.stouffer_weights,
+            fdr: misc.fdr,
+        },
+        GeneAggregationSelection::Acat => GeneAggregation::Acat { fdr: misc.fdr },
+        GeneAggregationSelection::SecondBest => GeneAggregation::SecondBest {
+            token: &misc.ntc_token,
+            n_draws: second_best.second_best_draws,
+            fdr: misc.fdr,
+        },
+    });
+    let primary = aggregations
+        .next()
+        .expect("At least one aggregation method is required");
+    (primary, aggregations.collect())
+}
 
-    // create multiple hypothesis correction from option
-    let correction = match misc.correction {
+/// Creates the multiple hypothesis correction from the provided option
+fn build_correction(correction: &Adjustment) -> Procedure {
+    match correction {
         Adjustment::Bf => Procedure::Bonferroni,
         Adjustment::Bh => Procedure::BenjaminiHochberg,
         Adjustment::By => Procedure::BenjaminiYekutieli,
+    }
+}
+
+/// Loads the user-supplied size factors if provided which replace the normalization method
+fn build_normalization(
+    norm: Normalization,
+    size_factors: Option<String>,
+) -> Result<(Normalization, Option<SizeFactors>)> {
+    match size_factors {
+        Some(path) => Ok((
+            Normalization::Provided,
+            Some(SizeFactors::from_path(path.into())?),
+        )),
+        None => Ok((norm, None)),
+    }
+}
+
+#[builder]
+fn validate(
+    input: String,
+    samples: Option<Vec<String>>,
+    sgrna_col: Option<String>,
+    gene_col: Option<String>,
+    ntc_token: String,
+    output: Option<String>,
+    quiet: bool,
+) -> Result<()> {
+    let logger = Logger::from_quiet(quiet);
+    let frame = load_dataframe(input.into())?;
+
+    let sample_labels = match samples {
+        Some(samples) => match_headers_from_regex_set(&frame, &build_regex_set(&samples)?)?,
+        None => count_columns(&frame, sgrna_col.as_deref(), gene_col.as_deref()),
     };
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(&frame, sgrna_col.as_deref(), gene_col.as_deref())?;
+    logger.sampled_names(&sample_labels);
+    logger.num_sgrnas(&sgrna_names);
+    logger.num_genes(&gene_names);
+
+    let token = Some(ntc_token.as_str()).filter(|x| !x.is_empty());
+    let report = validate_counts(&frame, &sample_labels, &sgrna_names, &gene_names, token)?;
+    logger.validation_report(&report);
+    write_table(&mut report.to_dataframe()?, output, OutputFormat::Tsv)?;
+    report.into_result()
+}
+
+/// Loads the essential and non-essential reference gene sets if provided
+fn build_reference_sets(misc: &MiscArgs) -> Result<Option<ReferenceSets>> {
+    match (&misc.essential_genes, &misc.nonessential_genes) {
+        (Some(essential), Some(nonessential)) => {
+            Ok(Some(ReferenceSets::from_paths(essential, nonessential)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Loads the user-supplied sgRNA efficacy scores if provided
+fn build_sgrna_efficacy(stouffer: &StoufferArgs) -> Result<Option<SgrnaEfficacy>> {
+    match &stouffer.sgrna_efficacy {
+        Some(path) => Ok(Some(SgrnaEfficacy::from_path(path.into())?)),
+        None => Ok(None),
+    }
+}
+
+/// Loads the user-supplied control sgRNAs if provided
+fn build_control_sgrnas(control_sgrnas: Option<String>) -> Result<Vec<String>> {
+    match control_sgrnas {
+        Some(path) => load_sgrna_list(path.into()),
+        None => Ok(vec![]),
+    }
+}
+
+/// Sets the number of rayon threads if provided
+fn set_threads(threads: Option<usize>) {
+    if let Some(t) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(t)
+            .build_global()
+            .unwrap();
+    }
+}
 
-    let config = Configuration::builder()
-        .normalization(diff_args.norm)
+/// Assembles the run configuration shared by the subcommands
+///
+/// Differential abundance options left unset keep their configuration defaults, as in `agg`
+/// which only aggregates precomputed sgRNA results.
+#[builder]
+fn build_config<'a>(
+    agg: Vec<GeneAggregationSelection>,
+    rra: &RraArgs,
+    inc: &IncArgs,
+    geopagg: &GeopaggArgs,
+    bagel: &BagelArgs,
+    drugz: &DrugzArgs,
+    stouffer: &StoufferArgs,
+    second_best: &SecondBestArgs,
+    misc: &'a MiscArgs,
+    structure: Option<&SampleStructureArgs>,
+    #[builder(default)] norm: Normalization,
+    size_factors: Option<String>,
+    #[builder(default)] strict_norm: bool,
+    control_sgrnas: Option<String>,
+    #[builder(default)] model_choice: ModelChoice,
+    #[builder(default)] min_base_mean: f64,
+    #[builder(default)] strategy: TestStrategy,
+    #[builder(default)] covariates: Vec<String>,
+    #[builder(default)] glm_test: GlmTest,
+    #[builder(default)] ntc_calibration: NtcCalibration,
+    sgrna_column: Option<&'a str>,
+    gene_column: Option<&'a str>,
+    prefix: &'a str,
+) -> Result<Configuration<'a>> {
+    let (agg, consensus_aggs) = build_aggregation()
+        .agg(agg)
+        .rra(rra)
+        .inc(inc)
+        .geopagg(geopagg)
+        .bagel(bagel)
+        .drugz(drugz)
+        .stouffer(stouffer)
+        .second_best(second_best)
+        .misc(misc)
+        .call();
+    let (norm, size_factors) = build_normalization(norm, size_factors)?;
+
+    Ok(Configuration::builder()
+        .normalization(norm)
         .aggregation(agg)
-        .correction(correction)
+        .consensus_aggregations(consensus_aggs)
+        .correction(build_correction(&misc.correction))
+        .model_choice(model_choice)
+        .min_base_mean(min_base_mean)
+        .strategy(strategy)
+        .covariates(covariates)
+        .glm_test(glm_test)
+        .ntc_calibration(ntc_calibration)
+        .ntc_token(&misc.ntc_token)
+        .control_sgrnas(build_control_sgrnas(control_sgrnas)?)
+        .maybe_size_factors(size_factors)
+        .strict_normalization(strict_norm)
+        .maybe_sgrna_column(sgrna_column)
+        .maybe_gene_column(gene_column)
+        .output_format(misc.output_format)
+        .mageck_output(misc.mageck_output)
+        .maybe_reference_sets(build_reference_sets(misc)?)
+        .maybe_sgrna_efficacy(build_sgrna_efficacy(stouffer)?)
+        .sample_pca(structure.is_some_and(|x| x.sample_pca))
+        .warn_outliers(structure.is_some_and(|x| x.warn_outliers))
+        .seed(misc.seed)
+        .prefix(prefix)
+        .build())
+}
+
+#[builder]
+fn test(
+    input_args: InputArgs,
+    prefix: String,
+    diff_args: DiffAbundanceArgs,
+    agg: Vec<GeneAggregationSelection>,
+    rra: RraArgs,
+    inc: IncArgs,
+    geopagg: GeopaggArgs,
+    bagel: BagelArgs,
+    drugz: DrugzArgs,
+    stouffer: StoufferArgs,
+    second_best: SecondBestArgs,
+    structure: SampleStructureArgs,
+    misc: MiscArgs,
+    skip_agg: bool,
+) -> Result<()> {
+    // validate input path
+    let path = if input_args.input == STDIN_PATH || Path::new(&input_args.input).exists() {
+        input_args.input
+    } else {
+        panic!("Provided Input Does Not Exist: {}", input_args.input)
+    };
+
+    set_threads(misc.threads);
+
+    let logger = Logger::from_quiet(misc.quiet);
+    let config = build_config()
+        .agg(agg)
+        .rra(&rra)
+        .inc(&inc)
+        .geopagg(&geopagg)
+        .bagel(&bagel)
+        .drugz(&drugz)
+        .stouffer(&stouffer)
+        .second_best(&second_best)
+        .misc(&misc)
+        .structure(&structure)
+        .norm(diff_args.norm)
+        .maybe_size_factors(diff_args.size_factors)
+        .strict_norm(diff_args.strict_norm)
+        .maybe_control_sgrnas(diff_args.control_sgrnas)
         .model_choice(diff_args.model_choice)
         .min_base_mean(diff_args.min_base_mean)
         .strategy(diff_args.strategy)
-        .seed(misc.seed)
+        .covariates(diff_args.covariates)
+        .glm_test(diff_args.glm_test)
+        .ntc_calibration(diff_args.ntc_calibration)
+        .maybe_sgrna_column(input_args.sgrna_col.as_deref())
+        .maybe_gene_column(input_args.gene_col.as_deref())
         .prefix(&prefix)
-        .build();
+        .call()?;
     let frame = load_dataframe(path.clone().into())?;
 
-    let regex_controls = build_regex_set(&input_args.controls)?;
-    let regex_treatments = build_regex_set(&input_args.treatments)?;
-
-    let mageck_results = mageck(
-        &frame,
-        &regex_controls,
-        &regex_treatments,
-        &config,
-        &logger,
-        skip_agg,
-    );
+    let design = match input_args.design {
+        Some(design_path) => Some(Design::from_path(design_path.into())?),
+        None => None,
+    };
+    let mageck_results = if let Some(contrasts_path) = input_args.contrasts {
+        let contrasts = Contrasts::from_path(contrasts_path.into())?;
+        mageck_contrasts(
+            &frame,
+            contrasts.contrasts(),
+            design.as_ref(),
+            &config,
+            &logger,
+            skip_agg,
+        )
+    } else {
+        let (control_labels, treatment_labels) = select_sample_labels(
+            &frame,
+            design.as_ref(),
+            &input_args.controls,
+            &input_args.treatments,
+        )?;
+        mageck(
+            &frame,
+            &control_labels,
+            &treatment_labels,
+            design.as_ref(),
+            &config,
+            &logger,
+            skip_agg,
+        )
+    };
 
     match mageck_results {
+        Err(e) if e.is::<StrictNormalizationError>() => Err(e),
         Err(e) => {
             println!("ERROR: {e}");
             Ok(())
@@ -142,90 +383,204 @@ fn test(
 }
 
 #[builder]
-fn aggregate(
+fn timecourse(
     input: String,
+    design: String,
+    time_column: String,
+    conditions: Vec<String>,
     prefix: String,
-    columns: SgrnaColumns,
-    agg: GeneAggregationSelection,
+    norm: Normalization,
+    size_factors: Option<String>,
+    strict_norm: bool,
+    control_sgrnas: Option<String>,
+    model_choice: ModelChoice,
+    min_base_mean: f64,
+    agg: Vec<GeneAggregationSelection>,
     rra: RraArgs,
     inc: IncArgs,
     geopagg: GeopaggArgs,
+    bagel: BagelArgs,
+    drugz: DrugzArgs,
+    stouffer: StoufferArgs,
+    second_best: SecondBestArgs,
+    structure: SampleStructureArgs,
     misc: MiscArgs,
+    skip_agg: bool,
 ) -> Result<()> {
     // validate input path
-    let path = if Path::new(&input).exists() {
+    let path = if input == STDIN_PATH || Path::new(&input).exists() {
         input
     } else {
         panic!("Provided Input Does Not Exist: {}", input)
     };
 
-    // set rayon threads
-    if let Some(t) = misc.threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(t)
-            .build_global()
-            .unwrap();
-    }
+    set_threads(misc.threads);
+    let logger = Logger::from_quiet(misc.quiet);
+    let config = build_config()
+        .agg(agg)
+        .rra(&rra)
+        .inc(&inc)
+        .geopagg(&geopagg)
+        .bagel(&bagel)
+        .drugz(&drugz)
+        .stouffer(&stouffer)
+        .second_best(&second_best)
+        .misc(&misc)
+        .structure(&structure)
+        .norm(norm)
+        .maybe_size_factors(size_factors)
+        .strict_norm(strict_norm)
+        .maybe_control_sgrnas(control_sgrnas)
+        .model_choice(model_choice)
+        .min_base_mean(min_base_mean)
+        .prefix(&prefix)
+        .call()?;
+    let frame = load_dataframe(path.into())?;
 
-    // assign and parameterize gene aggregation method
-    let agg = match agg {
-        GeneAggregationSelection::RRA => GeneAggregation::AlpaRRA {
-            alpha: rra.alpha,
-            npermutations: rra.permutations,
-            adjust_alpha: !rra.no_adjust_alpha,
-            fdr: misc.fdr,
-        },
-        GeneAggregationSelection::Inc => GeneAggregation::Inc {
-            token: &misc.ntc_token,
-            group_size: inc.inc_group_size,
-            use_product: inc.inc_product,
-            n_draws: inc.n_draws,
-            fdr: misc.fdr,
-        },
-        GeneAggregationSelection::GeoPAGG => {
-            let token = if misc.ntc_token.is_empty() | geopagg.use_all {
-                None
-            } else {
-                Some(misc.ntc_token.as_str())
-            };
-            GeneAggregation::GeoPAGG {
-                token,
-                fdr: misc.fdr,
-                weight_config: {
-                    match geopagg.weight_config {
-                        GeoPAGGWeightConfigEnum::DropFirst => WeightConfig::DropFirst {
-                            alpha: geopagg.df_alpha,
-                        },
-                        GeoPAGGWeightConfigEnum::RankOrder => WeightConfig::RankOrder,
-                        GeoPAGGWeightConfigEnum::Balanced => WeightConfig::Balanced,
-                    }
-                },
-                use_product: geopagg.use_product,
-                zscore_threshold: geopagg.zscore_threshold,
-            }
+    let design = Design::from_path(design.into())?;
+    design.validate(&frame)?;
+    let (sample_labels, times): (Vec<String>, Vec<f64>) = design
+        .select_times(&conditions, &time_column)?
+        .into_iter()
+        .unzip();
+
+    match mageck_timecourse(&frame, &sample_labels, &times, &config, &logger, skip_agg) {
+        Err(e) if e.is::<StrictNormalizationError>() => Err(e),
+        Err(e) => {
+            println!("ERROR: {e}");
+            Ok(())
         }
-    };
+        Ok(_) => Ok(()),
+    }
+}
 
-    // create logger based on quiet option
-    let logger = if misc.quiet {
-        Logger::new_silent()
+#[builder]
+fn interaction(
+    input: String,
+    design: Option<String>,
+    controls: Vec<String>,
+    reference: Vec<String>,
+    query: Vec<String>,
+    query_controls: Option<Vec<String>>,
+    prefix: String,
+    norm: Normalization,
+    size_factors: Option<String>,
+    strict_norm: bool,
+    control_sgrnas: Option<String>,
+    model_choice: ModelChoice,
+    min_base_mean: f64,
+    agg: Vec<GeneAggregationSelection>,
+    rra: RraArgs,
+    inc: IncArgs,
+    geopagg: GeopaggArgs,
+    bagel: BagelArgs,
+    drugz: DrugzArgs,
+    stouffer: StoufferArgs,
+    second_best: SecondBestArgs,
+    structure: SampleStructureArgs,
+    misc: MiscArgs,
+    skip_agg: bool,
+) -> Result<()> {
+    // validate input path
+    let path = if input == STDIN_PATH || Path::new(&input).exists() {
+        input
     } else {
-        Logger::new()
+        panic!("Provided Input Does Not Exist: {}", input)
     };
 
-    // create multiple hypothesis correction from option
-    let correction = match misc.correction {
-        Adjustment::Bf => Procedure::Bonferroni,
-        Adjustment::Bh => Procedure::BenjaminiHochberg,
-        Adjustment::By => Procedure::BenjaminiYekutieli,
+    set_threads(misc.threads);
+    let logger = Logger::from_quiet(misc.quiet);
+    let config = build_config()
+        .agg(agg)
+        .rra(&rra)
+        .inc(&inc)
+        .geopagg(&geopagg)
+        .bagel(&bagel)
+        .drugz(&drugz)
+        .stouffer(&stouffer)
+        .second_best(&second_best)
+        .misc(&misc)
+        .structure(&structure)
+        .norm(norm)
+        .maybe_size_factors(size_factors)
+        .strict_norm(strict_norm)
+        .maybe_control_sgrnas(control_sgrnas)
+        .model_choice(model_choice)
+        .min_base_mean(min_base_mean)
+        .prefix(&prefix)
+        .call()?;
+    let frame = load_dataframe(path.into())?;
+
+    let design = match design {
+        Some(design_path) => Some(Design::from_path(design_path.into())?),
+        None => None,
+    };
+    let control_labels = select_group_labels(&frame, design.as_ref(), &controls)?;
+    let reference_labels = select_group_labels(&frame, design.as_ref(), &reference)?;
+    let query_labels = select_group_labels(&frame, design.as_ref(), &query)?;
+    let query_control_labels = match query_controls {
+        Some(labels) => Some(select_group_labels(&frame, design.as_ref(), &labels)?),
+        None => None,
     };
 
-    let config = Configuration::builder()
-        .aggregation(agg)
-        .correction(correction)
-        .seed(misc.seed)
+    let mageck_results = mageck_interaction()
+        .frame(&frame)
+        .control_labels(&control_labels)
+        .reference_labels(&reference_labels)
+        .query_labels(&query_labels)
+        .maybe_query_control_labels(query_control_labels.as_deref())
+        .config(&config)
+        .logger(&logger)
+        .skip_agg(skip_agg)
+        .call();
+
+    match mageck_results {
+        Err(e) if e.is::<StrictNormalizationError>() => Err(e),
+        Err(e) => {
+            println!("ERROR: {e}");
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+    }
+}
+
+#[builder]
+fn aggregate(
+    input: String,
+    prefix: String,
+    columns: SgrnaColumns,
+    agg: Vec<GeneAggregationSelection>,
+    rra: RraArgs,
+    inc: IncArgs,
+    geopagg: GeopaggArgs,
+    bagel: BagelArgs,
+    drugz: DrugzArgs,
+    stouffer: StoufferArgs,
+    second_best: SecondBestArgs,
+    misc: MiscArgs,
+) -> Result<()> {
+    // validate input path
+    let path = if input == STDIN_PATH || Path::new(&input).exists() {
+        input
+    } else {
+        panic!("Provided Input Does Not Exist: {}", input)
+    };
+
+    set_threads(misc.threads);
+
+    let logger = Logger::from_quiet(misc.quiet);
+    let config = build_config()
+        .agg(agg)
+        .rra(&rra)
+        .inc(&inc)
+        .geopagg(&geopagg)
+        .bagel(&bagel)
+        .drugz(&drugz)
+        .stouffer(&stouffer)
+        .second_best(&second_best)
+        .misc(&misc)
         .prefix(&prefix)
-        .build();
+        .call()?;
     let frame = load_dataframe(path.into())?;
 
     run_aggregation(&frame, columns, &config, &logger)
@@ -249,6 +604,11 @@ fn main() -> Result<()> {
             rra,
             inc,
             geopagg,
+            bagel,
+            drugz,
+            stouffer,
+            second_best,
+            structure,
             misc,
             skip_agg,
         } => test()
@@ -259,6 +619,109 @@ fn main() -> Result<()> {
             .rra(rra)
             .inc(inc)
             .geopagg(geopagg)
+            .bagel(bagel)
+            .drugz(drugz)
+            .stouffer(stouffer)
+            .second_best(second_best)
+            .structure(structure)
+            .misc(misc)
+            .skip_agg(skip_agg)
+            .call(),
+        Commands::Timecourse {
+            input,
+            design,
+            time_column,
+            conditions,
+            prefix,
+            norm,
+            size_factors,
+            strict_norm,
+            control_sgrnas,
+            model_choice,
+            min_base_mean,
+            agg,
+            rra,
+            inc,
+            geopagg,
+            bagel,
+            drugz,
+            stouffer,
+            second_best,
+            structure,
+            misc,
+            skip_agg,
+        } => timecourse()
+            .input(input)
+            .design(design)
+            .time_column(time_column)
+            .conditions(conditions)
+            .prefix(prefix)
+            .norm(norm)
+            .maybe_size_factors(size_factors)
+            .strict_norm(strict_norm)
+            .maybe_control_sgrnas(control_sgrnas)
+            .model_choice(model_choice)
+            .min_base_mean(min_base_mean)
+            .agg(agg)
+            .rra(rra)
+            .inc(inc)
+            .geopagg(geopagg)
+            .bagel(bagel)
+            .drugz(drugz)
+            .stouffer(stouffer)
+            .second_best(second_best)
+            .structure(structure)
+            .misc(misc)
+            .skip_agg(skip_agg)
+            .call(),
+        Commands::Interaction {
+            input,
+            design,
+            controls,
+            reference,
+            query,
+            query_controls,
+            prefix,
+            norm,
+            size_factors,
+            strict_norm,
+            control_sgrnas,
+            model_choice,
+            min_base_mean,
+            agg,
+            rra,
+            inc,
+            geopagg,
+            bagel,
+            drugz,
+            stouffer,
+            second_best,
+            structure,
+            misc,
+            skip_agg,
+        } => interaction()
+            .input(input)
+            .maybe_design(design)
+            .controls(controls)
+            .reference(reference)
+            .query(query)
+            .maybe_query_controls(query_controls)
+            .prefix(prefix)
+            .norm(norm)
+            .maybe_size_factors(size_factors)
+            .strict_norm(strict_norm)
+            .maybe_control_sgrnas(control_sgrnas)
+            .model_choice(model_choice)
+            .min_base_mean(min_base_mean)
+            .agg(agg)
+            .rra(rra)
+            .inc(inc)
+            .geopagg(geopagg)
+            .bagel(bagel)
+            .drugz(drugz)
+            .stouffer(stouffer)
+            .second_best(second_best)
+            .structure(structure)
             .misc(misc)
             .skip_agg(skip_agg)
             .call(),
@@ -270,6 +733,10 @@ fn main() -> Result<()> {
             rra,
             inc,
             geopagg,
+            bagel,
+            drugz,
+            stouffer,
+            second_best,
             misc,
         } => aggregate()
             .input(input)
@@ -279,6 +746,10 @@ fn main() -> Result<()> {
             .rra(rra)
             .inc(inc)
             .geopagg(geopagg)
+            .bagel(bagel)
+            .drugz(drugz)
+            .stouffer(stouffer)
+            .second_best(second_best)
             .misc(misc)
             .call(),
         Commands::Resample {
@@ -289,6 +760,7 @@ fn main() -> Result<()> {
             depth_samples,
             seed,
             samples,
+            output_format,
             quiet,
         } => resample()
             .input(input)
@@ -298,7 +770,69 @@ fn main() -> Result<()> {
             .maybe_depth(depth)
             .maybe_depth_samples(depth_samples)
             .samples(samples)
+            .output_format(output_format)
             .quiet(quiet)
             .call(),
+        Commands::Validate {
+            input,
+            samples,
+            sgrna_col,
+            gene_col,
+            ntc_token,
+            output,
+            quiet,
+        } => validate()
+            .input(input)
+            .maybe_samples(samples)
+            .maybe_sgrna_col(sgrna_col)
+            .maybe_gene_col(gene_col)
+            .ntc_token(ntc_token)
+            .maybe_output(output)
+            .quiet(quiet)
+            .call(),
+        Commands::Qc {
+            input,
+            design,
+            samples,
+            sgrna_col,
+            gene_col,
+            norm,
+            prefix,
+            quiet,
+        } => qc()
+            .input(input)
+            .maybe_design(design)
+            .maybe_samples(samples)
+            .maybe_sgrna_col(sgrna_col)
+            .maybe_gene_col(gene_col)
+            .norm(norm)
+            .prefix(prefix)
+            .quiet(quiet)
+            .call(),
+        Commands::Count {
+            input,
+            library,
+            samples,
+            prefix,
+            offset,
+            mismatches,
+            reverse_complement,
+            output_format,
+            threads,
+            quiet,
+        } => {
+            set_threads(threads);
+            count()
+                .inputs(input)
+                .library(library)
+                .maybe_samples(samples)
+                .prefix(prefix)
+                .maybe_offset(offset)
+                .mismatches(mismatches)
+                .reverse_complement(reverse_complement)
+                .output_format(output_format)
+                .quiet(quiet)
+                .call()
+        }
     }
 }
@@ -0,0 +1,179 @@
+use super::invert;
+use crate::io::Design;
+use anyhow::{bail, Result};
+use ndarray::Array2;
+
+/// Name of the coefficient describing the treatment effect
+pub const TREATMENT_COEFFICIENT: &str = "treatment";
+
+/// A numeric design matrix for the generalized linear model
+///
+/// Columns are the intercept, an indicator of the treatment samples, and any requested
+/// covariates. Numeric covariates are used as is while categorical covariates are encoded
+/// as indicators against their first observed level.
+#[derive(Debug)]
+pub struct DesignMatrix {
+    matrix: Array2<f64>,
+    names: Vec<String>,
+}
+impl DesignMatrix {
+    /// Builds the design matrix for the provided controls followed by the treatments
+    pub fn from_design(
+        design: &Design,
+        controls: &[String],
+        treatments: &[String],
+        covariates: &[String],
+    ) -> Result<Self> {
+        let samples = [controls, treatments].concat();
+        let n = samples.len();
+        let mut columns = vec![
+            ("intercept".to_string(), vec![1.0; n]),
+            (
+                TREATMENT_COEFFICIENT.to_string(),
+                (0..n)
+                    .map(|idx| if idx < controls.len() { 0. } else { 1. })
+                    .collect(),
+            ),
+        ];
+
+        for covariate in covariates {
+            let Some(cov_idx) = design.covariate_names().iter().position(|x| x == covariate) else {
+                bail!("Covariate ({covariate}) is not a column of the design file")
+            };
+            let values = samples
+                .iter()
+                .map(|sample| match design.get(sample) {
+                    Some(entry) => Ok(entry.covariates()[cov_idx].clone()),
+                    None => bail!("Sample ({sample}) is not described in the design file"),
+                })
+                .collect::<Result<Vec<String>>>()?;
+
+            if let Ok(numeric) = values
+                .iter()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+            {
+                columns.push((covariate.clone(), numeric));
+            } else {
+                let mut levels: Vec<&String> = vec![];
+                values.iter().for_each(|x| {
+                    if !levels.contains(&x) {
+                        levels.push(x);
+                    }
+                });
+                for level in levels.iter().skip(1) {
+                    columns.push((
+                        format!("{covariate}_{level}"),
+                        values
+                            .iter()
+                            .map(|x| if x == *level { 1. } else { 0. })
+                            .collect(),
+                    ));
+                }
+            }
+        }
+
+        let names = columns.iter().map(|(name, _)| name.clone()).collect();
+        let matrix = Array2::from_shape_fn((n, columns.len()), |(i, j)| columns[j].1[i]);
+        if matrix.ncols() >= n {
+            bail!(
+                "Design matrix has {} coefficients but only {n} samples",
+                matrix.ncols()
+            )
+        }
+        if invert(&matrix.t().dot(&matrix)).is_none() {
+            bail!("Design matrix is not full rank - check whether a covariate is confounded with the condition")
+        }
+        Ok(Self { matrix, names })
+    }
+
+    pub fn matrix(&self) -> &Array2<f64> {
+        &self.matrix
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Index of the treatment coefficient
+    pub fn treatment_index(&self) -> usize {
+        1
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use polars::prelude::*;
+
+    fn build_design() -> Design {
+        let frame = df!(
+            "sample" => &["c1", "c2", "c3", "t1", "t2", "t3"],
+            "condition" => &["c", "c", "c", "t", "t", "t"],
+            "batch" => &["b1", "b2", "b1", "b1", "b2", "b2"],
+            "depth" => &["1.0", "2.0", "1.5", "1.0", "0.5", "2.5"],
+        )
+        .unwrap();
+        Design::from_dataframe(&frame).unwrap()
+    }
+
+    fn labels(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_design_matrix_categorical() -> Result<()> {
+        let design = build_design();
+        let dm = DesignMatrix::from_design(
+            &design,
+            &labels(&["c1", "c2", "c3"]),
+            &labels(&["t1", "t2", "t3"]),
+            &labels(&["batch"]),
+        )?;
+        assert_eq!(dm.names(), &["intercept", "treatment", "batch_b2"]);
+        assert_eq!(dm.matrix().column(1).to_vec(), vec![0., 0., 0., 1., 1., 1.]);
+        assert_eq!(dm.matrix().column(2).to_vec(), vec![0., 1., 0., 0., 1., 1.]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_matrix_numeric() -> Result<()> {
+        let design = build_design();
+        let dm = DesignMatrix::from_design(
+            &design,
+            &labels(&["c1", "c2", "c3"]),
+            &labels(&["t1", "t2", "t3"]),
+            &labels(&["depth"]),
+        )?;
+        assert_eq!(dm.names(), &["intercept", "treatment", "depth"]);
+        assert_eq!(
+            dm.matrix().column(2).to_vec(),
+            vec![1., 2., 1.5, 1., 0.5, 2.5]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_design_matrix_confounded() {
+        let design = build_design();
+        let dm = DesignMatrix::from_design(
+            &design,
+            &labels(&["c1", "c3"]),
+            &labels(&["t2", "t3"]),
+            &labels(&["batch"]),
+        );
+        assert!(dm.is_err());
+    }
+
+    #[test]
+    fn test_design_matrix_missing_covariate() {
+        let design = build_design();
+        let dm = DesignMatrix::from_design(
+            &design,
+            &labels(&["c1", "c2"]),
+            &labels(&["t1", "t2"]),
+            &labels(&["missing"]),
+        );
+        assert!(dm.is_err());
+    }
+}
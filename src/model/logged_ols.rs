@@ -57,6 +57,14 @@ impl LoggedOls {
         Self::replace_zeros_with_min(&adj_var)
     }
 
+    /// Calculates the negative binomial dispersion implied by the mean-variance trend
+    ///
+    /// The trend is `var = mean + kappa * mean^beta` so the dispersion of a negative
+    /// binomial with `var = mean + alpha * mean^2` is `alpha = kappa * mean^(beta - 2)`
+    pub fn dispersion(&self, mean: f64) -> f64 {
+        (self.kappa * mean.max(1.).pow(self.beta - 2.)).clamp(1e-8, 1e4)
+    }
+
     /// Subset arrays to those that will not cause numerical instability
     fn subset_arrays(
         means: &Array1<f64>,
@@ -1,25 +1,38 @@
 use ndarray::Array2;
 
-/// Determininant of a 2x2 matrix
+/// Inverse of a square matrix using Gauss-Jordan elimination with partial pivoting
 ///
-/// Calculated as a * d - b * c
-pub fn determinant(m: &Array2<f64>) -> f64 {
-    assert_eq!(m.shape(), &[2, 2]);
-    m[[0, 0]] * m[[1, 1]] - m[[0, 1]] * m[[1, 0]]
-}
-
-/// Inverse of a 2x2 matrix
-///
-/// Calculated as 1 / det * [[d, -b], [-c, a]]
-pub fn inverse(m: &Array2<f64>) -> Array2<f64> {
-    assert_eq!(m.shape(), &[2, 2]);
-    let det = determinant(m);
-    let mut inv = Array2::zeros((2, 2));
-    inv[[0, 0]] = m[[1, 1]] / det;
-    inv[[0, 1]] = -m[[0, 1]] / det;
-    inv[[1, 0]] = -m[[1, 0]] / det;
-    inv[[1, 1]] = m[[0, 0]] / det;
-    inv
+/// Returns `None` if the matrix is singular
+pub fn invert(m: &Array2<f64>) -> Option<Array2<f64>> {
+    assert_eq!(m.nrows(), m.ncols());
+    let n = m.nrows();
+    let mut a = m.clone();
+    let mut inv = Array2::eye(n);
+    for col in 0..n {
+        let pivot = (col..n).max_by(|x, y| a[[*x, col]].abs().total_cmp(&a[[*y, col]].abs()))?;
+        if a[[pivot, col]].abs() < f64::EPSILON {
+            return None;
+        }
+        for j in 0..n {
+            a.swap([col, j], [pivot, j]);
+            inv.swap([col, j], [pivot, j]);
+        }
+        let scale = a[[col, col]];
+        for j in 0..n {
+            a[[col, j]] /= scale;
+            inv[[col, j]] /= scale;
+        }
+        for row in (0..n).filter(|row| *row != col) {
+            let factor = a[[row, col]];
+            if factor != 0. {
+                for j in 0..n {
+                    a[[row, j]] -= factor * a[[col, j]];
+                    inv[[row, j]] -= factor * inv[[col, j]];
+                }
+            }
+        }
+    }
+    Some(inv)
 }
 
 #[cfg(test)]
@@ -29,16 +42,31 @@ mod testing {
     use ndarray::array;
 
     #[test]
-    fn test_determinant() {
+    fn test_invert_2x2() {
         let m = array![[1., 2.], [3., 4.]];
-        assert_eq!(determinant(&m), -2.);
+        let inv = invert(&m).unwrap();
+        let expected = array![[-2., 1.], [1.5, -0.5]];
+        for (x, y) in inv.iter().zip(expected.iter()) {
+            assert!((x - y).abs() < 1e-12);
+        }
     }
 
     #[test]
-    fn test_inverse() {
-        let m = array![[1., 2.], [3., 4.]];
-        let inv = inverse(&m);
-        let expected = array![[-2., 1.], [1.5, -0.5]];
-        assert_eq!(inv, expected);
+    fn test_invert() {
+        let m = array![[4., 7., 2.], [3., 6., 1.], [2., 5., 3.]];
+        let inv = invert(&m).unwrap();
+        let identity = m.dot(&inv);
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((identity[[i, j]] - expected).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invert_singular() {
+        let m = array![[1., 2.], [2., 4.]];
+        assert!(invert(&m).is_none());
     }
 }
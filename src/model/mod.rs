@@ -1,5 +1,7 @@
 use clap::ValueEnum;
 
+mod design_matrix;
+mod glm;
 mod logged_ols;
 mod math;
 mod model_mean_variance;
@@ -7,9 +9,11 @@ mod ols;
 mod sqmean;
 mod wols;
 
-use logged_ols::LoggedOls;
-use math::inverse;
-pub use model_mean_variance::model_mean_variance;
+pub use design_matrix::DesignMatrix;
+pub use glm::{moments_dispersion, GlmFit, NegativeBinomialGlm};
+pub use logged_ols::LoggedOls;
+use math::invert;
+pub use model_mean_variance::{control_variance, fit_mean_variance, model_mean_variance};
 use ols::Ols;
 use sqmean::Sqmean;
 use wols::Wols;
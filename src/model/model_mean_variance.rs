@@ -3,13 +3,15 @@ use crate::norm::median;
 use crate::utils::logging::Logger;
 use ndarray::{s, Array1, Array2, Axis};
 
-/// Model Mean Variance using Ordinary Least Squares Regression
-pub fn model_mean_variance(
+/// Fits the mean-variance relationship of the control samples
+///
+/// If only a single control sample is provided all samples are used for the fit.
+pub fn fit_mean_variance(
     normed_matrix: &Array2<f64>,
     n_controls: usize,
     model_choice: &ModelChoice,
     logger: &Logger,
-) -> Array1<f64> {
+) -> LoggedOls {
     let model_matrix = if n_controls == 1 {
         normed_matrix.view()
     } else {
@@ -17,11 +19,33 @@ pub fn model_mean_variance(
     };
     let model_mean = model_matrix.map_axis(Axis(1), |x| median(&x));
     let model_var = model_matrix.var_axis(Axis(1), 1.);
+    LoggedOls::fit(&model_mean, &model_var, model_choice, logger)
+}
+
+/// Model Mean Variance using Ordinary Least Squares Regression
+///
+/// Returns the fit model and the adjusted variance of the median control counts
+pub fn model_mean_variance(
+    normed_matrix: &Array2<f64>,
+    n_controls: usize,
+    model_choice: &ModelChoice,
+    logger: &Logger,
+) -> (Array1<f64>, LoggedOls) {
+    let logged_ols = fit_mean_variance(normed_matrix, n_controls, model_choice, logger);
+    (
+        control_variance(normed_matrix, n_controls, &logged_ols),
+        logged_ols,
+    )
+}
 
+/// Predicts the adjusted variance of the median control counts from a fit model
+pub fn control_variance(
+    normed_matrix: &Array2<f64>,
+    n_controls: usize,
+    model: &LoggedOls,
+) -> Array1<f64> {
     let control_mean = normed_matrix
         .slice(s![.., ..n_controls])
         .map_axis(Axis(1), |x| median(&x));
-    let logged_ols = LoggedOls::fit(&model_mean, &model_var, model_choice, logger);
-
-    logged_ols.predict(&control_mean)
+    model.predict(&control_mean)
 }
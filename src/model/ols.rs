@@ -1,4 +1,4 @@
-use super::inverse;
+use super::invert;
 use ndarray::{Array1, Array2, Axis};
 
 /// An implementation of [Ordinary Least Squares](https://en.wikipedia.org/wiki/Ordinary_least_squares#Matrix/vector_formulation) using a Matrix/Vector Formulation
@@ -29,7 +29,7 @@ impl Ols {
         // B = inv(XtX)XtY
         let xt = mat_x.t();
         let xtx = xt.dot(&mat_x);
-        let inv_xtx = inverse(&xtx);
+        let inv_xtx = invert(&xtx).expect("Singular design matrix in OLS");
 
         // drop an axis from the solution for a 1D vector
         let solution = inv_xtx.dot(&xt).dot(&mat_y).remove_axis(Axis(1));
@@ -1,4 +1,4 @@
-use super::inverse;
+use super::invert;
 use ndarray::{Array1, Array2, Axis};
 
 /// An implementation of [Weighted Least Squares](https://en.wikipedia.org/wiki/Weighted_least_squares) using a Matrix/Vector Formulation
@@ -38,7 +38,7 @@ impl Wols {
         let xt = mat_x.t();
         let xtw = xt.dot(&mat_w);
         let xtwx = xtw.dot(&mat_x);
-        let inv_xtwx = inverse(&xtwx);
+        let inv_xtwx = invert(&xtwx).expect("Singular design matrix in WLS");
 
         // drop an axis from the solution for a 1D vector
         let solution = inv_xtwx.dot(&xtw).dot(&mat_y).remove_axis(Axis(1));
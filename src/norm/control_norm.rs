@@ -0,0 +1,91 @@
+use super::median_ratio_norm::median_ratio_size_factors;
+use anyhow::{bail, Result};
+use hashbrown::HashSet;
+use ndarray::{Array1, Array2, Axis};
+
+/// Selects the indices of the control sgRNAs
+///
+/// If a list of control sgRNAs is provided the sgRNAs are matched by name, otherwise all
+/// sgRNAs containing the non-targeting token are used.
+pub fn control_sgrna_indices(
+    sgrna_names: &[String],
+    control_sgrnas: &[String],
+    token: &str,
+) -> Result<Vec<usize>> {
+    let indices = if control_sgrnas.is_empty() {
+        if token.is_empty() {
+            bail!(
+                "Control normalization requires a non-targeting token or a list of control sgRNAs"
+            )
+        }
+        sgrna_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| name.contains(token))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>()
+    } else {
+        let control_sgrnas = control_sgrnas
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<HashSet<_>>();
+        sgrna_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| control_sgrnas.contains(name.as_str()))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>()
+    };
+    if indices.is_empty() {
+        bail!("No control sgRNAs found in the count matrix for control normalization")
+    }
+    Ok(indices)
+}
+
+/// Calculates the median ratio size factors using only the control sgRNAs.
+/// Size factors are computed from the control sgRNAs alone and applied to all sgRNAs
+/// so that strong selection on the bulk of the library does not distort them.
+pub fn control_size_factors(matrix: &Array2<f64>, controls: &[usize]) -> Result<Array1<f64>> {
+    let control_matrix = matrix.select(Axis(0), controls);
+    let Ok(size_factors) = median_ratio_size_factors(&control_matrix) else {
+        bail!("median-ratio of the control sgRNAs is unstable - too many zero counts in the control sgRNAs")
+    };
+    Ok(size_factors)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_control_sgrna_indices() -> Result<()> {
+        let names = ["g1_a", "ntc_1", "g2_a", "ntc_2"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(control_sgrna_indices(&names, &[], "ntc")?, vec![1, 3]);
+        assert_eq!(
+            control_sgrna_indices(&names, &["g2_a".to_string()], "ntc")?,
+            vec![2]
+        );
+        assert!(control_sgrna_indices(&names, &[], "missing").is_err());
+        assert!(control_sgrna_indices(&names, &[], "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_control_normalization() -> Result<()> {
+        // the second sample is sequenced twice as deep but the first sgRNA is strongly
+        // depleted which would distort the size factors of the bulk library
+        let matrix = array![[100., 10.], [100., 200.], [50., 100.], [20., 40.]];
+        let norm = &matrix / control_size_factors(&matrix, &[1, 2, 3])?;
+        assert!((norm[[1, 0]] - norm[[1, 1]]).abs() < 1e-10);
+        assert!((norm[[3, 0]] - norm[[3, 1]]).abs() < 1e-10);
+        assert!(norm[[0, 1]] < norm[[0, 0]]);
+
+        let zeros = array![[1., 2.], [0., 0.]];
+        assert!(control_size_factors(&zeros, &[1]).is_err());
+        Ok(())
+    }
+}
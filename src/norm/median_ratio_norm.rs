@@ -1,13 +1,15 @@
 use anyhow::{bail, Result};
 use ndarray::{Array1, Array2, ArrayView1, Axis};
 use ndarray_stats::SummaryStatisticsExt;
-use std::ops::{Div, Mul};
+use std::ops::Div;
+
+use super::utils::center_size_factors;
 
 /// Calculates the median of a provided ndarray
 pub fn median(array: &ArrayView1<f64>) -> f64 {
     let mut sorted = array.to_vec();
     sorted.sort_by(|a, b| a.partial_cmp(b).expect("NaN Uncovered in Median"));
-    if array.len() % 2 == 0 {
+    if array.len().is_multiple_of(2) {
         let rhs = array.len().div(2);
         let lhs = rhs - 1;
         (sorted[lhs] + sorted[rhs]).div(2.)
@@ -17,38 +19,79 @@ pub fn median(array: &ArrayView1<f64>) -> f64 {
     }
 }
 
-/// Calculates the geometric means of each row in a 2D matrix
-/// This will also set `0.`s to `1.`s to avoid downstream numerical instability
-fn geometric_means(matrix: &Array2<f64>) -> Array1<f64> {
-    matrix
-        .map_axis(Axis(1), |row| {
-            row.geometric_mean().expect("Unexpected Empty Row")
-        })
-        .iter()
-        .map(|x| if *x == 0. { 1. } else { *x })
-        .collect()
+/// Selects the rows of a 2D matrix without any zero counts
+fn zero_free_rows(matrix: &Array2<f64>) -> Array2<f64> {
+    let rows = matrix
+        .axis_iter(Axis(0))
+        .enumerate()
+        .filter(|(_, row)| row.iter().all(|x| *x > 0.))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+    matrix.select(Axis(0), &rows)
 }
 
-/// Performs the median ratio normalization method.
-/// Read counts are adjusted by the median of the size factors of each `sgRNA`.
-/// Size factors are computed as the geometric mean of the `sgRNAs` across all
-/// experimental libraries.
-pub fn median_ratio_normalization(matrix: &Array2<f64>) -> Result<Array2<f64>> {
-    let gmeans = geometric_means(matrix);
-    let transformed_matrix = matrix.t().div(gmeans).reversed_axes();
-    let sample_scalars = transformed_matrix.map_axis(Axis(0), |axis| 1. / median(&axis));
-
-    if sample_scalars.iter().any(|x| x.is_infinite()) {
-        bail!("median-ratio is unstable")
+/// Calculates the median ratio size factors of each sample.
+/// Size factors are the median ratio of each `sgRNA` in a sample to the geometric
+/// mean of the `sgRNAs` across all experimental libraries.
+///
+/// Only the `sgRNAs` with nonzero counts in every sample are used, as a single zero
+/// collapses the geometric mean of an `sgRNA`.
+pub fn median_ratio_size_factors(matrix: &Array2<f64>) -> Result<Array1<f64>> {
+    let zero_free = zero_free_rows(matrix);
+    if zero_free.nrows() == 0 {
+        bail!("median-ratio is unstable - no sgRNAs have nonzero counts in every sample")
     }
+    let gmeans = zero_free.map_axis(Axis(1), |row| {
+        row.geometric_mean().expect("Unexpected Empty Row")
+    });
+    let transformed_matrix = zero_free.t().div(gmeans).reversed_axes();
+    Ok(transformed_matrix.map_axis(Axis(0), |axis| median(&axis)))
+}
+
+/// Calculates the median ratio size factors of each sample using only positive counts.
+///
+/// Zeros are ignored in the geometric mean of each `sgRNA` (they contribute as a count of
+/// one) and in the ratios of each sample. The size factors are scaled to a geometric
+/// mean of one. This is robust to libraries where most `sgRNAs` have a zero in at least
+/// one sample.
+pub fn poscounts_size_factors(matrix: &Array2<f64>) -> Result<Array1<f64>> {
+    let log_gmeans = matrix.map_axis(Axis(1), |row| {
+        if row.iter().all(|x| *x == 0.) {
+            f64::NEG_INFINITY
+        } else {
+            row.iter()
+                .map(|x| if *x > 0. { x.ln() } else { 0. })
+                .sum::<f64>()
+                / row.len() as f64
+        }
+    });
 
-    Ok(matrix.mul(sample_scalars))
+    let log_ratios = matrix
+        .axis_iter(Axis(1))
+        .map(|column| {
+            column
+                .iter()
+                .zip(log_gmeans.iter())
+                .filter(|(x, g)| **x > 0. && g.is_finite())
+                .map(|(x, g)| x.ln() - g)
+                .collect::<Array1<f64>>()
+        })
+        .collect::<Vec<_>>();
+    if log_ratios.iter().any(|ratios| ratios.is_empty()) {
+        bail!("poscounts is unstable - a sample has no positive counts")
+    }
+
+    let size_factors = log_ratios
+        .iter()
+        .map(|ratios| median(&ratios.view()).exp())
+        .collect::<Array1<f64>>();
+    Ok(center_size_factors(size_factors))
 }
 
 #[cfg(test)]
 mod testing {
-    use super::{median, median_ratio_normalization};
-    use ndarray::{Array1, Array2};
+    use super::{median, median_ratio_size_factors, poscounts_size_factors};
+    use ndarray::{array, Array1, Array2};
     use ndarray_rand::{rand_distr::Uniform, RandomExt};
 
     #[test]
@@ -67,17 +110,48 @@ mod testing {
     fn test_median_normalization() {
         (0..1000).for_each(|_| {
             let matrix = Array2::random((10, 4), Uniform::new(1., 10.));
-            let norm = median_ratio_normalization(&matrix).unwrap();
+            let size_factors = median_ratio_size_factors(&matrix).unwrap();
+            let norm = &matrix / &size_factors;
 
             // matrices must be equal shape
             assert_eq!(norm.shape(), matrix.shape());
+
+            // one finite and positive size factor per sample
+            assert_eq!(size_factors.len(), matrix.ncols());
+            assert!(size_factors.iter().all(|x| x.is_finite() && *x > 0.));
         })
     }
 
     #[test]
     fn test_median_error() {
         let matrix = Array2::zeros((10, 4));
-        let norm = median_ratio_normalization(&matrix);
-        assert!(norm.is_err());
+        let size_factors = median_ratio_size_factors(&matrix);
+        assert!(size_factors.is_err());
+    }
+
+    #[test]
+    fn test_median_ratio_skips_zeros() {
+        // the second sample is twice the depth of the first and the zero row is ignored
+        let matrix = array![[10., 20.], [20., 40.], [0., 50.], [40., 80.]];
+        let size_factors = median_ratio_size_factors(&matrix).unwrap();
+        assert!((size_factors[1] / size_factors[0] - 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_poscounts() {
+        // every sgRNA has a zero in at least one sample
+        let matrix = array![
+            [0., 20., 10.],
+            [10., 0., 10.],
+            [10., 20., 0.],
+            [20., 40., 0.]
+        ];
+        assert!(median_ratio_size_factors(&matrix).is_err());
+        let size_factors = poscounts_size_factors(&matrix).unwrap();
+        assert!(size_factors[1] > size_factors[0]);
+        assert!((size_factors.product() - 1.).abs() < 1e-12);
+
+        let empty = array![[0., 1.], [0., 2.]];
+        assert!(poscounts_size_factors(&empty).is_err());
     }
 }
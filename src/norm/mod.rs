@@ -1,8 +1,21 @@
+mod control_norm;
 mod median_ratio_norm;
 mod normalize_counts;
+mod quantile_norm;
+mod tmm_norm;
 mod total_norm;
+mod upper_quartile_norm;
+mod utils;
 
+pub use control_norm::control_sgrna_indices;
+use control_norm::control_size_factors;
 pub use median_ratio_norm::median;
-use median_ratio_norm::median_ratio_normalization;
-pub use normalize_counts::{normalize_counts, Normalization};
-use total_norm::total_normalization;
+use median_ratio_norm::{median_ratio_size_factors, poscounts_size_factors};
+pub use normalize_counts::{
+    apply_size_factors, normalize_counts, Normalization, NormalizedCounts, StrictNormalizationError,
+};
+use quantile_norm::{quantile_normalization, quantile_size_factors};
+use tmm_norm::tmm_size_factors;
+use total_norm::total_size_factors;
+use upper_quartile_norm::upper_quartile_size_factors;
+pub use utils::{average_ranks, quantile};
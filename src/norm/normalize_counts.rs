@@ -1,34 +1,332 @@
 use crate::utils::logging::Logger;
+use anyhow::{bail, Result};
 use clap::ValueEnum;
-use ndarray::Array2;
+use ndarray::{Array1, Array2, Axis};
+use std::fmt::Display;
 
-use super::{median_ratio_normalization, total_normalization};
+use super::{
+    control_size_factors, median_ratio_size_factors, poscounts_size_factors,
+    quantile_normalization, quantile_size_factors, tmm_size_factors, total_size_factors,
+    upper_quartile_size_factors,
+};
 
 #[derive(ValueEnum, Debug, Clone, Default, PartialEq, Eq)]
 pub enum Normalization {
-    /// Median ratio of sgRNA geometric means (unstable at high zeros)
+    /// Median ratio of sgRNA geometric means over sgRNAs without zeros
     #[default]
     MedianRatio,
 
+    /// Median ratio over positive counts only (robust to zeros in most sgRNAs)
+    Poscounts,
+
     /// Total read count per sample scaling (more stable)
     Total,
+
+    /// Median ratio of control sgRNAs only (non-targeting token or a provided list)
+    Control,
+
+    /// Upper quartile of the counts of expressed sgRNAs per sample
+    UpperQuartile,
+
+    /// Trimmed mean of M-values against a reference sample (robust to strong hits)
+    Tmm,
+
+    /// Map each sample onto the mean sorted count distribution (no single size factor)
+    Quantile,
+
+    /// Size factors provided by the user (set by `--size-factors`)
+    #[value(skip)]
+    Provided,
+}
+impl Normalization {
+    /// The name of the method as provided on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MedianRatio => "median-ratio",
+            Self::Poscounts => "poscounts",
+            Self::Total => "total",
+            Self::Control => "control",
+            Self::UpperQuartile => "upper-quartile",
+            Self::Tmm => "tmm",
+            Self::Quantile => "quantile",
+            Self::Provided => "provided",
+        }
+    }
+}
+
+/// Error raised when the requested normalization cannot be applied in strict mode
+#[derive(Debug)]
+pub struct StrictNormalizationError {
+    method: Normalization,
+    reason: String,
+}
+impl Display for StrictNormalizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unable to perform {} normalization in strict mode: {}",
+            self.method.name(),
+            self.reason
+        )
+    }
+}
+impl std::error::Error for StrictNormalizationError {}
+
+/// Calculates the fraction of zero counts in each sample
+fn zero_fractions(count_matrix: &Array2<f64>) -> Array1<f64> {
+    count_matrix.map_axis(Axis(0), |column| {
+        column.iter().filter(|x| **x == 0.).count() as f64 / column.len() as f64
+    })
+}
+
+/// The normalized count matrix and the per-sample size factors used to produce it
+#[derive(Debug, Clone)]
+pub struct NormalizedCounts {
+    matrix: Array2<f64>,
+    size_factors: Array1<f64>,
+    zero_fractions: Array1<f64>,
+    method: Normalization,
+    fallback: Option<String>,
+}
+impl NormalizedCounts {
+    /// Divides the counts of each sample by its size factor
+    fn from_size_factors(
+        count_matrix: &Array2<f64>,
+        size_factors: Array1<f64>,
+        method: Normalization,
+    ) -> Self {
+        Self {
+            matrix: count_matrix / &size_factors,
+            zero_fractions: zero_fractions(count_matrix),
+            size_factors,
+            method,
+            fallback: None,
+        }
+    }
+
+    /// Records the reason the requested normalization method was replaced
+    fn with_fallback(self, reason: String) -> Self {
+        Self {
+            fallback: Some(reason),
+            ..self
+        }
+    }
+
+    pub fn matrix(&self) -> &Array2<f64> {
+        &self.matrix
+    }
+
+    pub fn into_matrix(self) -> Array2<f64> {
+        self.matrix
+    }
+
+    /// The divisor applied to each sample (for quantile normalization this is the ratio of
+    /// the original to the normalized total counts)
+    pub fn size_factors(&self) -> &Array1<f64> {
+        &self.size_factors
+    }
+
+    /// The fraction of zero counts in each sample
+    pub fn zero_fractions(&self) -> &Array1<f64> {
+        &self.zero_fractions
+    }
+
+    /// The normalization method that was actually applied
+    pub fn method(&self) -> &Normalization {
+        &self.method
+    }
+
+    /// The reason the requested normalization method was replaced (if it was)
+    pub fn fallback(&self) -> Option<&str> {
+        self.fallback.as_deref()
+    }
+}
+
+/// Performs median ratio normalization and falls back to `poscounts` and then `total`
+/// normalization if the size factors cannot be estimated.
+///
+/// Every fallback is reported with its reason and the fraction of zeros in each sample.
+/// In strict mode a fallback is an error instead.
+fn median_ratio_with_fallback(
+    count_matrix: &Array2<f64>,
+    strict: bool,
+    logger: &Logger,
+) -> Result<NormalizedCounts> {
+    let mut reason = match median_ratio_size_factors(count_matrix) {
+        Ok(size_factors) => {
+            return Ok(NormalizedCounts::from_size_factors(
+                count_matrix,
+                size_factors,
+                Normalization::MedianRatio,
+            ))
+        }
+        Err(e) => e.to_string(),
+    };
+    logger.zero_fractions(&zero_fractions(count_matrix));
+    if strict {
+        return Err(StrictNormalizationError {
+            method: Normalization::MedianRatio,
+            reason,
+        }
+        .into());
+    }
+
+    let normed = match poscounts_size_factors(count_matrix) {
+        Ok(size_factors) => NormalizedCounts::from_size_factors(
+            count_matrix,
+            size_factors,
+            Normalization::Poscounts,
+        ),
+        Err(e) => {
+            reason = format!("{reason}; {e}");
+            NormalizedCounts::from_size_factors(
+                count_matrix,
+                total_size_factors(count_matrix),
+                Normalization::Total,
+            )
+        }
+    };
+    logger.convert_normalization(&Normalization::MedianRatio, normed.method(), &reason);
+    Ok(normed.with_fallback(reason))
+}
+
+/// Normalize read counts by dividing each sample by a user-supplied size factor
+pub fn apply_size_factors(
+    count_matrix: &Array2<f64>,
+    size_factors: Array1<f64>,
+    logger: &Logger,
+) -> NormalizedCounts {
+    logger.size_factors(&size_factors);
+    NormalizedCounts::from_size_factors(count_matrix, size_factors, Normalization::Provided)
 }
 
 /// Normalize read counts using the provided method
+///
+/// The indices of the control sgRNAs are only required for `Control` normalization.
+/// If `strict` is set the median ratio normalization will not fall back to another method
+/// and a [`StrictNormalizationError`] is returned instead.
 pub fn normalize_counts(
     count_matrix: &Array2<f64>,
     normalization: &Normalization,
+    controls: Option<&[usize]>,
+    strict: bool,
     logger: &Logger,
-) -> Array2<f64> {
-    match normalization {
-        Normalization::MedianRatio => {
-            if let Ok(normed_matrix) = median_ratio_normalization(count_matrix) {
-                normed_matrix
-            } else {
-                logger.convert_normalization();
-                total_normalization(count_matrix)
+) -> Result<NormalizedCounts> {
+    let normed = match normalization {
+        Normalization::MedianRatio => median_ratio_with_fallback(count_matrix, strict, logger)?,
+        Normalization::Poscounts => NormalizedCounts::from_size_factors(
+            count_matrix,
+            poscounts_size_factors(count_matrix)?,
+            Normalization::Poscounts,
+        ),
+        Normalization::Total => NormalizedCounts::from_size_factors(
+            count_matrix,
+            total_size_factors(count_matrix),
+            Normalization::Total,
+        ),
+        Normalization::Control => match controls {
+            Some(controls) => {
+                logger.num_control_sgrnas(controls.len());
+                NormalizedCounts::from_size_factors(
+                    count_matrix,
+                    control_size_factors(count_matrix, controls)?,
+                    Normalization::Control,
+                )
+            }
+            None => bail!("Control normalization requires control sgRNAs"),
+        },
+        Normalization::Provided => {
+            bail!("Provided normalization requires a size factors file")
+        }
+        Normalization::UpperQuartile => NormalizedCounts::from_size_factors(
+            count_matrix,
+            upper_quartile_size_factors(count_matrix)?,
+            Normalization::UpperQuartile,
+        ),
+        Normalization::Tmm => NormalizedCounts::from_size_factors(
+            count_matrix,
+            tmm_size_factors(count_matrix)?,
+            Normalization::Tmm,
+        ),
+        Normalization::Quantile => {
+            let matrix = quantile_normalization(count_matrix);
+            NormalizedCounts {
+                size_factors: quantile_size_factors(count_matrix, &matrix),
+                zero_fractions: zero_fractions(count_matrix),
+                matrix,
+                method: Normalization::Quantile,
+                fallback: None,
+            }
+        }
+    };
+    logger.size_factors(normed.size_factors());
+    Ok(normed)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_normalize_counts() -> Result<()> {
+        let logger = Logger::new_silent();
+        let matrix = array![[10., 20.], [20., 40.], [30., 60.], [40., 80.]];
+        for method in Normalization::value_variants() {
+            if matches!(method, Normalization::Control | Normalization::Provided) {
+                continue;
             }
+            let normed = normalize_counts(&matrix, method, None, false, &logger)?;
+            assert_eq!(normed.method(), method);
+            assert_eq!(normed.size_factors().len(), 2);
+
+            // both samples have the same composition at different depths
+            assert!(
+                (normed.matrix()[[3, 0]] - normed.matrix()[[3, 1]]).abs() < 1e-9,
+                "{method:?}"
+            );
         }
-        Normalization::Total => total_normalization(count_matrix),
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_counts_fallback() -> Result<()> {
+        let logger = Logger::new_silent();
+        let matrix = array![[0., 20.], [0., 20.], [20., 0.]];
+        let normed = normalize_counts(&matrix, &Normalization::MedianRatio, None, false, &logger)?;
+        assert_eq!(normed.method(), &Normalization::Poscounts);
+        assert!(normed.fallback().is_some());
+        assert_eq!(normed.zero_fractions().to_vec(), vec![2. / 3., 1. / 3.]);
+
+        // a sample without any counts falls back to total normalization
+        let matrix = array![[0., 20.], [0., 20.]];
+        let normed = normalize_counts(&matrix, &Normalization::MedianRatio, None, false, &logger)?;
+        assert_eq!(normed.method(), &Normalization::Total);
+
+        assert!(normalize_counts(&matrix, &Normalization::Control, None, false, &logger).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_counts_strict() -> Result<()> {
+        let logger = Logger::new_silent();
+        let matrix = array![[0., 20.], [0., 20.], [20., 0.]];
+        let error = normalize_counts(&matrix, &Normalization::MedianRatio, None, true, &logger)
+            .unwrap_err();
+        assert!(error.is::<StrictNormalizationError>());
+
+        let matrix = array![[10., 20.], [20., 40.]];
+        let normed = normalize_counts(&matrix, &Normalization::MedianRatio, None, true, &logger)?;
+        assert!(normed.fallback().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_size_factors() {
+        let logger = Logger::new_silent();
+        let matrix = array![[10., 20.], [20., 40.]];
+        let normed = apply_size_factors(&matrix, array![1., 2.], &logger);
+        assert_eq!(normed.method(), &Normalization::Provided);
+        assert_eq!(normed.matrix(), &array![[10., 10.], [20., 20.]]);
     }
 }
@@ -0,0 +1,65 @@
+use super::utils::average_ranks;
+use ndarray::{Array1, Array2, Axis};
+
+/// Performs quantile normalization.
+///
+/// Every sample is mapped onto the same distribution: the mean of the sorted counts
+/// across all samples. Tied counts within a sample are assigned the interpolated value
+/// of their average rank.
+pub fn quantile_normalization(matrix: &Array2<f64>) -> Array2<f64> {
+    let mut sorted = matrix.to_owned();
+    sorted.axis_iter_mut(Axis(1)).for_each(|mut column| {
+        let mut values = column.to_vec();
+        values.sort_by(|a, b| a.total_cmp(b));
+        column.assign(&Array1::from_vec(values));
+    });
+    let rank_means = sorted.mean_axis(Axis(1)).expect("Unexpected Empty Input");
+
+    let mut normed = Array2::zeros(matrix.raw_dim());
+    matrix
+        .axis_iter(Axis(1))
+        .zip(normed.axis_iter_mut(Axis(1)))
+        .for_each(|(column, mut normed_column)| {
+            let ranks = average_ranks(column.as_slice().unwrap_or(&column.to_vec()));
+            ranks.iter().enumerate().for_each(|(idx, rank)| {
+                let position = rank - 1.;
+                let lower = position.floor() as usize;
+                let upper = position.ceil() as usize;
+                normed_column[idx] = rank_means[lower]
+                    + (rank_means[upper] - rank_means[lower]) * (position - lower as f64);
+            });
+        });
+    normed
+}
+
+/// Calculates the effective size factors of a quantile normalization as the ratio of the
+/// original to the normalized total counts of each sample
+pub fn quantile_size_factors(matrix: &Array2<f64>, normed_matrix: &Array2<f64>) -> Array1<f64> {
+    matrix.sum_axis(Axis(0)) / normed_matrix.sum_axis(Axis(0))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_quantile_normalization() {
+        let matrix = array![[5., 4., 3.], [2., 1., 4.], [3., 4., 6.], [4., 2., 8.]];
+        let normed = quantile_normalization(&matrix);
+
+        // every sample shares the same sorted distribution without ties
+        let mut first = normed.column(0).to_vec();
+        let mut third = normed.column(2).to_vec();
+        first.sort_by(|a, b| a.total_cmp(b));
+        third.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(first, third);
+
+        // ties share the average of their values
+        assert_eq!(normed[[0, 1]], normed[[2, 1]]);
+        assert_eq!(normed.shape(), matrix.shape());
+
+        let size_factors = quantile_size_factors(&matrix, &normed);
+        assert_eq!(size_factors.len(), 3);
+    }
+}
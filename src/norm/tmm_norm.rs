@@ -0,0 +1,131 @@
+use super::utils::{average_ranks, center_size_factors, quantile};
+use anyhow::{bail, Result};
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+
+/// Fraction of the log-ratios (M-values) trimmed from each tail
+const LOGRATIO_TRIM: f64 = 0.3;
+
+/// Fraction of the mean abundances (A-values) trimmed from each tail
+const SUM_TRIM: f64 = 0.05;
+
+/// Calculates the trimmed mean of M-values between a sample and the reference sample.
+///
+/// The log-ratios and mean abundances are computed over the `sgRNAs` with nonzero counts
+/// in both samples, trimmed at both tails, and the remaining log-ratios are averaged with
+/// inverse variance weights.
+fn tmm_factor(
+    sample: &ArrayView1<f64>,
+    reference: &ArrayView1<f64>,
+    sample_size: f64,
+    reference_size: f64,
+) -> f64 {
+    let (log_ratios, (abundances, variances)): (Vec<f64>, (Vec<f64>, Vec<f64>)) = sample
+        .iter()
+        .zip(reference.iter())
+        .filter(|(s, r)| **s > 0. && **r > 0.)
+        .map(|(s, r)| {
+            let s_frac = s / sample_size;
+            let r_frac = r / reference_size;
+            (
+                (s_frac / r_frac).log2(),
+                (
+                    (s_frac * r_frac).log2() / 2.,
+                    (sample_size - s) / sample_size / s + (reference_size - r) / reference_size / r,
+                ),
+            )
+        })
+        .unzip();
+    let n = log_ratios.len() as f64;
+    if log_ratios.is_empty() {
+        return 1.;
+    }
+
+    let m_low = (n * LOGRATIO_TRIM).floor() + 1.;
+    let m_high = n + 1. - m_low;
+    let a_low = (n * SUM_TRIM).floor() + 1.;
+    let a_high = n + 1. - a_low;
+    let m_ranks = average_ranks(&log_ratios);
+    let a_ranks = average_ranks(&abundances);
+
+    let (weighted_sum, weight_sum) = (0..log_ratios.len())
+        .filter(|idx| {
+            (m_low..=m_high).contains(&m_ranks[*idx]) && (a_low..=a_high).contains(&a_ranks[*idx])
+        })
+        .fold((0., 0.), |(ws, w), idx| {
+            (
+                ws + log_ratios[idx] / variances[idx],
+                w + 1. / variances[idx],
+            )
+        });
+    if weight_sum == 0. {
+        1.
+    } else {
+        (weighted_sum / weight_sum).exp2()
+    }
+}
+
+/// Calculates the trimmed mean of M-values (TMM) size factors of each sample.
+///
+/// The reference sample is the sample whose library-size scaled upper quartile is closest
+/// to the mean of all samples. The size factors are the library sizes scaled by the TMM
+/// factors of each sample against the reference, scaled to a geometric mean of one.
+pub fn tmm_size_factors(matrix: &Array2<f64>) -> Result<Array1<f64>> {
+    let library_sizes = matrix.sum_axis(Axis(0));
+    if library_sizes.iter().any(|x| *x == 0.) {
+        bail!("TMM is unstable - a sample has no counts")
+    }
+
+    let scaled_quartiles = matrix
+        .axis_iter(Axis(1))
+        .zip(library_sizes.iter())
+        .map(|(column, size)| {
+            let mut sorted = column.to_vec();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            quantile(&sorted, 0.75) / size
+        })
+        .collect::<Vec<_>>();
+    let mean_quartile = scaled_quartiles.iter().sum::<f64>() / scaled_quartiles.len() as f64;
+    let reference = (0..scaled_quartiles.len())
+        .min_by(|a, b| {
+            (scaled_quartiles[*a] - mean_quartile)
+                .abs()
+                .total_cmp(&(scaled_quartiles[*b] - mean_quartile).abs())
+        })
+        .expect("Unexpected Empty Input");
+
+    let factors = (0..matrix.ncols())
+        .map(|idx| {
+            tmm_factor(
+                &matrix.column(idx),
+                &matrix.column(reference),
+                library_sizes[idx],
+                library_sizes[reference],
+            )
+        })
+        .collect::<Array1<f64>>();
+    Ok(center_size_factors(
+        library_sizes * center_size_factors(factors),
+    ))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::Array2;
+
+    #[test]
+    fn test_tmm_size_factors() -> Result<()> {
+        // the second sample is twice the depth of the first except for a strongly
+        // enriched sgRNA which inflates its library size
+        let mut matrix =
+            Array2::from_shape_fn((100, 2), |(i, j)| (i as f64 + 10.) * (j as f64 + 1.));
+        matrix[[0, 1]] = 20000.;
+        let size_factors = tmm_size_factors(&matrix)?;
+        let ratio = size_factors[1] / size_factors[0];
+        assert!((ratio - 2.).abs() < 0.05, "{ratio}");
+
+        let zeros = Array2::zeros((4, 2));
+        assert!(tmm_size_factors(&zeros).is_err());
+        Ok(())
+    }
+}
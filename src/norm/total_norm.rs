@@ -1,19 +1,18 @@
-use ndarray::{Array2, Axis};
+use ndarray::{Array1, Array2, Axis};
 
-/// Performs normalization using the average of total reads in each sample
-pub fn total_normalization(matrix: &Array2<f64>) -> Array2<f64> {
+/// Calculates the size factors of each sample as the ratio of its total reads to the
+/// average total reads of all samples
+pub fn total_size_factors(matrix: &Array2<f64>) -> Array1<f64> {
     let sample_totals = matrix.sum_axis(Axis(0));
 
     let average_size = sample_totals.mean().expect("Unexpected Empty Input");
 
-    let sample_factor = average_size / sample_totals;
-
-    matrix * sample_factor
+    sample_totals / average_size
 }
 
 #[cfg(test)]
 mod testing {
-    use super::total_normalization;
+    use super::total_size_factors;
     use ndarray::{Array2, Axis};
     use ndarray_rand::{rand_distr::Uniform, RandomExt};
     use ndarray_stats::QuantileExt;
@@ -22,7 +21,7 @@ mod testing {
     fn test_total_normalization() {
         (0..1000).for_each(|_| {
             let matrix = Array2::random((10, 4), Uniform::new(0, 5)).mapv(f64::from);
-            let norm = total_normalization(&matrix);
+            let norm = &matrix / total_size_factors(&matrix);
 
             // take sample sums for each matrix
             let sample_sums = matrix.sum_axis(Axis(0));
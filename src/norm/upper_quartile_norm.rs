@@ -0,0 +1,52 @@
+use super::utils::{center_size_factors, quantile};
+use anyhow::{bail, Result};
+use ndarray::{Array1, Array2, Axis};
+
+/// Quantile of the sample counts used as the size factor
+const UPPER_QUARTILE: f64 = 0.75;
+
+/// Calculates the upper-quartile size factors of each sample.
+/// Size factors are the upper quartile of the counts of each sample over the `sgRNAs`
+/// with at least one nonzero count across all libraries, scaled to a geometric mean of one.
+pub fn upper_quartile_size_factors(matrix: &Array2<f64>) -> Result<Array1<f64>> {
+    let expressed = matrix
+        .axis_iter(Axis(0))
+        .enumerate()
+        .filter(|(_, row)| row.iter().any(|x| *x > 0.))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+    if expressed.is_empty() {
+        bail!("upper-quartile is unstable - no sgRNAs have nonzero counts")
+    }
+
+    let quartiles = matrix
+        .select(Axis(0), &expressed)
+        .map_axis(Axis(0), |column| {
+            let mut sorted = column.to_vec();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            quantile(&sorted, UPPER_QUARTILE)
+        });
+    if quartiles.iter().any(|x| *x == 0.) {
+        bail!("upper-quartile is unstable - a sample has an upper quartile of zero")
+    }
+    Ok(center_size_factors(quartiles))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_upper_quartile_size_factors() -> Result<()> {
+        // the second sample is twice the depth of the first
+        let matrix = array![[1., 2.], [2., 4.], [3., 6.], [4., 8.], [5., 10.], [0., 0.]];
+        let size_factors = upper_quartile_size_factors(&matrix)?;
+        assert!((size_factors[1] / size_factors[0] - 2.).abs() < 1e-12);
+        assert!((size_factors.product() - 1.).abs() < 1e-12);
+
+        let zeros = array![[0., 1.], [0., 0.]];
+        assert!(upper_quartile_size_factors(&zeros).is_err());
+        Ok(())
+    }
+}
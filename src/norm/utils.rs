@@ -0,0 +1,61 @@
+use ndarray::Array1;
+
+/// Calculates the quantile of a sorted slice with linear interpolation between the
+/// closest ranks
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+/// Calculates the 1-based ranks of the provided values where ties are assigned the
+/// average of their ranks
+pub fn average_ranks(values: &[f64]) -> Vec<f64> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+
+    let mut ranks = vec![0.; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end + 1 < order.len() && values[order[end + 1]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end) as f64 / 2. + 1.;
+        order[start..=end].iter().for_each(|idx| ranks[*idx] = rank);
+        start = end + 1;
+    }
+    ranks
+}
+
+/// Scales size factors to a geometric mean of one
+pub fn center_size_factors(size_factors: Array1<f64>) -> Array1<f64> {
+    let log_mean = size_factors.mapv(f64::ln).mean().unwrap_or(0.);
+    size_factors / log_mean.exp()
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_quantile() {
+        let sorted = [1., 2., 3., 4., 5.];
+        assert_eq!(quantile(&sorted, 0.5), 3.);
+        assert_eq!(quantile(&sorted, 0.75), 4.);
+        assert_eq!(quantile(&sorted, 0.1), 1.4);
+    }
+
+    #[test]
+    fn test_average_ranks() {
+        assert_eq!(average_ranks(&[3., 1., 2., 1.]), vec![4., 1.5, 3., 1.5]);
+    }
+
+    #[test]
+    fn test_center_size_factors() {
+        let centered = center_size_factors(array![0.5, 2., 4.]);
+        assert!((centered.iter().product::<f64>() - 1.).abs() < 1e-12);
+    }
+}
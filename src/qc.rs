@@ -0,0 +1,305 @@
+use anyhow::Result;
+use bon::builder;
+use ndarray::{Array2, ArrayView1, Axis};
+use polars::prelude::*;
+use serde::Serialize;
+use std::{fs::File, io::BufWriter};
+
+use crate::{
+    io::{
+        build_regex_set, count_columns, get_annotation_columns, load_dataframe,
+        match_headers_from_regex_set, to_ndarray, validate_counts, write_table, Design,
+        OutputFormat,
+    },
+    norm::{average_ranks, normalize_counts, quantile, Normalization},
+    utils::{logging::Logger, math::pearson_correlation},
+};
+
+/// Pseudocount added to the counts before the log transform
+const PSEUDOCOUNT: f64 = 1.;
+
+/// Library health metrics of a single sample
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleQc {
+    sample: String,
+    condition: Option<String>,
+    read_depth: f64,
+    zero_fraction: f64,
+    gini_index: f64,
+    skew_ratio: f64,
+    coverage: f64,
+}
+
+/// Correlation of the log normalized counts of two replicate samples
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicateCorrelation {
+    condition: Option<String>,
+    sample_a: String,
+    sample_b: String,
+    pearson: f64,
+    spearman: f64,
+}
+
+/// Machine-readable summary of the library quality control
+#[derive(Debug, Clone, Serialize)]
+pub struct QcSummary {
+    n_sgrnas: usize,
+    n_genes: usize,
+    normalization: &'static str,
+    samples: Vec<SampleQc>,
+    correlations: Vec<ReplicateCorrelation>,
+}
+
+/// Calculates the Gini index of the log2 counts of a sample
+///
+/// Like MAGeCK the index is calculated on the log scale, where 0 is a perfectly even
+/// library and values approaching 1 indicate that few sgRNAs dominate the reads.
+fn gini_index(counts: &ArrayView1<f64>) -> f64 {
+    let mut sorted = counts
+        .iter()
+        .map(|x| (x + PSEUDOCOUNT).log2())
+        .collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len() as f64;
+    let total = sorted.iter().sum::<f64>();
+    if total == 0. {
+        return 0.;
+    }
+    let weighted = sorted
+        .iter()
+        .enumerate()
+        .map(|(idx, x)| (idx as f64 + 1.) * x)
+        .sum::<f64>();
+    2. * weighted / (n * total) - (n + 1.) / n
+}
+
+/// Calculates the ratio of the 90th to the 10th percentile of the counts of a sample
+///
+/// The ratio is infinite if the 10th percentile is zero.
+fn skew_ratio(counts: &ArrayView1<f64>) -> f64 {
+    let mut sorted = counts.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    quantile(&sorted, 0.9) / quantile(&sorted, 0.1)
+}
+
+fn sample_qc(sample: &str, condition: Option<&str>, counts: &ArrayView1<f64>) -> SampleQc {
+    let read_depth = counts.sum();
+    SampleQc {
+        sample: sample.to_string(),
+        condition: condition.map(|x| x.to_string()),
+        read_depth,
+        zero_fraction: counts.iter().filter(|x| **x == 0.).count() as f64 / counts.len() as f64,
+        gini_index: gini_index(counts),
+        skew_ratio: skew_ratio(counts),
+        coverage: read_depth / counts.len() as f64,
+    }
+}
+
+/// Calculates the Spearman rank correlation of two equal-length slices
+fn spearman_correlation(x: &[f64], y: &[f64]) -> f64 {
+    pearson_correlation(&average_ranks(x), &average_ranks(y))
+}
+
+/// Correlates every pair of samples that share a condition
+///
+/// Without a design every sample has no condition and all pairs are correlated.
+fn replicate_correlations(
+    log_matrix: &Array2<f64>,
+    labels: &[String],
+    conditions: &[Option<String>],
+) -> Vec<ReplicateCorrelation> {
+    let columns = log_matrix
+        .axis_iter(Axis(1))
+        .map(|x| x.to_vec())
+        .collect::<Vec<_>>();
+    let mut correlations = Vec::new();
+    for i in 0..labels.len() {
+        for j in (i + 1)..labels.len() {
+            if conditions[i] != conditions[j] {
+                continue;
+            }
+            correlations.push(ReplicateCorrelation {
+                condition: conditions[i].clone(),
+                sample_a: labels[i].clone(),
+                sample_b: labels[j].clone(),
+                pearson: pearson_correlation(&columns[i], &columns[j]),
+                spearman: spearman_correlation(&columns[i], &columns[j]),
+            });
+        }
+    }
+    correlations
+}
+
+fn build_sample_frame(samples: &[SampleQc]) -> PolarsResult<DataFrame> {
+    let collect = |f: fn(&SampleQc) -> f64| samples.iter().map(f).collect::<Vec<_>>();
+    df!(
+        "sample" => samples.iter().map(|x| x.sample.as_str()).collect::<Vec<_>>(),
+        "condition" => samples.iter().map(|x| x.condition.as_deref()).collect::<Vec<_>>(),
+        "read_depth" => collect(|x| x.read_depth),
+        "zero_fraction" => collect(|x| x.zero_fraction),
+        "gini_index" => collect(|x| x.gini_index),
+        "skew_ratio" => collect(|x| x.skew_ratio),
+        "coverage" => collect(|x| x.coverage),
+    )
+}
+
+fn build_correlation_frame(correlations: &[ReplicateCorrelation]) -> PolarsResult<DataFrame> {
+    df!(
+        "condition" => correlations.iter().map(|x| x.condition.as_deref()).collect::<Vec<_>>(),
+        "sample_a" => correlations.iter().map(|x| x.sample_a.as_str()).collect::<Vec<_>>(),
+        "sample_b" => correlations.iter().map(|x| x.sample_b.as_str()).collect::<Vec<_>>(),
+        "pearson" => correlations.iter().map(|x| x.pearson).collect::<Vec<_>>(),
+        "spearman" => correlations.iter().map(|x| x.spearman).collect::<Vec<_>>(),
+    )
+}
+
+#[builder]
+pub fn qc(
+    input: String,
+    design: Option<String>,
+    samples: Option<Vec<String>>,
+    sgrna_col: Option<String>,
+    gene_col: Option<String>,
+    norm: Normalization,
+    prefix: String,
+    quiet: bool,
+) -> Result<()> {
+    let logger = Logger::from_quiet(quiet);
+    logger.start_qc();
+
+    let frame = load_dataframe(input.into())?;
+    let design = match design {
+        Some(path) => {
+            let design = Design::from_path(path.into())?;
+            design.validate(&frame)?;
+            Some(design)
+        }
+        None => None,
+    };
+    let labels = match (samples, &design) {
+        (Some(samples), _) => match_headers_from_regex_set(&frame, &build_regex_set(&samples)?)?,
+        (None, Some(design)) => design
+            .samples()
+            .iter()
+            .map(|x| x.sample().to_string())
+            .collect(),
+        (None, None) => count_columns(&frame, sgrna_col.as_deref(), gene_col.as_deref()),
+    };
+    let conditions = labels
+        .iter()
+        .map(|x| {
+            design
+                .as_ref()
+                .and_then(|d| d.get(x))
+                .map(|x| x.condition().to_string())
+        })
+        .collect::<Vec<_>>();
+
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(&frame, sgrna_col.as_deref(), gene_col.as_deref())?;
+    let report = validate_counts(&frame, &labels, &sgrna_names, &gene_names, None)?;
+    logger.validation_report(&report);
+    report.into_result()?;
+
+    logger.sampled_names(&labels);
+    logger.num_sgrnas(&sgrna_names);
+    logger.num_genes(&gene_names);
+    logger.norm_method(&norm);
+
+    let count_matrix = to_ndarray(&frame, &labels)?;
+    let sample_metrics = labels
+        .iter()
+        .zip(conditions.iter())
+        .zip(count_matrix.axis_iter(Axis(1)))
+        .map(|((label, condition), counts)| sample_qc(label, condition.as_deref(), &counts))
+        .collect::<Vec<_>>();
+
+    let normed = normalize_counts(&count_matrix, &norm, None, false, &logger)?;
+    let log_matrix = normed.matrix().mapv(|x| (x + PSEUDOCOUNT).log2());
+    let correlations = replicate_correlations(&log_matrix, &labels, &conditions);
+    logger.qc_metrics(
+        &sample_metrics
+            .iter()
+            .map(|x| x.gini_index)
+            .collect::<Vec<_>>(),
+        &sample_metrics
+            .iter()
+            .map(|x| x.skew_ratio)
+            .collect::<Vec<_>>(),
+        correlations
+            .iter()
+            .map(|x| x.pearson)
+            .min_by(|a, b| a.total_cmp(b)),
+    );
+
+    write_table(
+        &mut build_sample_frame(&sample_metrics)?,
+        Some(format!("{prefix}.qc.tsv")),
+        OutputFormat::Tsv,
+    )?;
+    write_table(
+        &mut build_correlation_frame(&correlations)?,
+        Some(format!("{prefix}.qc_correlations.tsv")),
+        OutputFormat::Tsv,
+    )?;
+
+    let n_genes = {
+        let mut genes = gene_names.clone();
+        genes.sort_unstable();
+        genes.dedup();
+        genes.len()
+    };
+    let summary = QcSummary {
+        n_sgrnas: sgrna_names.len(),
+        n_genes,
+        normalization: normed.method().name(),
+        samples: sample_metrics,
+        correlations,
+    };
+    let writer = File::create(format!("{prefix}.qc.json")).map(BufWriter::new)?;
+    serde_json::to_writer_pretty(writer, &summary)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::{array, Array1};
+
+    #[test]
+    fn test_gini_index() {
+        let even = array![100., 100., 100., 100.];
+        assert!(gini_index(&even.view()).abs() < 1e-12);
+
+        let uneven = array![0., 0., 0., 1000.];
+        assert!((gini_index(&uneven.view()) - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_skew_ratio() {
+        let counts = Array1::linspace(1., 11., 11);
+        assert!((skew_ratio(&counts.view()) - 5.).abs() < 1e-12);
+
+        let zeros = array![0., 0., 5., 10.];
+        assert!(skew_ratio(&zeros.view()).is_infinite());
+    }
+
+    #[test]
+    fn test_replicate_correlations() {
+        let log_matrix = array![[1., 2., 3.], [2., 4., 1.], [3., 6., 2.]];
+        let labels = ["a_1", "a_2", "b_1"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        let conditions = vec![Some("a".to_string()), Some("a".to_string()), None];
+        let correlations = replicate_correlations(&log_matrix, &labels, &conditions);
+        assert_eq!(correlations.len(), 1);
+        assert_eq!(correlations[0].sample_b, "a_2");
+        assert!((correlations[0].pearson - 1.).abs() < 1e-12);
+        assert!((correlations[0].spearman - 1.).abs() < 1e-12);
+
+        let all_pairs = replicate_correlations(&log_matrix, &labels, &[None, None, None]);
+        assert_eq!(all_pairs.len(), 3);
+    }
+}
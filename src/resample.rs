@@ -7,7 +7,10 @@ use rand_chacha::ChaCha8Rng;
 use rand_distr::{Dirichlet, DirichletError, Distribution};
 
 use crate::{
-    io::{build_regex_set, load_dataframe, match_headers_from_regex_set, to_ndarray, write_tsv},
+    io::{
+        build_regex_set, load_dataframe, match_headers_from_regex_set, to_ndarray, write_table,
+        OutputFormat,
+    },
     norm::{normalize_counts, Normalization},
     utils::{logging::Logger, math::get_multinomial},
 };
@@ -102,6 +105,7 @@ pub fn resample(
     depth_samples: Option<Vec<String>>,
     seed: Option<u64>,
     samples: Vec<String>,
+    output_format: OutputFormat,
     quiet: bool,
 ) -> Result<()> {
     let logger = Logger::from_quiet(quiet);
@@ -115,7 +119,14 @@ pub fn resample(
     logger.number_of_resamples(n_resamples);
 
     let count_matrix = to_ndarray(&dataframe, &sample_labels)?;
-    let normed_matrix = normalize_counts(&count_matrix, &Normalization::default(), &logger);
+    let normed_matrix = normalize_counts(
+        &count_matrix,
+        &Normalization::default(),
+        None,
+        false,
+        &logger,
+    )?
+    .into_matrix();
     let sgrna_counts = normed_matrix
         .mean_axis(Axis(1))
         .expect("Could not generate mean of sgRNAs across normed samples");
@@ -130,7 +141,7 @@ pub fn resample(
     let resamples = loop_resample(&dirichlet, total, &mut rng, n_resamples)?;
 
     let mut full_data = dataframe.hstack(&resamples)?;
-    write_tsv(&mut full_data, path)?;
+    write_table(&mut full_data, path, output_format)?;
 
     Ok(())
 }
@@ -1,23 +1,27 @@
 use anyhow::Result;
-use ndarray::s;
+use ndarray::{s, Array1};
 use polars::frame::DataFrame;
 
 use crate::{
-    aggregation::compute_aggregation,
+    aggregation::{compute_aggregation, run_consensus, screen_performance, AggregationResult},
     cli::SgrnaColumns,
     enrich::EnrichmentResult,
-    io::{get_string_column, to_ndarray, write_gene_frame, write_hit_list, Screenviz},
+    io::{
+        get_annotation_columns, map_mageck_summary_columns, to_ndarray, write_gene_frame,
+        write_gene_summary, write_hit_list, Screenviz,
+    },
     utils::{config::Configuration, logging::Logger},
 };
 
 pub fn run_aggregation(
     frame: &DataFrame,
-    columns: SgrnaColumns,
+    mut columns: SgrnaColumns,
     config: &Configuration,
     logger: &Logger,
 ) -> Result<()> {
-    let sgrna_names = get_string_column(frame, 0);
-    let gene_names = get_string_column(frame, 1);
+    let mageck_input = map_mageck_summary_columns(frame, &mut columns);
+    let (sgrna_names, gene_names) =
+        get_annotation_columns(frame, Some(&columns.sgrna), Some(&columns.gene))?;
     let sgrna_matrix = to_ndarray(
         frame,
         &[
@@ -36,28 +40,94 @@ pub fn run_aggregation(
     );
 
     logger.start_mageck();
+    if mageck_input {
+        logger.mageck_input();
+    }
     logger.num_sgrnas(&sgrna_names);
     logger.num_genes(&gene_names);
     logger.aggregation_method(config.aggregation());
     logger.correction(*config.correction());
 
-    let aggregation_results = compute_aggregation(
-        config.aggregation(),
+    let sgrna_efficacy = config
+        .sgrna_efficacy()
+        .as_ref()
+        .map(|efficacy| efficacy.select(&sgrna_names));
+    let aggregation_results = compute_aggregation()
+        .agg(config.aggregation())
+        .sgrna_results(&enrichment_result)
+        .gene_names(&gene_names)
+        .sgrna_names(&sgrna_names)
+        .maybe_reference_sets(config.reference_sets().as_ref())
+        .maybe_sgrna_efficacy(sgrna_efficacy.as_ref())
+        .logger(logger)
+        .correction(*config.correction())
+        .seed(*config.seed())
+        .call()?;
+
+    write_aggregation_outputs(
+        &aggregation_results,
         &enrichment_result,
         &gene_names,
+        &sgrna_names,
+        sgrna_efficacy.as_ref(),
+        config,
         logger,
-        *config.correction(),
-        *config.seed(),
-    )?;
+    )
+}
 
-    // Write outputs
-    write_gene_frame(&aggregation_results, config.prefix())?;
+/// Writes the gene results, hit list, consensus, MAGeCK gene summary, reference set
+/// performance, and screenviz config of a gene aggregation
+pub fn write_aggregation_outputs(
+    aggregation_results: &AggregationResult,
+    sgrna_results: &EnrichmentResult,
+    gene_names: &[String],
+    sgrna_names: &[String],
+    sgrna_efficacy: Option<&Array1<f64>>,
+    config: &Configuration,
+    logger: &Logger,
+) -> Result<()> {
+    // Write gene results
+    write_gene_frame(
+        aggregation_results,
+        config.prefix(),
+        *config.output_format(),
+    )?;
 
     // Write hit list
-    write_hit_list(&aggregation_results, config, logger)?;
+    write_hit_list(aggregation_results, config, logger)?;
+
+    // Write consensus of all aggregation methods
+    if !config.consensus_aggregations().is_empty() {
+        run_consensus()
+            .primary(aggregation_results)
+            .sgrna_results(sgrna_results)
+            .gene_names(gene_names)
+            .sgrna_names(sgrna_names)
+            .maybe_sgrna_efficacy(sgrna_efficacy)
+            .config(config)
+            .logger(logger)
+            .call()?;
+    }
+
+    // Write MAGeCK gene summary
+    if *config.mageck_output() {
+        write_gene_summary(aggregation_results, gene_names, config.prefix())?;
+    }
+
+    // Write reference set performance
+    if let Some(sets) = config.reference_sets() {
+        if config.aggregation().uses_reference_sets() {
+            logger.skip_circular_performance(config.aggregation().name());
+        } else {
+            let performance =
+                screen_performance(aggregation_results, sets, config.aggregation().fdr())?;
+            logger.screen_performance(&performance);
+            performance.write(config.prefix())?;
+        }
+    }
 
     // Write screenviz config
-    let screenviz = Screenviz::new(&aggregation_results, config);
+    let screenviz = Screenviz::new(aggregation_results, config);
     screenviz.write(config.prefix())?;
 
     Ok(())
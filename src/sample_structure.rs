@@ -0,0 +1,433 @@
+use anyhow::Result;
+use ndarray::{s, Array1, Array2, Axis};
+use polars::prelude::*;
+
+use crate::{
+    io::{write_table, OutputFormat},
+    utils::math::symmetric_eigen,
+};
+
+/// Pseudocount added to the normalized counts before the log transform
+const PSEUDOCOUNT: f64 = 1.;
+
+/// Eigenvalues below this fraction of the total variance are not reported as components
+const MIN_VARIANCE_RATIO: f64 = 1e-12;
+
+/// A single agglomeration step of the hierarchical clustering
+///
+/// Samples are indexed by their position and the cluster formed at step `i` is indexed
+/// as `n_samples + i`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Merge {
+    left: usize,
+    right: usize,
+    distance: f64,
+    size: usize,
+}
+impl Merge {
+    pub fn left(&self) -> usize {
+        self.left
+    }
+
+    pub fn right(&self) -> usize {
+        self.right
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.distance
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// A sample that is closer on average to the samples of another group than to the other
+/// samples of its own group
+#[derive(Debug, Clone)]
+pub struct SampleOutlier {
+    sample: String,
+    group: String,
+    group_distance: f64,
+    nearest_group: String,
+    nearest_distance: f64,
+}
+impl SampleOutlier {
+    pub fn sample(&self) -> &str {
+        &self.sample
+    }
+
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    /// The mean distance to the other samples of its group
+    pub fn group_distance(&self) -> f64 {
+        self.group_distance
+    }
+
+    /// The group with the lowest mean distance to the sample
+    pub fn nearest_group(&self) -> &str {
+        &self.nearest_group
+    }
+
+    /// The mean distance to the samples of the nearest group
+    pub fn nearest_distance(&self) -> f64 {
+        self.nearest_distance
+    }
+}
+
+/// Principal components, pairwise distances, and hierarchical clustering of the samples on
+/// their log2 normalized counts
+#[derive(Debug)]
+pub struct SampleStructure {
+    labels: Vec<String>,
+    groups: Vec<String>,
+    coordinates: Array2<f64>,
+    explained_variance: Array1<f64>,
+    distances: Array2<f64>,
+    merges: Vec<Merge>,
+}
+impl SampleStructure {
+    /// Calculates the sample structure of a normalized count matrix (sgRNAs x samples)
+    ///
+    /// Each sample is assigned to a group (e.g. control or treatment) which is only used
+    /// to report outliers.
+    pub fn new(normed_matrix: &Array2<f64>, labels: &[String], groups: &[String]) -> Self {
+        assert_eq!(normed_matrix.ncols(), labels.len());
+        assert_eq!(labels.len(), groups.len());
+        let log_matrix = normed_matrix.mapv(|x| (x + PSEUDOCOUNT).log2());
+        let (coordinates, explained_variance) = principal_components(&log_matrix);
+        let distances = sample_distances(&log_matrix);
+        let merges = average_linkage(&distances);
+        Self {
+            labels: labels.to_vec(),
+            groups: groups.to_vec(),
+            coordinates,
+            explained_variance,
+            distances,
+            merges,
+        }
+    }
+
+    /// The fraction of the total variance explained by each principal component
+    pub fn explained_variance(&self) -> &Array1<f64> {
+        &self.explained_variance
+    }
+
+    /// The sample coordinates (samples x components)
+    pub fn coordinates(&self) -> &Array2<f64> {
+        &self.coordinates
+    }
+
+    /// The euclidean distances between the samples
+    pub fn distances(&self) -> &Array2<f64> {
+        &self.distances
+    }
+
+    pub fn merges(&self) -> &[Merge] {
+        &self.merges
+    }
+
+    /// Finds the samples that are closer to another group than to their own group
+    ///
+    /// A sample is compared by its mean distance to the other samples of its group and its
+    /// mean distance to the samples of every other group. Samples without another replicate
+    /// in their group are not evaluated.
+    pub fn outliers(&self) -> Vec<SampleOutlier> {
+        let mut group_names = self.groups.clone();
+        group_names.sort_unstable();
+        group_names.dedup();
+
+        let mut outliers = Vec::new();
+        for (idx, (sample, group)) in self.labels.iter().zip(self.groups.iter()).enumerate() {
+            let mean_distance = |name: &str| {
+                let distances = self
+                    .groups
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, g)| *other != idx && *g == name)
+                    .map(|(other, _)| self.distances[[idx, other]])
+                    .collect::<Vec<_>>();
+                (!distances.is_empty())
+                    .then(|| distances.iter().sum::<f64>() / distances.len() as f64)
+            };
+            let Some(group_distance) = mean_distance(group) else {
+                continue;
+            };
+            let nearest = group_names
+                .iter()
+                .filter(|name| *name != group)
+                .filter_map(|name| mean_distance(name).map(|d| (name, d)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((nearest_group, nearest_distance)) = nearest {
+                if nearest_distance < group_distance {
+                    outliers.push(SampleOutlier {
+                        sample: sample.clone(),
+                        group: group.clone(),
+                        group_distance,
+                        nearest_group: nearest_group.clone(),
+                        nearest_distance,
+                    });
+                }
+            }
+        }
+        outliers
+    }
+
+    /// Name of a sample or of a cluster formed during the clustering
+    fn node_name(&self, node: usize) -> String {
+        match self.labels.get(node) {
+            Some(label) => label.clone(),
+            None => format!("node{}", node - self.labels.len() + 1),
+        }
+    }
+
+    fn build_coordinate_frame(&self) -> PolarsResult<DataFrame> {
+        let mut columns = vec![
+            Series::new("sample".into(), &self.labels),
+            Series::new("group".into(), &self.groups),
+        ];
+        for (idx, component) in self.coordinates.axis_iter(Axis(1)).enumerate() {
+            columns.push(Series::new(
+                format!("PC{}", idx + 1).into(),
+                component.to_vec(),
+            ));
+        }
+        DataFrame::new(columns)
+    }
+
+    fn build_variance_frame(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "component" => (1..=self.explained_variance.len())
+                .map(|x| format!("PC{x}"))
+                .collect::<Vec<_>>(),
+            "variance_ratio" => self.explained_variance.to_vec(),
+        )
+    }
+
+    fn build_distance_frame(&self) -> PolarsResult<DataFrame> {
+        let mut columns = vec![Series::new("sample".into(), &self.labels)];
+        for (label, distances) in self.labels.iter().zip(self.distances.axis_iter(Axis(1))) {
+            columns.push(Series::new(label.into(), distances.to_vec()));
+        }
+        DataFrame::new(columns)
+    }
+
+    fn build_clustering_frame(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "node" => (0..self.merges.len())
+                .map(|x| self.node_name(self.labels.len() + x))
+                .collect::<Vec<_>>(),
+            "left" => self.merges.iter().map(|x| self.node_name(x.left)).collect::<Vec<_>>(),
+            "right" => self.merges.iter().map(|x| self.node_name(x.right)).collect::<Vec<_>>(),
+            "distance" => self.merges.iter().map(|x| x.distance).collect::<Vec<_>>(),
+            "size" => self.merges.iter().map(|x| x.size as u64).collect::<Vec<_>>(),
+        )
+    }
+
+    /// Writes the sample coordinates to `<prefix>.sample_pca.tsv`, the explained variance
+    /// to `<prefix>.sample_pca_variance.tsv`, the distance matrix to
+    /// `<prefix>.sample_distances.tsv`, and the clustering to `<prefix>.sample_clustering.tsv`
+    pub fn write(&self, prefix: &str) -> Result<()> {
+        for (mut frame, suffix) in [
+            (self.build_coordinate_frame()?, "sample_pca"),
+            (self.build_variance_frame()?, "sample_pca_variance"),
+            (self.build_distance_frame()?, "sample_distances"),
+            (self.build_clustering_frame()?, "sample_clustering"),
+        ] {
+            write_table(
+                &mut frame,
+                Some(format!("{prefix}.{suffix}.tsv")),
+                OutputFormat::Tsv,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the outlier samples to `<prefix>.sample_outliers.tsv`
+///
+/// The table only has a header if no sample is an outlier.
+pub fn write_outliers(outliers: &[SampleOutlier], prefix: &str) -> Result<()> {
+    let mut frame = df!(
+        "sample" => outliers.iter().map(|x| x.sample()).collect::<Vec<_>>(),
+        "group" => outliers.iter().map(|x| x.group()).collect::<Vec<_>>(),
+        "group_distance" => outliers.iter().map(|x| x.group_distance()).collect::<Vec<_>>(),
+        "nearest_group" => outliers.iter().map(|x| x.nearest_group()).collect::<Vec<_>>(),
+        "nearest_distance" => outliers.iter().map(|x| x.nearest_distance()).collect::<Vec<_>>(),
+    )?;
+    write_table(
+        &mut frame,
+        Some(format!("{prefix}.sample_outliers.tsv")),
+        OutputFormat::Tsv,
+    )
+}
+
+/// Calculates the principal components of the samples of a log matrix (sgRNAs x samples)
+///
+/// The sgRNAs are centered across the samples and the components are found from the
+/// eigendecomposition of the sample gram matrix, which is small for any screen. Returns
+/// the sample coordinates (samples x components) and the fraction of the total variance
+/// explained by each component.
+fn principal_components(log_matrix: &Array2<f64>) -> (Array2<f64>, Array1<f64>) {
+    let means = log_matrix
+        .mean_axis(Axis(1))
+        .expect("count matrix has no samples");
+    let centered = log_matrix - &means.insert_axis(Axis(1));
+    let gram = centered.t().dot(&centered);
+    let (values, vectors) = symmetric_eigen(&gram);
+
+    let total = values.iter().map(|x| x.max(0.)).sum::<f64>();
+    let n_components = if total > 0. {
+        values
+            .iter()
+            .take_while(|x| **x / total > MIN_VARIANCE_RATIO)
+            .count()
+    } else {
+        0
+    };
+    let mut coordinates = vectors.slice(s![.., ..n_components]).to_owned();
+    for (mut column, value) in coordinates.axis_iter_mut(Axis(1)).zip(values.iter()) {
+        column.mapv_inplace(|x| x * value.sqrt());
+    }
+    let explained = values.slice(s![..n_components]).mapv(|x| x / total);
+    (coordinates, explained)
+}
+
+/// Calculates the euclidean distances between the samples of a log matrix
+fn sample_distances(log_matrix: &Array2<f64>) -> Array2<f64> {
+    let n = log_matrix.ncols();
+    let mut distances = Array2::zeros((n, n));
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let distance = (&log_matrix.column(i) - &log_matrix.column(j))
+                .mapv(|x| x * x)
+                .sum()
+                .sqrt();
+            distances[[i, j]] = distance;
+            distances[[j, i]] = distance;
+        }
+    }
+    distances
+}
+
+/// Performs agglomerative hierarchical clustering with average linkage (UPGMA)
+///
+/// The closest pair of clusters is merged at every step and ties are broken by the
+/// lowest cluster index.
+fn average_linkage(distances: &Array2<f64>) -> Vec<Merge> {
+    let n = distances.nrows();
+    let mut distances = distances.to_owned();
+    let mut active = (0..n).map(|x| Some((x, 1))).collect::<Vec<_>>();
+    let mut merges = Vec::with_capacity(n.saturating_sub(1));
+    for step in 0..n.saturating_sub(1) {
+        let mut closest: Option<(usize, usize, f64)> = None;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                if active[i].is_none() || active[j].is_none() {
+                    continue;
+                }
+                if closest.is_none_or(|(_, _, d)| distances[[i, j]] < d) {
+                    closest = Some((i, j, distances[[i, j]]));
+                }
+            }
+        }
+        let (i, j, distance) = closest.expect("at least two clusters remain");
+        let (node_i, size_i) = active[i].unwrap();
+        let (node_j, size_j) = active[j].unwrap();
+
+        // the merged cluster takes the slot of `i`
+        for k in 0..n {
+            if k == i || k == j || active[k].is_none() {
+                continue;
+            }
+            let merged = (distances[[i, k]] * size_i as f64 + distances[[j, k]] * size_j as f64)
+                / (size_i + size_j) as f64;
+            distances[[i, k]] = merged;
+            distances[[k, i]] = merged;
+        }
+        active[i] = Some((n + step, size_i + size_j));
+        active[j] = None;
+        merges.push(Merge {
+            left: node_i.min(node_j),
+            right: node_i.max(node_j),
+            distance,
+            size: size_i + size_j,
+        });
+    }
+    merges
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use ndarray::array;
+
+    fn to_strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_principal_components() {
+        // samples differ only along a single direction
+        let log_matrix = array![[0., 1., 2.], [0., 1., 2.]];
+        let (coordinates, explained) = principal_components(&log_matrix);
+        assert_eq!(explained.len(), 1);
+        assert!((explained[0] - 1.).abs() < 1e-12);
+        let projected = coordinates.column(0);
+        assert!((projected[0] + projected[2]).abs() < 1e-10);
+        assert!(projected[1].abs() < 1e-10);
+        assert!((projected[2] - projected[0] - 2. * 2f64.sqrt()).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_average_linkage() {
+        let distances = array![
+            [0., 1., 4., 5.],
+            [1., 0., 3., 6.],
+            [4., 3., 0., 2.],
+            [5., 6., 2., 0.],
+        ];
+        let merges = average_linkage(&distances);
+        assert_eq!(merges.len(), 3);
+        assert_eq!((merges[0].left(), merges[0].right()), (0, 1));
+        assert_eq!((merges[1].left(), merges[1].right()), (2, 3));
+        assert_eq!((merges[2].left(), merges[2].right()), (4, 5));
+        assert_eq!(merges[2].size(), 4);
+
+        // mean of the four pairwise distances between the clusters
+        assert!((merges[2].distance() - 4.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_outliers() {
+        // the third control sample resembles the treatments
+        let normed = array![
+            [100., 110., 10., 12., 11.],
+            [100., 105., 400., 410., 390.],
+            [50., 55., 50., 52., 51.],
+        ];
+        let labels = to_strings(&["c_1", "c_2", "c_3", "t_1", "t_2"]);
+        let groups = to_strings(&["control", "control", "control", "treatment", "treatment"]);
+        let structure = SampleStructure::new(&normed, &labels, &groups);
+        let outliers = structure.outliers();
+        assert_eq!(outliers.len(), 1);
+        assert_eq!(outliers[0].sample(), "c_3");
+        assert_eq!(outliers[0].nearest_group(), "treatment");
+        assert_eq!(structure.distances().dim(), (5, 5));
+        assert!(structure.explained_variance()[0] > 0.9);
+
+        let prefix = std::env::temp_dir()
+            .join(format!("crispr_screen_outliers_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        write_outliers(&outliers, &prefix).unwrap();
+        let path = format!("{prefix}.sample_outliers.tsv");
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("c_3\tcontrol\t"));
+    }
+}
@@ -1,16 +1,22 @@
 use crate::{
-    aggregation::GeneAggregation, enrich::TestStrategy, model::ModelChoice, norm::Normalization,
+    aggregation::{GeneAggregation, ReferenceSets},
+    enrich::{GlmTest, NtcCalibration, TestStrategy},
+    io::{OutputFormat, SgrnaEfficacy, SizeFactors},
+    model::ModelChoice,
+    norm::Normalization,
 };
 use adjustp::Procedure;
-use bon::{builder, Builder};
+use bon::Builder;
 use getset::Getters;
 
-#[derive(Debug, Getters, Builder)]
+#[derive(Debug, Clone, Getters, Builder)]
 #[getset(get = "pub")]
 pub struct Configuration<'a> {
     #[builder(default)]
     normalization: Normalization,
     aggregation: GeneAggregation<'a>,
+    #[builder(default)]
+    consensus_aggregations: Vec<GeneAggregation<'a>>,
     #[builder(default = Procedure::BenjaminiHochberg)]
     correction: Procedure,
     #[builder(default)]
@@ -20,9 +26,51 @@ pub struct Configuration<'a> {
     #[builder(default)]
     strategy: TestStrategy,
     #[builder(default)]
+    covariates: Vec<String>,
+    #[builder(default)]
+    glm_test: GlmTest,
+    #[builder(default)]
+    ntc_calibration: NtcCalibration,
+    #[builder(default)]
+    ntc_token: &'a str,
+    #[builder(default)]
+    control_sgrnas: Vec<String>,
+    size_factors: Option<SizeFactors>,
+    #[builder(default)]
+    strict_normalization: bool,
+    sgrna_column: Option<&'a str>,
+    gene_column: Option<&'a str>,
+    #[builder(default)]
+    output_format: OutputFormat,
+    #[builder(default)]
+    mageck_output: bool,
+    reference_sets: Option<ReferenceSets>,
+    sgrna_efficacy: Option<SgrnaEfficacy>,
+    #[builder(default)]
+    sample_pca: bool,
+    #[builder(default)]
+    warn_outliers: bool,
+    #[builder(default)]
     seed: u64,
     prefix: &'a str,
 }
+impl<'a> Configuration<'a> {
+    /// Iterates over the primary aggregation followed by any additional consensus methods
+    pub fn aggregations(&self) -> impl Iterator<Item = &GeneAggregation<'a>> {
+        std::iter::once(&self.aggregation).chain(self.consensus_aggregations.iter())
+    }
+
+    /// Returns a copy of the configuration that writes to a different output prefix
+    pub fn with_prefix<'b>(&self, prefix: &'b str) -> Configuration<'b>
+    where
+        'a: 'b,
+    {
+        Configuration {
+            prefix,
+            ..self.clone()
+        }
+    }
+}
 
 #[cfg(test)]
 mod testing {
@@ -105,4 +153,13 @@ mod testing {
         let config = build_config();
         assert_eq!(*config.prefix(), "results");
     }
+
+    #[test]
+    fn test_with_prefix() {
+        let config = build_config();
+        let prefix = format!("{}.contrast", config.prefix());
+        let contrast_config = config.with_prefix(&prefix);
+        assert_eq!(*contrast_config.prefix(), "results.contrast");
+        assert_eq!(*contrast_config.model_choice(), ModelChoice::Wols);
+    }
 }
@@ -6,7 +6,12 @@ use ndarray::Array1;
 use std::fmt::Debug;
 
 use crate::{
-    aggregation::GeneAggregation, enrich::TestStrategy, model::ModelChoice, norm::Normalization,
+    aggregation::{Combination, Consensus, GeneAggregation, ScreenPerformance, StoufferWeights},
+    enrich::{GlmTest, NtcCalibration, TestStrategy},
+    io::ValidationReport,
+    model::ModelChoice,
+    norm::Normalization,
+    sample_structure::SampleOutlier,
 };
 
 #[derive(Default)]
@@ -41,12 +46,164 @@ impl Logger {
         }
     }
 
+    pub fn start_contrasts(&self) {
+        if self.verbose {
+            eprintln!("\n{}", "Contrasts".bold().underline());
+        }
+    }
+
+    pub fn contrast_groups(&self, name: &str, controls: &[String], treatments: &[String]) {
+        if self.verbose {
+            Self::write_to_stderr(
+                "Contrast                   : ",
+                format!(
+                    "{name} ({} vs {})",
+                    controls.join(","),
+                    treatments.join(",")
+                ),
+            );
+        }
+    }
+
+    pub fn num_contrasts(&self, n_contrasts: usize) {
+        if self.verbose {
+            Self::write_to_stderr("Number of Contrasts        : ", n_contrasts);
+        }
+    }
+
     pub fn start_resampling(&self) {
         if self.verbose {
             eprintln!("\n{}", "Resampling Configuration".bold().underline());
         }
     }
 
+    pub fn validation_report(&self, report: &ValidationReport) {
+        if self.verbose && !report.issues().is_empty() {
+            eprintln!("\n{}", "Input Validation".bold().underline());
+            Self::write_to_stderr("Number of Errors           : ", report.n_errors());
+            Self::write_to_stderr("Number of Warnings         : ", report.n_warnings());
+            for (check, n) in report.summary() {
+                Self::write_to_stderr(
+                    &format!("{:<27}: ", check.name()),
+                    format_args!("{n} ({})", check.severity().name()),
+                );
+            }
+        }
+    }
+
+    pub fn screen_performance(&self, performance: &ScreenPerformance) {
+        if self.verbose {
+            eprintln!("\n{}", "Screen Performance".bold().underline());
+            Self::write_to_stderr("AUROC (log2fc)             : ", performance.auroc_log2fc());
+            Self::write_to_stderr("AUPRC (log2fc)             : ", performance.auprc_log2fc());
+            Self::write_to_stderr("NNMD                       : ", performance.nnmd());
+            Self::write_to_stderr(
+                "Essential Recall           : ",
+                performance.essential_recall(),
+            );
+        }
+    }
+
+    pub fn consensus(&self, consensus: &Consensus) {
+        if self.verbose {
+            eprintln!("\n{}", "Consensus Aggregation".bold().underline());
+            Self::write_to_stderr("Methods                    : ", consensus.methods());
+            Self::write_to_stderr("Number of Shared Genes     : ", consensus.genes().len());
+            Self::write_to_stderr("Hits per Method            : ", consensus.method_hits());
+            Self::write_to_stderr("Hits in All Methods        : ", consensus.unanimous_hits());
+        }
+    }
+
+    pub fn sample_structure(&self, explained_variance: &Array1<f64>) {
+        if self.verbose {
+            eprintln!("\n{}", "Sample Structure".bold().underline());
+            Self::write_to_stderr("Number of Components       : ", explained_variance.len());
+            Self::write_to_stderr(
+                "Explained Variance (PC1-2) : ",
+                explained_variance.iter().take(2).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    pub fn skip_circular_performance(&self, method: &str) {
+        if self.verbose {
+            eprintln!(
+                "\n{}: {}",
+                "Warning".bold().yellow(),
+                format!(
+                    "Skipping the screen performance: {method} is trained on the same reference sets it would be evaluated on"
+                )
+                .bold()
+            );
+        }
+    }
+
+    pub fn sample_outliers(&self, outliers: &[SampleOutlier]) {
+        if self.verbose {
+            for outlier in outliers {
+                eprintln!(
+                    "\n{}: {}",
+                    "Warning".bold().yellow(),
+                    format!(
+                        "Sample {} ({}) is closer to {} (mean distance: {:.3}) than to its own group (mean distance: {:.3})",
+                        outlier.sample(),
+                        outlier.group(),
+                        outlier.nearest_group(),
+                        outlier.nearest_distance(),
+                        outlier.group_distance(),
+                    )
+                    .bold()
+                );
+            }
+        }
+    }
+
+    pub fn start_qc(&self) {
+        if self.verbose {
+            eprintln!("\n{}", "Library Quality Control".bold().underline());
+        }
+    }
+
+    pub fn qc_metrics(&self, gini_indices: &[f64], skew_ratios: &[f64], min_pearson: Option<f64>) {
+        if self.verbose {
+            Self::write_to_stderr("Gini Indices               : ", gini_indices);
+            Self::write_to_stderr("Skew Ratios                : ", skew_ratios);
+            if let Some(min_pearson) = min_pearson {
+                Self::write_to_stderr("Min Replicate Pearson      : ", min_pearson);
+            }
+        }
+    }
+
+    pub fn start_counting(&self) {
+        if self.verbose {
+            eprintln!("\n{}", "Counting Configuration".bold().underline());
+        }
+    }
+
+    pub fn library_summary(&self, n_sgrnas: usize, length: usize, mismatches: u8) {
+        if self.verbose {
+            Self::write_to_stderr("Number of sgRNAs           : ", n_sgrnas);
+            Self::write_to_stderr("Protospacer Length         : ", length);
+            Self::write_to_stderr("Allowed Mismatches         : ", mismatches);
+        }
+    }
+
+    pub fn sample_mapping(&self, name: &str, offset: usize, mapped: usize, total: usize) {
+        if self.verbose {
+            let rate = if total == 0 {
+                0.
+            } else {
+                100. * mapped as f64 / total as f64
+            };
+            Self::write_to_stderr("Sample                     : ", format_args!("{name}"));
+            Self::write_to_stderr("Protospacer Offset         : ", offset);
+            Self::write_to_stderr(
+                "Mapped Reads               : ",
+                format_args!("{mapped} / {total} ({rate:.2}%)"),
+            );
+        }
+    }
+
     pub fn num_sgrnas<T>(&self, x: &[T]) {
         if self.verbose {
             Self::write_to_stderr("Number of sgRNAs           : ", x.len());
@@ -60,12 +217,38 @@ impl Logger {
         }
     }
 
+    pub fn interaction_groups(
+        &self,
+        controls: &[String],
+        reference: &[String],
+        query: &[String],
+        query_controls: Option<&[String]>,
+    ) {
+        if self.verbose {
+            Self::write_to_stderr("Control Group              : ", controls);
+            Self::write_to_stderr("Reference Group            : ", reference);
+            Self::write_to_stderr("Query Group                : ", query);
+            if let Some(query_controls) = query_controls {
+                Self::write_to_stderr("Query Control Group        : ", query_controls);
+            }
+        }
+    }
+
     pub fn sampled_names(&self, samples: &[String]) {
         if self.verbose {
             Self::write_to_stderr("Sampled Group              : ", samples);
         }
     }
 
+    pub fn mageck_input(&self) {
+        if self.verbose {
+            Self::write_to_stderr(
+                "Input Format               : ",
+                format_args!("MAGeCK sgRNA summary"),
+            );
+        }
+    }
+
     pub fn num_genes(&self, x: &[String]) {
         if self.verbose {
             let unique_genes = x.iter().cloned().collect::<HashSet<String>>();
@@ -79,6 +262,24 @@ impl Logger {
         }
     }
 
+    pub fn num_control_sgrnas(&self, n_controls: usize) {
+        if self.verbose {
+            Self::write_to_stderr("Number of Control sgRNAs   : ", n_controls);
+        }
+    }
+
+    pub fn zero_fractions(&self, zero_fractions: &Array1<f64>) {
+        if self.verbose {
+            Self::write_to_stderr("Sample Zero Fractions      : ", zero_fractions.to_vec());
+        }
+    }
+
+    pub fn size_factors(&self, size_factors: &Array1<f64>) {
+        if self.verbose {
+            Self::write_to_stderr("Size Factors               : ", size_factors.to_vec());
+        }
+    }
+
     pub fn aggregation_method(&self, g: &GeneAggregation) {
         if self.verbose {
             Self::write_to_stderr("Aggregation Method         : ", g);
@@ -151,6 +352,51 @@ impl Logger {
         }
     }
 
+    pub fn sample_pairs(&self, pairs: &[(String, String)]) {
+        if self.verbose {
+            let pairs = pairs
+                .iter()
+                .map(|(c, t)| format!("{c} -> {t}"))
+                .collect::<Vec<_>>();
+            Self::write_to_stderr("Sample Pairs               : ", pairs);
+        }
+    }
+
+    pub fn num_sample_pairs(&self, n_pairs: usize) {
+        if self.verbose {
+            Self::write_to_stderr("Number of Sample Pairs     : ", n_pairs);
+        }
+    }
+
+    pub fn glm_parameters(&self, test: GlmTest, coefficients: &[String]) {
+        if self.verbose {
+            Self::write_to_stderr("GLM Coefficients           : ", coefficients);
+            Self::write_to_stderr("GLM Coefficient Test       : ", test);
+        }
+    }
+
+    pub fn timepoints(&self, times: &[f64]) {
+        if self.verbose {
+            let mut unique = times.to_vec();
+            unique.dedup();
+            Self::write_to_stderr("Timepoints                 : ", unique);
+        }
+    }
+
+    pub fn ntc_calibration(&self, calibration: NtcCalibration, n_ntc: usize) {
+        if self.verbose {
+            Self::write_to_stderr("NTC Calibration            : ", calibration);
+            Self::write_to_stderr("Number of NTC sgRNAs       : ", n_ntc);
+        }
+    }
+
+    pub fn ntc_null_parameters(&self, location: f64, scale: f64) {
+        if self.verbose {
+            Self::write_to_stderr("NTC Null Location          : ", location);
+            Self::write_to_stderr("NTC Null Scale             : ", scale);
+        }
+    }
+
     pub fn sample_weights(&self, survival: bool, weights: &Array1<f64>) {
         if self.verbose {
             if survival {
@@ -219,6 +465,55 @@ impl Logger {
         }
     }
 
+    pub fn report_bagel_params(
+        &self,
+        n_essential: usize,
+        n_nonessential: usize,
+        n_bootstraps: usize,
+        fdr: f64,
+        seed: usize,
+    ) {
+        if self.verbose {
+            Self::write_to_stderr("Essential Training Genes   : ", n_essential);
+            Self::write_to_stderr("Nonessential Training Genes: ", n_nonessential);
+            Self::write_to_stderr("Bootstrap Iterations       : ", n_bootstraps);
+            Self::write_to_stderr("FDR                        : ", fdr);
+            Self::write_to_stderr("Seed                       : ", seed);
+        }
+    }
+
+    pub fn report_drugz_params(&self, half_window: usize, n_replicates: usize, fdr: f64) {
+        if self.verbose {
+            Self::write_to_stderr("Half Window Size           : ", half_window);
+            Self::write_to_stderr("Number of Replicates       : ", n_replicates);
+            Self::write_to_stderr("FDR                        : ", fdr);
+        }
+    }
+
+    pub fn report_second_best_params(&self, token: &str, n_draws: usize, fdr: f64, seed: usize) {
+        if self.verbose {
+            Self::write_to_stderr("Non-Targeting Token        : ", token);
+            Self::write_to_stderr("Number of Null Draws       : ", n_draws);
+            Self::write_to_stderr("FDR                        : ", fdr);
+            Self::write_to_stderr("Seed                       : ", seed);
+        }
+    }
+
+    pub fn report_combination_params(
+        &self,
+        method: Combination,
+        weights: Option<StoufferWeights>,
+        fdr: f64,
+    ) {
+        if self.verbose {
+            Self::write_to_stderr("Combination Method         : ", method);
+            if let Some(weights) = weights {
+                Self::write_to_stderr("Stouffer Weights           : ", weights);
+            }
+            Self::write_to_stderr("FDR                        : ", fdr);
+        }
+    }
+
     pub fn report_inc_low_threshold(&self, threshold: f64, use_product: bool) {
         if self.verbose {
             if use_product {
@@ -245,13 +540,18 @@ impl Logger {
         }
     }
 
-    pub fn convert_normalization(&self) {
+    pub fn convert_normalization(&self, from: &Normalization, to: &Normalization, reason: &str) {
         if self.verbose {
             eprintln!(
                 "\n{}: {}",
                 "Warning".bold().yellow(),
-                "Numeric instability found in median-ratio normalization. Performing total normalization instead.".bold()
-                );
+                format!(
+                    "Unable to perform {} normalization ({reason}). Performing {} normalization instead.",
+                    from.name(),
+                    to.name()
+                )
+                .bold()
+            );
         }
     }
 
@@ -291,15 +591,89 @@ impl Logger {
 mod testing {
 
     use super::Logger;
-    use crate::aggregation::GeneAggregation;
+    use crate::aggregation::{
+        screen_performance, AggregationResult, Combination, Consensus, GeneAggregation,
+        ReferenceSets, ScreenPerformance, StoufferWeights,
+    };
+    use crate::enrich::{GlmTest, NtcCalibration};
+    use crate::io::{validate_counts, ValidationReport};
     use crate::model::ModelChoice;
     use crate::norm::Normalization;
+    use crate::sample_structure::SampleStructure;
     use adjustp::Procedure;
+    use ndarray::array;
+    use polars::prelude::*;
+
+    fn build_results() -> AggregationResult {
+        AggregationResult::builder()
+            .genes(["e1", "n1", "n2"].map(|x| x.to_string()).to_vec())
+            .gene_fc(array![0.5, 1., 1.1])
+            .pvalues_low(array![0.01, 0.5, 0.6])
+            .pvalues_high(array![0.99, 0.5, 0.4])
+            .fdr_low(array![0.02, 0.6, 0.6])
+            .fdr_high(array![1., 0.6, 0.6])
+            .aggregation_score_low(array![0.01, 0.5, 0.6])
+            .aggregation_score_high(array![0.99, 0.5, 0.4])
+            .build()
+    }
+
+    fn build_performance() -> ScreenPerformance {
+        let results = build_results();
+        let genes = results.genes();
+        let sets = ReferenceSets::new(genes[..1].to_vec(), genes[1..].to_vec()).unwrap();
+        screen_performance(&results, &sets, 0.1).unwrap()
+    }
+
+    fn build_consensus() -> Consensus {
+        let results = build_results();
+        let fisher = GeneAggregation::Fisher { fdr: 0.1 };
+        let acat = GeneAggregation::Acat { fdr: 0.1 };
+        Consensus::new(
+            &[(&fisher, &results), (&acat, &results)],
+            Procedure::BenjaminiHochberg,
+        )
+        .unwrap()
+    }
+
+    fn build_structure() -> SampleStructure {
+        let normed = array![[100., 110., 400.], [100., 105., 10.], [50., 55., 52.]];
+        let labels = ["c_1", "c_2", "t_1"].map(|x| x.to_string()).to_vec();
+        let groups = ["control", "treatment", "control"]
+            .map(|x| x.to_string())
+            .to_vec();
+        SampleStructure::new(&normed, &labels, &groups)
+    }
+
+    fn build_report() -> ValidationReport {
+        let frame = df!("sgrna" => &["a"], "gene" => &["a"], "low" => &[0]).unwrap();
+        let names = vec!["a".to_string()];
+        validate_counts(&frame, &["low".to_string()], &names, &names, Some("ntc")).unwrap()
+    }
 
     #[test]
     fn test_logger() {
         let logger = Logger::new();
         logger.start_mageck();
+        logger.screen_performance(&build_performance());
+        logger.skip_circular_performance("bagel");
+        logger.consensus(&build_consensus());
+        logger.report_bagel_params(3, 4, 100, 0.1, 42);
+        logger.report_drugz_params(500, 3, 0.1);
+        logger.report_second_best_params("non-targeting", 10000, 0.1, 42);
+        logger.report_combination_params(
+            Combination::Stouffer,
+            Some(StoufferWeights::BaseMean),
+            0.1,
+        );
+        let structure = build_structure();
+        logger.sample_structure(structure.explained_variance());
+        logger.sample_outliers(&structure.outliers());
+        logger.start_qc();
+        logger.qc_metrics(&[0.1, 0.2], &[3.5, 4.0], Some(0.95));
+        logger.validation_report(&build_report());
+        logger.start_counting();
+        logger.library_summary(2, 20, 1);
+        logger.sample_mapping("s1", 3, 90, 100);
         logger.num_sgrnas(&["a".to_string(), "b".to_string()]);
         logger.num_genes(&["a".to_string(), "b".to_string()]);
         logger.norm_method(&Normalization::MedianRatio);
@@ -318,6 +692,25 @@ mod testing {
         logger.num_zeros(1);
         logger.num_varied(1);
         logger.ols_parameters(&ModelChoice::Ols, 1.0, 1.0);
+        logger.sample_pairs(&[("a".to_string(), "b".to_string())]);
+        logger.num_sample_pairs(1);
+        logger.glm_parameters(GlmTest::Wald, &["treatment".to_string()]);
+        logger.timepoints(&[0., 0., 7., 14.]);
+        logger.interaction_groups(
+            &["a".to_string()],
+            &["b".to_string()],
+            &["c".to_string()],
+            Some(&["d".to_string()]),
+        );
+        logger.ntc_calibration(NtcCalibration::Empirical, 10);
+        logger.ntc_null_parameters(0.1, 1.2);
+        logger.num_control_sgrnas(10);
+        logger.size_factors(&array![0.5, 2.0]);
+        logger.zero_fractions(&array![0.1, 0.2]);
+        logger.mageck_input();
+        logger.start_contrasts();
+        logger.contrast_groups("a_vs_b", &["a".to_string()], &["b".to_string()]);
+        logger.num_contrasts(1);
         logger.start_gene_aggregation();
         logger.report_rra_params(1.0, 1.0, 42);
         logger.permutation_sizes(&[1, 2, 3]);
@@ -326,13 +719,37 @@ mod testing {
         logger.report_inc_high_threshold(1.0, false);
         logger.report_inc_low_threshold(1.0, true);
         logger.report_inc_high_threshold(1.0, true);
-        logger.convert_normalization();
+        logger.convert_normalization(
+            &Normalization::MedianRatio,
+            &Normalization::Poscounts,
+            "median-ratio is unstable",
+        );
     }
 
     #[test]
     fn test_logger_quiet() {
         let logger = Logger::new_silent();
         logger.start_mageck();
+        logger.screen_performance(&build_performance());
+        logger.skip_circular_performance("bagel");
+        logger.consensus(&build_consensus());
+        logger.report_bagel_params(3, 4, 100, 0.1, 42);
+        logger.report_drugz_params(500, 3, 0.1);
+        logger.report_second_best_params("non-targeting", 10000, 0.1, 42);
+        logger.report_combination_params(
+            Combination::Stouffer,
+            Some(StoufferWeights::BaseMean),
+            0.1,
+        );
+        let structure = build_structure();
+        logger.sample_structure(structure.explained_variance());
+        logger.sample_outliers(&structure.outliers());
+        logger.start_qc();
+        logger.qc_metrics(&[0.1, 0.2], &[3.5, 4.0], Some(0.95));
+        logger.validation_report(&build_report());
+        logger.start_counting();
+        logger.library_summary(2, 20, 1);
+        logger.sample_mapping("s1", 3, 90, 100);
         logger.num_sgrnas(&["a".to_string(), "b".to_string()]);
         logger.num_genes(&["a".to_string(), "b".to_string()]);
         logger.norm_method(&Normalization::MedianRatio);
@@ -351,6 +768,25 @@ mod testing {
         logger.num_zeros(1);
         logger.num_varied(1);
         logger.ols_parameters(&ModelChoice::Ols, 1.0, 1.0);
+        logger.sample_pairs(&[("a".to_string(), "b".to_string())]);
+        logger.num_sample_pairs(1);
+        logger.glm_parameters(GlmTest::Wald, &["treatment".to_string()]);
+        logger.timepoints(&[0., 0., 7., 14.]);
+        logger.interaction_groups(
+            &["a".to_string()],
+            &["b".to_string()],
+            &["c".to_string()],
+            Some(&["d".to_string()]),
+        );
+        logger.ntc_calibration(NtcCalibration::Empirical, 10);
+        logger.ntc_null_parameters(0.1, 1.2);
+        logger.num_control_sgrnas(10);
+        logger.size_factors(&array![0.5, 2.0]);
+        logger.zero_fractions(&array![0.1, 0.2]);
+        logger.mageck_input();
+        logger.start_contrasts();
+        logger.contrast_groups("a_vs_b", &["a".to_string()], &["b".to_string()]);
+        logger.num_contrasts(1);
         logger.start_gene_aggregation();
         logger.report_rra_params(1.0, 1.0, 42);
         logger.permutation_sizes(&[1, 2, 3]);
@@ -359,6 +795,10 @@ mod testing {
         logger.report_inc_high_threshold(1.0, false);
         logger.report_inc_low_threshold(1.0, true);
         logger.report_inc_high_threshold(1.0, true);
-        logger.convert_normalization();
+        logger.convert_normalization(
+            &Normalization::MedianRatio,
+            &Normalization::Poscounts,
+            "median-ratio is unstable",
+        );
     }
 }
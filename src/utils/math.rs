@@ -19,6 +19,85 @@ pub fn normalize(array: &Array1<f64>) -> Array1<f64> {
     array.mapv(|x| x / sum)
 }
 
+/// Calculates the Pearson correlation coefficient of two equal-length slices
+///
+/// Returns NaN if either slice has no variance.
+pub fn pearson_correlation(x: &[f64], y: &[f64]) -> f64 {
+    assert_eq!(x.len(), y.len());
+    let n = x.len() as f64;
+    let x_mean = x.iter().sum::<f64>() / n;
+    let y_mean = y.iter().sum::<f64>() / n;
+    let (sxy, sxx, syy) = x.iter().zip(y.iter()).fold((0., 0., 0.), |acc, (a, b)| {
+        let (da, db) = (a - x_mean, b - y_mean);
+        (acc.0 + da * db, acc.1 + da * da, acc.2 + db * db)
+    });
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Calculates the eigenvalues and eigenvectors of a symmetric matrix with the cyclic Jacobi
+/// method
+///
+/// Eigenvalues are returned in descending order with the eigenvectors as the corresponding
+/// columns. Each eigenvector is signed so that its largest absolute component is positive.
+pub fn symmetric_eigen(matrix: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    const MAX_SWEEPS: usize = 100;
+    let n = matrix.nrows();
+    assert_eq!(n, matrix.ncols());
+    let mut a = matrix.to_owned();
+    let mut v = Array2::<f64>::eye(n);
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal = (0..n)
+            .flat_map(|p| ((p + 1)..n).map(move |q| (p, q)))
+            .map(|(p, q)| a[[p, q]].powi(2))
+            .sum::<f64>();
+        if off_diagonal < 1e-22 * a.iter().map(|x| x * x).sum::<f64>().max(f64::MIN_POSITIVE) {
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[[p, q]] == 0. {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2. * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by(|i, j| a[[*j, *j]].total_cmp(&a[[*i, *i]]));
+    let values = order.iter().map(|i| a[[*i, *i]]).collect::<Array1<f64>>();
+    let mut vectors = v.select(Axis(1), &order);
+    for mut column in vectors.axis_iter_mut(Axis(1)) {
+        let largest = column
+            .iter()
+            .copied()
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or_default();
+        if largest < 0. {
+            column.mapv_inplace(|x| -x);
+        }
+    }
+    (values, vectors)
+}
+
 /// Takes the sum of all negative log values along an axis
 pub fn negative_log_sum(m: &Array2<f64>, axis: Axis) -> Array1<f64> {
     m.map_axis(axis, |row| row.iter().map(|x| -x.ln()).sum())
@@ -83,6 +162,30 @@ mod testing {
         assert!(z.std(0.) - 1. < 1e-6);
     }
 
+    #[test]
+    fn test_pearson_correlation() {
+        let x = [1., 2., 3., 4.];
+        assert!((pearson_correlation(&x, &[2., 4., 6., 8.]) - 1.).abs() < 1e-12);
+        assert!((pearson_correlation(&x, &[4., 3., 2., 1.]) + 1.).abs() < 1e-12);
+        assert!(pearson_correlation(&x, &[1., 1., 1., 1.]).is_nan());
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let matrix = array![[2., 1., 0.], [1., 2., 0.], [0., 0., 5.]];
+        let (values, vectors) = symmetric_eigen(&matrix);
+        let expected = [5., 3., 1.];
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-10);
+        }
+        for (idx, value) in values.iter().enumerate() {
+            let vector = vectors.column(idx);
+            let residual = matrix.dot(&vector) - vector.mapv(|x| x * value);
+            assert!(residual.iter().all(|x| x.abs() < 1e-10));
+        }
+        assert!((vectors[[2, 0]] - 1.).abs() < 1e-10);
+    }
+
     #[test]
     fn test_geometric_mean_weighted() {
         let x = array![[18., 1327., 1024., 1001., 1116.]];